@@ -1,3 +1,4 @@
+use crate::span::Spanned;
 use crate::token::Token;
 use std::fmt::Display;
 
@@ -13,37 +14,41 @@ impl Display for Ident {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Stmt {
     Blank,
-    Var(Ident, ExprType), // var x = 1;
-    Expr(ExprType),
-    Block(Vec<Stmt>),
-    Return(ExprType),
+    Var(Ident, ExprNode), // var x = 1;
+    Expr(ExprNode),
+    Block(Vec<StmtNode>),
+    Return(ExprNode),
     Function(Ident, Vec<Ident>, BlockStmt),
-    Switch(ExprType, Vec<Stmt>),
-    Case(ExprType, BlockStmt),
+    Switch(ExprNode, Vec<StmtNode>),
+    Case(ExprNode, BlockStmt),
     Default(BlockStmt),
-    While(ExprType, BlockStmt),
+    While(ExprNode, BlockStmt),
     Import(String),
-    Assign(ExprType, ExprType),
+    Assign(ExprNode, ExprNode),
     ClassStmt {
         name: Ident,
-        properties: Vec<Stmt>,
+        superclass: Option<Ident>, // class B < A
+        properties: Vec<StmtNode>,
     },
     For {
-        init: Box<Stmt>,
-        conditions: Box<ExprType>,
-        step: Box<Stmt>,
+        init: Box<StmtNode>,
+        conditions: Box<ExprNode>,
+        step: Box<StmtNode>,
         block: BlockStmt,
     },
     ForIn {
-        var: Box<Stmt>,
-        iter: Box<ExprType>,
+        var: Box<StmtNode>,
+        iter: Box<ExprNode>,
         block: BlockStmt,
     },
-    ClassInit(Ident, Vec<ExprType>),
+    ClassInit(Ident, Vec<ExprNode>),
     Assert {
-        condition: Box<ExprType>,
-        message: Box<ExprType>,
+        condition: Box<ExprNode>,
+        message: Box<ExprNode>,
     },
+    Break(Option<Ident>),    // break; or break outer;
+    Continue(Option<Ident>), // continue; or continue outer;
+    Labeled(Ident, Box<StmtNode>), // outer: while (...) { ... }
 }
 
 impl Display for Stmt {
@@ -69,42 +74,49 @@ impl Display for Stmt {
                     }
                     write!(f, "{}", param)?;
                 }
-                write!(f, ") {{\n")?;
+                writeln!(f, ") {{")?;
                 for stmt in body {
                     writeln!(f, "\t{}", stmt)?;
                 }
                 write!(f, "}}")
             }
             Stmt::Switch(e, cases) => {
-                write!(f, "switch {} {{\n", e)?;
+                writeln!(f, "switch {} {{", e)?;
                 for stmt in cases {
                     writeln!(f, "\t{}", stmt)?;
                 }
                 write!(f, "}}")
             }
             Stmt::Case(e, block) => {
-                write!(f, "case {}: \n", e)?;
+                writeln!(f, "case {}: ", e)?;
                 for stmt in block {
                     writeln!(f, "\t{}", stmt)?;
                 }
                 Ok(())
             }
             Stmt::Default(block) => {
-                write!(f, "default:\n")?;
+                writeln!(f, "default:")?;
                 for stmt in block {
                     writeln!(f, "\t{}", stmt)?;
                 }
                 Ok(())
             }
             Stmt::While(expr, block) => {
-                write!(f, "while ({}) {{\n", expr)?;
+                writeln!(f, "while ({}) {{", expr)?;
                 for stmt in block {
                     writeln!(f, "\t{}", stmt)?;
                 }
                 write!(f, "}}")
             }
-            Stmt::ClassStmt { name, properties } => {
-                write!(f, "class {} {{\n", name)?;
+            Stmt::ClassStmt {
+                name,
+                superclass,
+                properties,
+            } => {
+                match superclass {
+                    Some(superclass) => writeln!(f, "class {} < {} {{", name, superclass)?,
+                    None => writeln!(f, "class {} {{", name)?,
+                }
                 for stmt in properties {
                     writeln!(f, "\t{}", stmt)?;
                 }
@@ -116,7 +128,7 @@ impl Display for Stmt {
                 step,
                 block,
             } => {
-                write!(f, "for ({}; {}; {}) {{\n", init, conditions, step)?;
+                writeln!(f, "for ({}; {}; {}) {{", init, conditions, step)?;
                 for stmt in block {
                     writeln!(f, "\t{}", stmt)?;
                 }
@@ -133,7 +145,7 @@ impl Display for Stmt {
                 write!(f, ")")
             }
             Stmt::ForIn { var, iter, block } => {
-                write!(f, "for ({} in {}) {{\n", var, iter)?;
+                writeln!(f, "for ({} in {}) {{", var, iter)?;
                 for stmt in block {
                     writeln!(f, "\t{}", stmt)?;
                 }
@@ -142,39 +154,49 @@ impl Display for Stmt {
             Stmt::Assert { condition, message } => {
                 write!(f, "assert {} {}", condition, message)
             }
+            Stmt::Break(None) => write!(f, "break"),
+            Stmt::Break(Some(label)) => write!(f, "break {}", label),
+            Stmt::Continue(None) => write!(f, "continue"),
+            Stmt::Continue(Some(label)) => write!(f, "continue {}", label),
+            Stmt::Labeled(label, stmt) => write!(f, "{}: {}", label, stmt),
         }
     }
 }
 
-pub type BlockStmt = Vec<Stmt>;
+pub type StmtNode = Spanned<Stmt>;
+
+pub type ExprNode = Spanned<ExprType>;
+
+pub type BlockStmt = Vec<StmtNode>;
 
 pub type Program = BlockStmt;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
     Number(f64),
+    Int(i64),
     String(String),
     Bool(bool),
-    Index(usize),
-    Array(Vec<ExprType>),
-    Hash(Vec<(ExprType, ExprType)>),
+    Array(Vec<ExprNode>),
+    Hash(Vec<(ExprNode, ExprNode)>),
     Nil,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ExprType {
     Ident(Ident),
-    ThisExpr(Ident),
+    This,
+    Super(Ident), // super.method
     Literal(Literal),
-    GroupingExpr(Box<ExprType>),
-    UnaryExpr(Token, Box<ExprType>), // prefix unary parse
-    PrefixExpr(Token, Box<ExprType>),
-    InfixExpr(Box<ExprType>, Token, Box<ExprType>),
-    PrintExpr(Box<Vec<ExprType>>),
-    IndexExpr(Box<ExprType>, Box<ExprType>),
+    GroupingExpr(Box<ExprNode>),
+    UnaryExpr(Token, Box<ExprNode>), // prefix unary parse
+    PrefixExpr(Token, Box<ExprNode>),
+    InfixExpr(Box<ExprNode>, Token, Box<ExprNode>),
+    PrintExpr(Box<Vec<ExprNode>>),
+    IndexExpr(Box<ExprNode>, Box<ExprNode>),
     If {
-        condition: Box<ExprType>,
-        elseif: Vec<(Box<ExprType>, BlockStmt)>,
+        condition: Box<ExprNode>,
+        elseif: Vec<(Box<ExprNode>, BlockStmt)>,
         then_branch: BlockStmt,
         else_branch: BlockStmt,
     },
@@ -183,25 +205,23 @@ pub enum ExprType {
         body: BlockStmt,
     },
     Call {
-        callee: Box<ExprType>,
-        args: Vec<ExprType>,
+        callee: Box<ExprNode>,
+        args: Vec<ExprNode>,
     },
     ClassInit {
         name: Ident,
-        args: Vec<ExprType>,
-    },
-    ClassCall {
-        callee: Ident,
-        method: Ident,
-        args: Vec<ExprType>,
+        args: Vec<ExprNode>,
     },
-    ClassGet {
-        callee: Ident,
-        prop: Ident,
+    // object.name, a field or a method of any expression
+    Get {
+        object: Box<ExprNode>,
+        name: Ident,
     },
-    ThisCall {
-        method: Ident,
-        args: Vec<ExprType>,
+    // object.name = value
+    Set {
+        object: Box<ExprNode>,
+        name: Ident,
+        value: Box<ExprNode>,
     },
 }
 
@@ -226,7 +246,7 @@ impl Display for ExprType {
                         write!(f, "{}.0", inum)
                     }
                 }
-                Literal::Index(i) => write!(f, "{}", i),
+                Literal::Int(i) => write!(f, "{}.0", i),
                 Literal::String(s) => write!(f, "{}", s),
                 Literal::Bool(b) => write!(f, "{}", b),
                 Literal::Nil => write!(f, "nil"),
@@ -254,16 +274,6 @@ impl Display for ExprType {
             ExprType::GroupingExpr(expr) => {
                 write!(f, "(group {})", expr)
             }
-            ExprType::ThisCall { method, args } => {
-                write!(f, "this.{}(", method)?;
-                for (i, arg) in args.iter().enumerate() {
-                    if i > 0 {
-                        write!(f, ", ")?;
-                    }
-                    write!(f, "{}", arg)?;
-                }
-                write!(f, ")")
-            }
             ExprType::PrefixExpr(token, expr) => {
                 let op = match token {
                     Token::Minus => "-",
@@ -288,6 +298,8 @@ impl Display for ExprType {
                     Token::And => "&&",
                     Token::Or => "||",
                     Token::Mod => "%",
+                    Token::BitAnd => "&",
+                    Token::BitOr => "|",
                     Token::ModSelf => "%=",
                     Token::PlusSelf => "+=",
                     Token::MinusSelf => "-=",
@@ -311,20 +323,20 @@ impl Display for ExprType {
                 then_branch,
                 else_branch,
             } => {
-                write!(f, "if {} {{\n", condition)?;
+                writeln!(f, "if {} {{", condition)?;
                 for stmt in then_branch {
                     writeln!(f, "\t{}", stmt)?;
                 }
                 write!(f, "}}")?;
                 for (cond, block) in elseif {
-                    write!(f, " else if {} {{\n", cond)?;
+                    writeln!(f, " else if {} {{", cond)?;
                     for stmt in block {
                         writeln!(f, "\t{}", stmt)?;
                     }
                     write!(f, "}}")?;
                 }
                 if !else_branch.is_empty() {
-                    write!(f, " else {{\n")?;
+                    writeln!(f, " else {{")?;
                     for stmt in else_branch {
                         writeln!(f, "\t{}", stmt)?;
                     }
@@ -340,11 +352,11 @@ impl Display for ExprType {
                     }
                     write!(f, "{}", param)?;
                 }
-                write!(f, ") {{\n")?;
+                writeln!(f, ") {{")?;
                 for stmt in body {
                     writeln!(f, "\t{}", stmt)?;
                 }
-                write!(f, "\n}}\n")
+                writeln!(f, "\n}}")
             }
             ExprType::Call { callee, args } => {
                 write!(f, "{}(", callee)?;
@@ -356,7 +368,8 @@ impl Display for ExprType {
                 }
                 write!(f, ")")
             }
-            ExprType::ThisExpr(ident) => write!(f, "this.{}", ident.0),
+            ExprType::This => write!(f, "this"),
+            ExprType::Super(method) => write!(f, "super.{}", method),
             ExprType::ClassInit { name, args } => {
                 write!(f, "new {}(", name)?;
                 for (i, arg) in args.iter().enumerate() {
@@ -367,21 +380,12 @@ impl Display for ExprType {
                 }
                 write!(f, ")")
             }
-            ExprType::ClassCall {
-                callee,
-                method,
-                args,
-            } => {
-                write!(f, "{}.{}(", callee, method)?;
-                for (i, arg) in args.iter().enumerate() {
-                    if i > 0 {
-                        write!(f, ", ")?;
-                    }
-                    write!(f, "{}", arg)?;
-                }
-                write!(f, ")")
-            }
-            ExprType::ClassGet { callee, prop } => write!(f, "{}.{}", callee, prop),
+            ExprType::Get { object, name } => write!(f, "{}.{}", object, name),
+            ExprType::Set {
+                object,
+                name,
+                value,
+            } => write!(f, "{}.{} = {}", object, name, value),
             ExprType::IndexExpr(left, right) => {
                 write!(f, "{}[{}]", left, right)
             }
@@ -393,6 +397,8 @@ impl Display for ExprType {
 pub enum Precedence {
     Lowest,
     And,         // &&
+    BitOr,       // |
+    BitAnd,      // &
     Equals,      // ==
     LessGreater, // > or <
     OpSelfSum,   // += -=
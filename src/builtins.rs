@@ -1,6 +1,8 @@
 use std::collections::HashMap;
+use std::{cell::RefCell, rc::Rc};
 
 use crate::objects::Object;
+use crate::output;
 
 pub struct Builtins {
     pub builtins: HashMap<String, Object>,
@@ -8,6 +10,12 @@ pub struct Builtins {
     indexs: Vec<String>,
 }
 
+impl Default for Builtins {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Builtins {
     pub fn new() -> Self {
         let builtins = new_builtins();
@@ -20,9 +28,9 @@ impl Builtins {
             indexs.push(k.to_string());
         }
         Builtins {
-            builtins: builtins,
-            sorted: sorted,
-            indexs: indexs,
+            builtins,
+            sorted,
+            indexs,
         }
     }
 
@@ -70,6 +78,8 @@ pub fn new_builtins() -> HashMap<String, Object> {
     builtin_insert!(builtins, "substr", 3, substr);
     builtin_insert!(builtins, "typeis", 1, typeis);
     builtin_insert!(builtins, "append", -1, append);
+    builtin_insert!(builtins, "push", -1, push);
+    builtin_insert!(builtins, "pop", 1, pop);
     builtin_insert!(builtins, "intval", 1, intval);
     builtin_insert!(builtins, "is_str", 1, is_str);
     builtin_insert!(builtins, "is_number", 1, is_number);
@@ -81,7 +91,7 @@ pub fn new_builtins() -> HashMap<String, Object> {
 
 fn x_print(args: Vec<Object>) -> Object {
     for arg in args {
-        print!("{}", arg);
+        output::print(format_args!("{}", arg));
     }
     Object::Nil
 }
@@ -91,9 +101,9 @@ fn len(args: Vec<Object>) -> Object {
         return Object::Nil;
     }
     match &args[0] {
-        Object::String(s) => Object::Number(s.len() as f64),
-        Object::Array(a) => Object::Number(a.len() as f64),
-        Object::Hash(h) => Object::Number(h.borrow().len() as f64),
+        Object::String(s) => Object::Int(s.len() as i64),
+        Object::Array(a) => Object::Int(a.borrow().len() as i64),
+        Object::Hash(h) => Object::Int(h.borrow().len() as i64),
         _ => Object::Nil,
     }
 }
@@ -114,7 +124,7 @@ fn x_println(args: Vec<Object>) -> Object {
         .map(|x| format!("{}", x))
         .collect::<Vec<String>>()
         .join(" ");
-    println!("{}", str);
+    output::print(format_args!("{}\n", str));
     Object::Nil
 }
 
@@ -123,23 +133,17 @@ fn substr(args: Vec<Object>) -> Object {
         return Object::Nil;
     }
     match (&args[0], &args[1], &args[2]) {
-        (Object::String(s), Object::Number(start), Object::Number(end)) => {
-            let start = start.floor() as usize;
-            let end = end.floor() as usize;
+        (Object::String(s), start, end) if start.is_number() && end.is_number() => {
+            let start = start.number().unwrap().floor() as usize;
+            let end = end.number().unwrap().floor() as usize;
             if start > s.len() || end > s.len() {
                 return Object::Nil;
             }
-            if end <= 0 {
+            if end == 0 {
                 return Object::String(s[start..].to_string());
             }
             Object::String(s[start..end].to_string())
         }
-        (Object::String(s), Object::Index(start), Object::Index(end)) => {
-            if *start > s.len() || *end > s.len() {
-                return Object::Nil;
-            }
-            Object::String(s[*start..*end].to_string())
-        }
         _ => Object::Nil,
     }
 }
@@ -148,22 +152,45 @@ fn typeis(args: Vec<Object>) -> Object {
     if args.len() != 1 {
         return Object::Nil;
     }
-    match &args[0] {
-        // Object::Hash(v) => Object::String(format!("{:?}", v.borrow())),
-        _ => Object::String(format!("{:?}", args[0])),
-    }
+    Object::String(format!("{:?}", args[0]))
 }
 
+// append returns a new array with the values after the ones of the array,
+// push adds them to the array itself.
 fn append(args: Vec<Object>) -> Object {
     if args.len() < 2 {
         return args[0].clone();
     }
     let mut arr = match &args[0] {
-        Object::Array(a) => a.clone(),
+        Object::Array(a) => a.borrow().clone(),
         _ => vec![],
     };
     arr.extend(args[1..].iter().cloned());
-    Object::Array(arr)
+    Object::Array(Rc::new(RefCell::new(arr)))
+}
+
+// push adds the values to the end of the array and returns its new length.
+fn push(args: Vec<Object>) -> Object {
+    match args.first() {
+        Some(Object::Array(a)) => {
+            let mut arr = a.borrow_mut();
+            arr.extend(args[1..].iter().cloned());
+            Object::Int(arr.len() as i64)
+        }
+        _ => Object::Nil,
+    }
+}
+
+// pop removes the last value of the array and returns it, nil when the
+// array is empty.
+fn pop(args: Vec<Object>) -> Object {
+    if args.len() != 1 {
+        return Object::Nil;
+    }
+    match &args[0] {
+        Object::Array(a) => a.borrow_mut().pop().unwrap_or(Object::Nil),
+        _ => Object::Nil,
+    }
 }
 
 fn intval(args: Vec<Object>) -> Object {
@@ -171,11 +198,12 @@ fn intval(args: Vec<Object>) -> Object {
         return Object::Nil;
     }
     match &args[0] {
-        Object::Number(n) => Object::Index(*n as usize),
+        Object::Number(n) => Object::Int(*n as i64),
+        Object::Int(i) => Object::Int(*i),
         Object::String(s) => {
             let s = s.trim();
-            match s.parse::<usize>() {
-                Ok(n) => Object::Index(n),
+            match s.parse::<i64>() {
+                Ok(n) => Object::Int(n),
                 Err(_) => Object::Nil,
             }
         }
@@ -201,10 +229,10 @@ fn is_number(args: Vec<Object>) -> Object {
         return Object::Nil;
     }
     let n = intval(args);
-    if let Object::Index(_) = n {
+    if let Object::Int(_) = n {
         return Object::Boolean(true);
     }
-    return Object::Boolean(false);
+    Object::Boolean(false)
 }
 
 fn strval(args: Vec<Object>) -> Object {
@@ -231,18 +259,19 @@ fn x_type(args: Vec<Object>) -> Object {
     }
     match &args[0] {
         Object::String(_) => Object::String("string".to_string()),
-        Object::Number(_) => Object::String("number".to_string()),
+        Object::Number(_) | Object::Int(_) => Object::String("number".to_string()),
         Object::Boolean(_) => Object::String("boolean".to_string()),
         Object::Nil => Object::String("nil".to_string()),
         Object::Array(_) => Object::String("array".to_string()),
         Object::Hash(_) => Object::String("object".to_string()),
-        Object::Index(_) => Object::String("number".to_string()),
         Object::Builtin(_, _, _) => Object::String("builtin".to_string()),
-        Object::Function(_, _) => Object::String("function".to_string()),
+        Object::Function(..) => Object::String("function".to_string()),
         Object::ReturnValue(_) => Object::String("return_value".to_string()),
-        Object::Class(_, _) => Object::String("class".to_string()),
+        Object::Class(..) | Object::CompiledClass { .. } => Object::String("class".to_string()),
         Object::ClassInstance { .. } => Object::String("class_instance".to_string()),
+        Object::BoundMethod { .. } => Object::String("bound_method".to_string()),
         Object::CompiledFunction { .. } => Object::String("compiled_function".to_string()),
         Object::Closure { .. } => Object::String("closure".to_string()),
+        Object::Iterator(..) => Object::String("iterator".to_string()),
     }
 }
@@ -0,0 +1,667 @@
+use std::fmt::Display;
+
+use crate::{
+    compiler::{Compiler, FunctionInfo},
+    lines::LineTable,
+    objects::Object,
+    opcode::Opcode,
+    vm::GLOBALS_SIZE,
+};
+
+// A .loxc file is a compiled program, laid out as
+//
+//   header        "LOXC", version: u16, flags: u16
+//   main start    u32, where the script begins in the instructions
+//   constants     count: u32, then a tag byte and the value of each
+//   functions     count: u32, then name, start, len, parameters and local
+//                 names of each
+//   instructions  count: u32, then an opcode byte and the operands of each
+//   lines         count: u32, then (instructions, line, column) runs, if
+//                 flags has LINES
+//
+// Every number is little endian, every usize is written as a u32 and every
+// string as its length in bytes followed by its utf-8.
+pub const MAGIC: &[u8; 4] = b"LOXC";
+pub const VERSION: u16 = 5;
+
+// the file has a line table
+const LINES: u16 = 1;
+
+const NIL: u8 = 0;
+const BOOLEAN: u8 = 1;
+const NUMBER: u8 = 2;
+const STRING: u8 = 3;
+const INT: u8 = 4;
+const FUNCTION: u8 = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BytecodeError {
+    NotBytecode,
+    UnsupportedVersion(u16),
+    Truncated,
+    InvalidConstant(u8),
+    InvalidFunction(usize),
+    InvalidOpcode(u8),
+    InvalidConstantIndex(usize),
+    InvalidGlobal(usize),
+    InvalidLocal(usize),
+    InvalidJump(usize),
+    InvalidString,
+    Unserializable(String),
+}
+
+impl std::error::Error for BytecodeError {}
+
+impl Display for BytecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BytecodeError::NotBytecode => write!(f, "Not a compiled Lox program."),
+            BytecodeError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported bytecode version {}, expected {}.", version, VERSION)
+            }
+            BytecodeError::Truncated => write!(f, "Unexpected end of bytecode."),
+            BytecodeError::InvalidConstant(tag) => write!(f, "Invalid constant tag {}.", tag),
+            BytecodeError::InvalidFunction(index) => write!(f, "Invalid function {}.", index),
+            BytecodeError::InvalidOpcode(tag) => write!(f, "Invalid opcode {}.", tag),
+            BytecodeError::InvalidConstantIndex(index) => {
+                write!(f, "Invalid constant index {}.", index)
+            }
+            BytecodeError::InvalidGlobal(index) => write!(f, "Invalid global {}.", index),
+            BytecodeError::InvalidLocal(index) => write!(f, "Invalid local {}.", index),
+            BytecodeError::InvalidJump(target) => write!(f, "Invalid jump target {}.", target),
+            BytecodeError::InvalidString => write!(f, "Invalid utf-8 in a string."),
+            BytecodeError::Unserializable(constant) => {
+                write!(f, "Can't serialize constant {}.", constant)
+            }
+        }
+    }
+}
+
+// Bytecode is everything the VM needs to run a program, without the source
+// it was compiled from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bytecode {
+    pub constants: Vec<Object>,
+    pub functions: Vec<FunctionInfo>,
+    pub main_start: usize,
+    pub instructions: Vec<Opcode>,
+    pub lines: Option<LineTable>,
+}
+
+impl Bytecode {
+    pub fn new(compiler: &Compiler) -> Self {
+        let (main_start, instructions) = compiler.get_instructions();
+        Bytecode {
+            constants: compiler.constants.clone(),
+            functions: compiler.functions.clone(),
+            main_start,
+            instructions,
+            lines: Some(compiler.get_lines()),
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, BytecodeError> {
+        let mut w = Writer::default();
+        w.bytes(MAGIC);
+        w.u16(VERSION);
+        w.u16(if self.lines.is_some() { LINES } else { 0 });
+        w.usize(self.main_start);
+
+        // functions are written to their own table, the constant refers to it
+        w.usize(self.constants.len());
+        for constant in &self.constants {
+            match constant {
+                Object::Nil => w.u8(NIL),
+                Object::Boolean(b) => {
+                    w.u8(BOOLEAN);
+                    w.u8(*b as u8);
+                }
+                Object::Number(n) => {
+                    w.u8(NUMBER);
+                    w.bytes(&n.to_le_bytes());
+                }
+                Object::String(s) => {
+                    w.u8(STRING);
+                    w.string(s);
+                }
+                Object::Int(i) => {
+                    w.u8(INT);
+                    w.bytes(&i.to_le_bytes());
+                }
+                Object::CompiledFunction { start, .. } => {
+                    let index = self
+                        .functions
+                        .iter()
+                        .position(|function| function.start == *start)
+                        .ok_or_else(|| BytecodeError::Unserializable(constant.to_string()))?;
+                    w.u8(FUNCTION);
+                    w.usize(index);
+                }
+                _ => return Err(BytecodeError::Unserializable(constant.to_string())),
+            }
+        }
+
+        w.usize(self.functions.len());
+        for function in &self.functions {
+            w.string(&function.name);
+            w.usize(function.start);
+            w.usize(function.len);
+            w.usize(function.num_parameters);
+            w.usize(function.locals.len());
+            for local in &function.locals {
+                w.string(local);
+            }
+        }
+
+        w.usize(self.instructions.len());
+        for instruction in &self.instructions {
+            w.opcode(instruction);
+        }
+
+        if let Some(lines) = &self.lines {
+            w.usize(lines.runs().len());
+            for (count, line, column) in lines.runs() {
+                w.usize(*count);
+                w.usize(*line);
+                w.usize(*column);
+            }
+        }
+        Ok(w.out)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, BytecodeError> {
+        let mut r = Reader { bytes, pos: 0 };
+        if r.take(MAGIC.len()).map_err(|_| BytecodeError::NotBytecode)? != MAGIC {
+            return Err(BytecodeError::NotBytecode);
+        }
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(BytecodeError::UnsupportedVersion(version));
+        }
+        let flags = r.u16()?;
+        let main_start = r.usize()?;
+
+        // functions are filled in once the function table has been read
+        let mut constants = vec![];
+        let mut function_slots = vec![];
+        for i in 0..r.usize()? {
+            constants.push(match r.u8()? {
+                NIL => Object::Nil,
+                BOOLEAN => Object::Boolean(r.u8()? != 0),
+                NUMBER => Object::Number(f64::from_bits(r.u64()?)),
+                STRING => Object::String(r.string()?),
+                INT => Object::Int(r.u64()? as i64),
+                FUNCTION => {
+                    function_slots.push((i, r.usize()?));
+                    Object::Nil
+                }
+                tag => return Err(BytecodeError::InvalidConstant(tag)),
+            });
+        }
+
+        let mut functions = vec![];
+        for _ in 0..r.usize()? {
+            let name = r.string()?;
+            let (start, len, num_parameters) = (r.usize()?, r.usize()?, r.usize()?);
+            let mut locals = vec![];
+            for _ in 0..r.usize()? {
+                locals.push(r.string()?);
+            }
+            functions.push(FunctionInfo {
+                name,
+                start,
+                len,
+                num_parameters,
+                locals,
+            });
+        }
+        for (slot, function) in function_slots {
+            constants[slot] = functions
+                .get(function)
+                .ok_or(BytecodeError::InvalidFunction(function))?
+                .function();
+        }
+
+        let mut instructions = vec![];
+        for _ in 0..r.usize()? {
+            instructions.push(r.opcode()?);
+        }
+
+        let mut lines = None;
+        if flags & LINES != 0 {
+            let mut runs = vec![];
+            for _ in 0..r.usize()? {
+                runs.push((r.usize()?, r.usize()?, r.usize()?));
+            }
+            lines = Some(LineTable::from_runs(runs));
+        }
+
+        let bytecode = Bytecode {
+            constants,
+            functions,
+            main_start,
+            instructions,
+            lines,
+        };
+        bytecode.validate()?;
+        Ok(bytecode)
+    }
+
+    // validate checks that every index in the program is in its table, so a
+    // corrupt file fails to load instead of crashing the VM.
+    fn validate(&self) -> Result<(), BytecodeError> {
+        let len = self.instructions.len();
+        if self.main_start > len {
+            return Err(BytecodeError::InvalidJump(self.main_start));
+        }
+        // the number of locals of the function each instruction is in, the
+        // script has none
+        let mut locals = vec![0; len];
+        for (i, function) in self.functions.iter().enumerate() {
+            let end = function.start.saturating_add(function.len);
+            if end > len || function.num_parameters >= function.locals.len().max(1) {
+                return Err(BytecodeError::InvalidFunction(i));
+            }
+            locals[function.start..end].fill(function.locals.len());
+        }
+        for (instruction, num_locals) in self.instructions.iter().zip(locals) {
+            match instruction {
+                Opcode::LoadConstant(index)
+                | Opcode::Assert(index)
+                | Opcode::Class(index)
+                | Opcode::Method(index)
+                | Opcode::GetProperty(index)
+                | Opcode::SetProperty(index)
+                | Opcode::Invoke(index, _)
+                | Opcode::GetSuper(index)
+                    if *index >= self.constants.len() =>
+                {
+                    return Err(BytecodeError::InvalidConstantIndex(*index))
+                }
+                Opcode::GetGlobal(index) | Opcode::SetGlobal(index) if *index >= GLOBALS_SIZE => {
+                    return Err(BytecodeError::InvalidGlobal(*index))
+                }
+                Opcode::GetLocal(index) | Opcode::SetLocal(index) if *index >= num_locals => {
+                    return Err(BytecodeError::InvalidLocal(*index))
+                }
+                Opcode::Jump(target)
+                | Opcode::JumpIfFalse(target)
+                | Opcode::JumpNotTruthy(target)
+                | Opcode::IterNext(target)
+                    if *target > len =>
+                {
+                    return Err(BytecodeError::InvalidJump(*target))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    out: Vec<u8>,
+}
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.out.extend_from_slice(bytes);
+    }
+
+    fn u8(&mut self, n: u8) {
+        self.out.push(n);
+    }
+
+    fn u16(&mut self, n: u16) {
+        self.bytes(&n.to_le_bytes());
+    }
+
+    fn usize(&mut self, n: usize) {
+        self.bytes(&(n as u32).to_le_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.usize(s.len());
+        self.bytes(s.as_bytes());
+    }
+
+    fn opcode(&mut self, op: &Opcode) {
+        let (tag, operands): (u8, &[usize]) = match op {
+            Opcode::Add => (0, &[]),
+            Opcode::LoadConstant(a) => (1, &[*a]),
+            Opcode::Print(a) => (2, &[*a]),
+            Opcode::Pop => (3, &[]),
+            Opcode::Abs => (4, &[]),
+            Opcode::Nagetive => (5, &[]),
+            Opcode::JumpNotTruthy(a) => (6, &[*a]),
+            Opcode::Jump(a) => (7, &[*a]),
+            Opcode::Null => (8, &[]),
+            Opcode::ReturnValue => (9, &[]),
+            Opcode::SetGlobal(a) => (10, &[*a]),
+            Opcode::GetGlobal(a) => (11, &[*a]),
+            Opcode::SetLocal(a) => (12, &[*a]),
+            Opcode::GetLocal(a) => (13, &[*a]),
+            Opcode::GetBuiltin(a) => (14, &[*a]),
+            Opcode::Closure(a, b) => return self.operands(15, &[*a, *b]),
+            Opcode::GetFree(a) => (16, &[*a]),
+            Opcode::CurrentClosure => (17, &[]),
+            Opcode::Call(a) => (18, &[*a]),
+            Opcode::TailCall(a) => (19, &[*a]),
+            Opcode::Return => (20, &[]),
+            Opcode::Array(a) => (21, &[*a]),
+            Opcode::Hash(a) => (22, &[*a]),
+            Opcode::Index => (23, &[]),
+            Opcode::SetIndex => (24, &[]),
+            Opcode::True => (25, &[]),
+            Opcode::False => (26, &[]),
+            Opcode::Equal => (27, &[]),
+            Opcode::JumpIfFalse(a) => (28, &[*a]),
+            Opcode::NotEqual => (29, &[]),
+            Opcode::GreaterThan => (30, &[]),
+            Opcode::LessThan => (31, &[]),
+            Opcode::GreaterThanEqual => (32, &[]),
+            Opcode::LessThanEqual => (33, &[]),
+            Opcode::Minus => (34, &[]),
+            Opcode::Mod => (35, &[]),
+            Opcode::Divide => (36, &[]),
+            Opcode::Multiply => (37, &[]),
+            Opcode::Remainder => (38, &[]),
+            Opcode::Bang => (39, &[]),
+            Opcode::MinusOne => (40, &[]),
+            Opcode::PlusOne => (41, &[]),
+            Opcode::DefineGlobal(name) => {
+                self.u8(42);
+                return self.string(name);
+            }
+            Opcode::Assert(a) => (43, &[*a]),
+            Opcode::EqualEqual => (44, &[]),
+            Opcode::Exit(a) => (45, &[*a]),
+            Opcode::Class(a) => (46, &[*a]),
+            Opcode::Inherit => (47, &[]),
+            Opcode::Method(a) => (48, &[*a]),
+            Opcode::GetProperty(a) => (49, &[*a]),
+            Opcode::SetProperty(a) => (50, &[*a]),
+            Opcode::Invoke(a, b) => return self.operands(51, &[*a, *b]),
+            Opcode::GetSuper(a) => (52, &[*a]),
+            Opcode::Iterator => (53, &[]),
+            Opcode::IterNext(a) => (54, &[*a]),
+            Opcode::BitAnd => (55, &[]),
+            Opcode::BitOr => (56, &[]),
+        };
+        self.operands(tag, operands);
+    }
+
+    fn operands(&mut self, tag: u8, operands: &[usize]) {
+        self.u8(tag);
+        for operand in operands {
+            self.usize(*operand);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], BytecodeError> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + n)
+            .ok_or(BytecodeError::Truncated)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, BytecodeError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, BytecodeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn usize(&mut self) -> Result<usize, BytecodeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize)
+    }
+
+    fn string(&mut self) -> Result<String, BytecodeError> {
+        let len = self.usize()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| BytecodeError::InvalidString)
+    }
+
+    fn opcode(&mut self) -> Result<Opcode, BytecodeError> {
+        Ok(match self.u8()? {
+            0 => Opcode::Add,
+            1 => Opcode::LoadConstant(self.usize()?),
+            2 => Opcode::Print(self.usize()?),
+            3 => Opcode::Pop,
+            4 => Opcode::Abs,
+            5 => Opcode::Nagetive,
+            6 => Opcode::JumpNotTruthy(self.usize()?),
+            7 => Opcode::Jump(self.usize()?),
+            8 => Opcode::Null,
+            9 => Opcode::ReturnValue,
+            10 => Opcode::SetGlobal(self.usize()?),
+            11 => Opcode::GetGlobal(self.usize()?),
+            12 => Opcode::SetLocal(self.usize()?),
+            13 => Opcode::GetLocal(self.usize()?),
+            14 => Opcode::GetBuiltin(self.usize()?),
+            15 => Opcode::Closure(self.usize()?, self.usize()?),
+            16 => Opcode::GetFree(self.usize()?),
+            17 => Opcode::CurrentClosure,
+            18 => Opcode::Call(self.usize()?),
+            19 => Opcode::TailCall(self.usize()?),
+            20 => Opcode::Return,
+            21 => Opcode::Array(self.usize()?),
+            22 => Opcode::Hash(self.usize()?),
+            23 => Opcode::Index,
+            24 => Opcode::SetIndex,
+            25 => Opcode::True,
+            26 => Opcode::False,
+            27 => Opcode::Equal,
+            28 => Opcode::JumpIfFalse(self.usize()?),
+            29 => Opcode::NotEqual,
+            30 => Opcode::GreaterThan,
+            31 => Opcode::LessThan,
+            32 => Opcode::GreaterThanEqual,
+            33 => Opcode::LessThanEqual,
+            34 => Opcode::Minus,
+            35 => Opcode::Mod,
+            36 => Opcode::Divide,
+            37 => Opcode::Multiply,
+            38 => Opcode::Remainder,
+            39 => Opcode::Bang,
+            40 => Opcode::MinusOne,
+            41 => Opcode::PlusOne,
+            42 => Opcode::DefineGlobal(self.string()?),
+            43 => Opcode::Assert(self.usize()?),
+            44 => Opcode::EqualEqual,
+            45 => Opcode::Exit(self.usize()?),
+            46 => Opcode::Class(self.usize()?),
+            47 => Opcode::Inherit,
+            48 => Opcode::Method(self.usize()?),
+            49 => Opcode::GetProperty(self.usize()?),
+            50 => Opcode::SetProperty(self.usize()?),
+            51 => Opcode::Invoke(self.usize()?, self.usize()?),
+            52 => Opcode::GetSuper(self.usize()?),
+            53 => Opcode::Iterator,
+            54 => Opcode::IterNext(self.usize()?),
+            55 => Opcode::BitAnd,
+            56 => Opcode::BitOr,
+            tag => return Err(BytecodeError::InvalidOpcode(tag)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::Lexing, parser::Parser, resolver::Resolver};
+
+    #[test]
+    fn test_round_trip() {
+        let program = Parser::new(Lexing::new(
+            "fun add(a, b) { return a + b; } var x = [1, 'two', true, nil]; print add(1, 2);",
+        ))
+        .parse();
+        let locals = Resolver::new().resolve(&program);
+        let mut compiler = Compiler::new(program, locals);
+        compiler.compile();
+        let bytecode = Bytecode::new(&compiler);
+        assert_eq!(bytecode.functions[0].locals, vec!["", "a", "b"]);
+        assert!(bytecode.lines.is_some());
+        let bytes = bytecode.encode().unwrap();
+        assert_eq!(&bytes[..4], MAGIC);
+        assert_eq!(Bytecode::decode(&bytes).unwrap(), bytecode);
+    }
+
+    #[test]
+    fn test_every_opcode() {
+        let instructions = vec![
+            Opcode::Add,
+            Opcode::LoadConstant(1),
+            Opcode::Print(2),
+            Opcode::Pop,
+            Opcode::Abs,
+            Opcode::Nagetive,
+            Opcode::JumpNotTruthy(3),
+            Opcode::Jump(4),
+            Opcode::Null,
+            Opcode::ReturnValue,
+            Opcode::SetGlobal(5),
+            Opcode::GetGlobal(6),
+            Opcode::SetLocal(7),
+            Opcode::GetLocal(8),
+            Opcode::GetBuiltin(9),
+            Opcode::Closure(10, 11),
+            Opcode::GetFree(12),
+            Opcode::CurrentClosure,
+            Opcode::Call(13),
+            Opcode::TailCall(14),
+            Opcode::Return,
+            Opcode::Array(15),
+            Opcode::Hash(16),
+            Opcode::Index,
+            Opcode::SetIndex,
+            Opcode::True,
+            Opcode::False,
+            Opcode::Equal,
+            Opcode::JumpIfFalse(17),
+            Opcode::NotEqual,
+            Opcode::GreaterThan,
+            Opcode::LessThan,
+            Opcode::GreaterThanEqual,
+            Opcode::LessThanEqual,
+            Opcode::Minus,
+            Opcode::Mod,
+            Opcode::Divide,
+            Opcode::Multiply,
+            Opcode::Remainder,
+            Opcode::Bang,
+            Opcode::MinusOne,
+            Opcode::PlusOne,
+            Opcode::DefineGlobal("a".to_string()),
+            Opcode::Assert(18),
+            Opcode::EqualEqual,
+            Opcode::Exit(19),
+            Opcode::Class(20),
+            Opcode::Inherit,
+            Opcode::Method(21),
+            Opcode::GetProperty(22),
+            Opcode::SetProperty(23),
+            Opcode::Invoke(24, 25),
+            Opcode::GetSuper(26),
+            Opcode::Iterator,
+            Opcode::IterNext(27),
+            Opcode::BitAnd,
+            Opcode::BitOr,
+        ];
+        // the indices have to be in their tables to decode
+        let function = FunctionInfo {
+            name: "f".to_string(),
+            start: 0,
+            len: instructions.len(),
+            num_parameters: 0,
+            locals: vec![String::new(); 9],
+        };
+        let bytecode = Bytecode {
+            constants: vec![Object::Nil; 27],
+            functions: vec![function],
+            main_start: 0,
+            instructions,
+            lines: None,
+        };
+        assert_eq!(Bytecode::decode(&bytecode.encode().unwrap()).unwrap(), bytecode);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(Bytecode::decode(b"LOX"), Err(BytecodeError::NotBytecode));
+        assert_eq!(
+            Bytecode::decode(b"LOXC\x01\x00"),
+            Err(BytecodeError::UnsupportedVersion(1))
+        );
+        let bytes = Bytecode {
+            constants: vec![Object::Number(1.0)],
+            functions: vec![],
+            main_start: 0,
+            instructions: vec![Opcode::LoadConstant(0)],
+            lines: None,
+        }
+        .encode()
+        .unwrap();
+        assert_eq!(
+            Bytecode::decode(&bytes[..bytes.len() - 1]),
+            Err(BytecodeError::Truncated)
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid_indices() {
+        let decode = |instructions: Vec<Opcode>, functions: Vec<FunctionInfo>| {
+            let bytecode = Bytecode {
+                constants: vec![Object::Nil],
+                functions,
+                main_start: 0,
+                instructions,
+                lines: None,
+            };
+            Bytecode::decode(&bytecode.encode().unwrap())
+        };
+        assert_eq!(
+            decode(vec![Opcode::LoadConstant(1)], vec![]),
+            Err(BytecodeError::InvalidConstantIndex(1))
+        );
+        assert_eq!(
+            decode(vec![Opcode::GetGlobal(GLOBALS_SIZE)], vec![]),
+            Err(BytecodeError::InvalidGlobal(GLOBALS_SIZE))
+        );
+        assert_eq!(
+            decode(vec![Opcode::GetLocal(0)], vec![]),
+            Err(BytecodeError::InvalidLocal(0))
+        );
+        assert_eq!(
+            decode(vec![Opcode::Jump(2)], vec![]),
+            Err(BytecodeError::InvalidJump(2))
+        );
+        let function = FunctionInfo {
+            name: "f".to_string(),
+            start: 0,
+            len: 2,
+            num_parameters: 0,
+            locals: vec![String::new()],
+        };
+        assert_eq!(
+            decode(vec![Opcode::Return], vec![function.clone()]),
+            Err(BytecodeError::InvalidFunction(0))
+        );
+        assert!(decode(vec![Opcode::GetLocal(0), Opcode::Return], vec![function]).is_ok());
+    }
+}
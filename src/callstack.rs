@@ -1,37 +1,49 @@
 use crate::frame::{Frame, FramePool};
-
-pub struct CallStack<'a> {
-    pub stack: Vec<*mut Frame>,
-    pub pool: &'a mut FramePool,
+use crate::objects::Object;
+
+// CallStack owns the frames of the calls the VM is running, innermost last.
+// Popped frames go back to the pool for the next call.
+#[derive(Debug, Default)]
+pub struct CallStack {
+    pub stack: Vec<Frame>,
+    pub pool: FramePool,
 }
 
-impl<'a> CallStack<'a> {
-    pub fn new(pool: &'a mut FramePool) -> Self {
+impl CallStack {
+    pub fn new() -> Self {
         CallStack {
             stack: Vec::with_capacity(1024),
-            pool,
+            pool: FramePool::new(),
         }
     }
 
-    pub fn push_frame(&mut self, frame: *mut Frame) {
+    pub fn push_frame(&mut self, closure: Object, ip: usize, base_pointer: usize) {
+        let frame = self.pool.get_frame(closure, ip, base_pointer);
         self.stack.push(frame);
     }
 
-    pub fn pop_frame(&mut self) -> Option<*mut Frame> {
-        println!("pop_frame------------------------>");
+    // pop_frame drops the innermost frame and returns its base pointer.
+    pub fn pop_frame(&mut self) -> Option<usize> {
+        let frame = self.stack.pop()?;
+        let base_pointer = frame.base_pointer();
+        self.pool.return_frame(frame);
+        Some(base_pointer)
+    }
 
-        for frame in self.stack.iter() {
-            println!("frame: {:?}", frame);
-        }
-        self.stack.pop()
+    pub fn current_frame(&self) -> Option<&Frame> {
+        self.stack.last()
     }
 
-    pub fn current_frame(&self) -> Option<&mut Frame> {
-        self.stack.last().map(|&ptr| unsafe { &mut *ptr })
+    pub fn current_frame_mut(&mut self) -> Option<&mut Frame> {
+        self.stack.last_mut()
     }
 
-    pub fn get_pool(&mut self) -> &mut FramePool {
-        self.pool
+    // frames lists the running calls, outermost first.
+    pub fn frames(&self) -> &[Frame] {
+        &self.stack
     }
 
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
 }
@@ -1,191 +1,346 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 use crate::{
-    ast::{ExprType, Literal, Program, Stmt},
+    ast::{ExprNode, ExprType, Ident, Literal, Program, Stmt, StmtNode},
     builtins::Builtins,
+    diagnostic::{
+        Diagnostic, INVALID_ASSIGNMENT, INVALID_JUMP, INVALID_SUPER, UNDEFINED_VARIABLE,
+        UNSUPPORTED_CAPTURE, UNSUPPORTED_SYNTAX,
+    },
+    lines::LineTable,
     objects::Object,
     opcode::Opcode,
+    resolver::Locals,
+    span::Span,
     symbol::{Scope, Symbol, SymbolTable},
     token::Token,
 };
 
+// LoopJumps collects the break and continue jumps of the loop being compiled.
+// They are patched once the loop's step and end positions are known.
+struct LoopJumps {
+    label: Option<String>,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+// FunctionInfo is what the disassembler and stack traces know about a
+// compiled function: its name, where its body is and its locals by slot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub start: usize,
+    pub len: usize,
+    pub num_parameters: usize,
+    pub locals: Vec<String>,
+}
+
+impl FunctionInfo {
+    pub fn function(&self) -> Object {
+        Object::CompiledFunction {
+            start: self.start,
+            len: self.len,
+            num_locals: self.locals.len(),
+            num_parameters: self.num_parameters,
+        }
+    }
+}
+
+// FIELDS names the method a class keeps its field initializers in. It can't
+// be written in Lox, so no user method collides with it.
+pub const FIELDS: &str = "<fields>";
+
+// FunctionKind is the kind of function being compiled. Methods keep the
+// instance in local 0, initializers also return it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
 pub struct Compiler {
     program: Program,
+    // depth of every local variable use, from the resolver
+    locals: Locals,
     pub constants: Vec<Object>,
     pub instructions: Vec<Opcode>,
-    pre_instructions: Vec<Opcode>,
+    // source lines of instructions, and of closure_ins
+    lines: LineTable,
+    closure_lines: LineTable,
+    // span of the statement or expression being compiled
+    span: Span,
+    // instructions of the enclosing functions while a nested one is compiled
+    pre_instructions: Vec<(Vec<Opcode>, LineTable)>,
     pub builtins: Builtins,
     pub symbols: Rc<RefCell<SymbolTable>>,
     pub closure_ins: Vec<Opcode>,
+    pub functions: Vec<FunctionInfo>,
+    loops: Vec<LoopJumps>,
+    function: FunctionKind,
+    // superclass of the class whose methods are being compiled
+    superclass: Option<Ident>,
+    // number of blocks around the statement being compiled
+    depth: usize,
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl Compiler {
-    pub fn new(program: Program) -> Compiler {
+    pub fn new(program: Program, locals: Locals) -> Compiler {
         Compiler {
             program,
+            locals,
             constants: Vec::new(),
             instructions: Vec::new(),
+            lines: LineTable::new(),
+            closure_lines: LineTable::new(),
+            span: Span::default(),
             builtins: Builtins::new(),
             pre_instructions: vec![],
             symbols: Rc::new(RefCell::new(SymbolTable::new())),
             closure_ins: vec![],
+            functions: vec![],
+            loops: vec![],
+            function: FunctionKind::Script,
+            superclass: None,
+            depth: 0,
+            diagnostics: vec![],
         }
     }
 
     pub fn compile(&mut self) {
+        // declare the top level names first, so functions can call functions
+        // and read variables that are defined further down the file
         for statement in self.program.clone() {
-            self.compile_statement(&statement);
+            match &statement.node {
+                Stmt::Var(ident, _) | Stmt::Function(ident, ..) => {
+                    self.define(&ident.0);
+                }
+                Stmt::ClassStmt { name: ident, .. } => {
+                    self.define(&ident.0);
+                }
+                _ => {}
+            }
         }
+        let program = self.program.clone();
+        for (i, statement) in program.iter().enumerate() {
+            match &statement.node {
+                // the value of a trailing expression is the result of the program
+                Stmt::Expr(expr) if i == program.len() - 1 => self.compile_expression(expr),
+                _ => self.compile_statement(statement),
+            }
+        }
+    }
+
+    pub fn has_errors(&self) -> bool {
+        !self.diagnostics.is_empty()
+    }
+
+    // diagnostics are the errors found in code the compiler can't translate.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    fn error(&mut self, code: &'static str, span: Span, message: &str) {
+        self.diagnostics
+            .push(Diagnostic::error(code, message, span));
+    }
+
+    // unsupported reports code the vm has no instructions for.
+    fn unsupported(&mut self, span: Span, what: &str) {
+        let message = format!("The compiler does not support {}.", what);
+        self.error(UNSUPPORTED_SYNTAX, span, &message);
     }
 
+    // get_instructions lays the script out after the function bodies and
+    // returns where it starts together with the whole program.
     pub fn get_instructions(&self) -> (usize, Vec<Opcode>) {
         let l = self.closure_ins.len();
         let mut instractions = self.closure_ins.clone();
-        instractions.extend(self.instructions.clone());
+        instractions.extend(relocate(self.instructions.clone(), l));
         (l, instractions)
     }
 
-    fn compile_statement(&mut self, stmt: &Stmt) {
-        match stmt {
+    // get_lines returns the line table of the program get_instructions lays out.
+    pub fn get_lines(&self) -> LineTable {
+        let mut lines = self.closure_lines.clone();
+        lines.extend(&self.lines);
+        lines
+    }
+
+    // compile_block compiles block in a scope of its own, so its declarations
+    // shadow the enclosing ones until the block ends.
+    fn compile_block(&mut self, block: &[StmtNode]) {
+        let store = self.begin_block();
+        for stmt in block.iter() {
+            self.compile_statement(stmt);
+        }
+        self.end_block(store);
+    }
+
+    fn compile_statement(&mut self, stmt: &StmtNode) {
+        let enclosing = std::mem::replace(&mut self.span, stmt.span);
+        self.compile_stmt(stmt);
+        self.span = enclosing;
+    }
+
+    fn compile_stmt(&mut self, stmt: &StmtNode) {
+        match &stmt.node {
+            Stmt::Blank => {}
             Stmt::Expr(expr) => {
                 self.compile_expression(expr);
+                // drop the unused value, so loops don't grow the stack
+                if leaves_value(expr) {
+                    self.emit(Opcode::Pop);
+                }
             }
             Stmt::Var(ident, expr) => {
                 self.compile_expression(expr);
-                let symbol = self.symbols.borrow_mut().define(ident.0.clone());
-                if symbol.scope == Scope::Global {
-                    self.emit(Opcode::SetGlobal(symbol.index));
-                } else {
-                    self.emit(Opcode::SetLocal(symbol.index));
-                }
+                let symbol = self.define(&ident.0);
+                self.store_symbol(symbol);
             }
-            Stmt::Block(stmts) => {
-                for stmt in stmts.iter() {
-                    self.compile_statement(stmt);
-                }
+            Stmt::Block(stmts) => self.compile_block(stmts),
+            Stmt::Function(ident, params, body) => {
+                let symbol = self.define(&ident.0);
+                let index = self.compile_function(&ident.0, params, body, FunctionKind::Function);
+                self.emit_load_constant(index);
+                self.store_symbol(symbol);
             }
-            Stmt::Function(ident, args, body) => {
-                let symbol = self.symbols.borrow_mut().define(ident.0.clone());
-                self.enter_scope();
-
-                for arg in args.iter() {
-                    let symbol = self.symbols.borrow_mut().resolve(arg.0.as_str());
-                    if symbol.is_none() {
-                        unimplemented!("Symbol not found: {:?}", arg);
-                    }
-                    // self.load_symbol(symbol.unwrap().clone());
+            Stmt::ClassStmt {
+                name,
+                superclass,
+                properties,
+            } => {
+                let symbol = self.define(&name.0);
+                let index = self.name_constant(&name.0);
+                self.emit(Opcode::Class(index));
+                if let Some(superclass) = superclass {
+                    let symbol = self.resolve_name(self.span, superclass);
+                    self.load_symbol(symbol);
+                    self.emit(Opcode::Inherit);
                 }
-
-                // println!("{:?}", self.symbols.borrow_mut().free_symbols);
-                for stmt in body.iter() {
-                    self.compile_statement(stmt);
+                let enclosing = std::mem::replace(&mut self.superclass, superclass.clone());
+                // field initializers become a method that sets them on a new
+                // instance before init runs
+                let fields: Vec<StmtNode> = properties
+                    .iter()
+                    .filter_map(|property| match &property.node {
+                        Stmt::Var(ident, expr) => {
+                            let this = ExprNode::new(ExprType::This, property.span);
+                            let set = ExprType::Set {
+                                object: Box::new(this),
+                                name: ident.clone(),
+                                value: Box::new(expr.clone()),
+                            };
+                            let set = ExprNode::new(set, property.span);
+                            Some(StmtNode::new(Stmt::Expr(set), property.span))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                if !fields.is_empty() {
+                    let index = self.compile_function(FIELDS, &[], &fields, FunctionKind::Method);
+                    self.emit_load_constant(index);
+                    let index = self.name_constant(FIELDS);
+                    self.emit(Opcode::Method(index));
                 }
-
-                let mut instraction = self.leave_scope();
-
-                let symbols = self.symbols.borrow().clone();
-                let num_locals = symbols.num_definitions;
-                let num_parameters = args.len();
-
-                if instraction.last().unwrap() != &Opcode::Return {
-                    instraction.push(Opcode::LoadConstant(1));
-                    instraction.push(Opcode::Return);
+                for property in properties.iter() {
+                    match &property.node {
+                        Stmt::Function(ident, params, body) => {
+                            let kind = match ident.0.as_str() {
+                                "init" => FunctionKind::Initializer,
+                                _ => FunctionKind::Method,
+                            };
+                            let index = self.compile_function(&ident.0, params, body, kind);
+                            self.emit_load_constant(index);
+                            let index = self.name_constant(&ident.0);
+                            self.emit(Opcode::Method(index));
+                        }
+                        Stmt::Var(..) => {}
+                        _ => self.error(
+                            UNSUPPORTED_SYNTAX,
+                            property.span,
+                            "Expect field or method in class body.",
+                        ),
+                    }
                 }
-
-                let start = self.closure_ins.len();
-
-                self.closure_ins.extend(instraction.clone());
-
-                let compiled_object = Object::CompiledFunction {
-                    num_locals,
-                    num_parameters,
-                    start,
-                    len: instraction.len(),
-                };
-                let index = self.constants.len();
-                self.constants.push(compiled_object);
-                self.emit_load_constant(index); // Load function object
-                self.emit(Opcode::SetGlobal(symbol.index)); // Set global variable
+                self.superclass = enclosing;
+                self.store_symbol(symbol);
             }
-            Stmt::For {
-                init,
-                conditions,
-                step,
-                block,
-            } => {
-                self.compile_statement(init);
-                let start = self.instructions.len();
-                self.compile_expression(conditions);
-                let jump_not_truthy = self.emit_return_position(Opcode::JumpIfFalse(0));
-                self.compile_statement(&Stmt::Block(block.clone()));
-                self.compile_statement(step);
-                self.emit(Opcode::Jump(start));
-                let end = self.instructions.len();
-                self.instructions[jump_not_truthy] = Opcode::JumpIfFalse(end);
+            Stmt::For { .. } | Stmt::While(..) | Stmt::ForIn { .. } => {
+                self.compile_loop(stmt, None)
             }
-            Stmt::Assert { condition, message } => {
-                self.compile_expression(condition);
-                let msg = match message.as_ref() {
-                    ExprType::Literal(Literal::String(msg)) => msg,
-                    _ => "",
-                };
-                if msg.is_empty() {
-                    self.emit(Opcode::Assert(0));
-                    return;
+            Stmt::Labeled(label, stmt) => self.compile_loop(stmt, Some(label.0.clone())),
+            Stmt::Break(label) => {
+                let pos = self.emit_return_position(Opcode::Jump(0));
+                if let Some(jumps) = self.enclosing_loop(label, "break") {
+                    jumps.breaks.push(pos);
                 }
-                let pos = self.emit_return_position(Opcode::Assert(0));
-                self.compile_expression(message);
-                self.emit(Opcode::Print(1));
-                self.emit(Opcode::Exit(3));
-                self.instructions[pos] = Opcode::Assert(self.instructions.len());
-            }
-            Stmt::Assign(ident, right) => match ident {
-                ExprType::Ident(ident) => {
-                    let symbol = self.symbols.borrow_mut().resolve(ident.0.as_str());
-                    if symbol.is_none() {
-                        unimplemented!("Symbol not found: {:?}", ident);
-                    }
-                    self.compile_expression(right);
-                    self.load_symbol(symbol.unwrap().clone());
-                    return;
+            }
+            Stmt::Continue(label) => {
+                let pos = self.emit_return_position(Opcode::Jump(0));
+                if let Some(jumps) = self.enclosing_loop(label, "continue") {
+                    jumps.continues.push(pos);
                 }
-                _ => unimplemented!("Left side of assignment not implemented: {:?}", ident),
-            },
+            }
+            Stmt::Assert { condition, message } => {
+                self.compile_expression(condition);
+                // a failing assert stops with the message the evaluator gives
+                let failed = format!("Assertion failed: {} {}", condition, message);
+                let index = self.name_constant(&failed);
+                self.emit(Opcode::Assert(index));
+            }
+            Stmt::Assign(target, right) => self.compile_assign(target, right),
             Stmt::Return(expr) => {
                 self.compile_expression(expr);
-                // let last = self.instructions.last().unwrap().clone();
-                // match last {
-                //     Opcode::Call(count) => self.emit(Opcode::TailCall(count)),
-                //     _ => self.emit(Opcode::Return)
-                // }
+                // init always hands back the instance it set up
+                if self.function == FunctionKind::Initializer {
+                    self.emit(Opcode::Pop);
+                    self.emit(Opcode::GetLocal(0));
+                }
                 self.emit(Opcode::ReturnValue);
-                // if self.is_tail_call(stmt) {
-                //     self.emit(Opcode::TailCall(0));
-                // } else {
-                //     self.emit(Opcode::ReturnValue);
-                //     // self.emit(Opcode::Return);
-                // }
-            }
-            _ => unimplemented!("Statement not implemented: {:?}", stmt),
+            }
+            Stmt::Switch(..) | Stmt::Case(..) | Stmt::Default(_) => {
+                self.unsupported(stmt.span, "switch statements")
+            }
+            Stmt::Import(_) => self.unsupported(stmt.span, "import"),
+            _ => self.unsupported(stmt.span, "this statement"),
         }
     }
 
-    fn compile_expression(&mut self, expr: &ExprType) {
-        match expr {
+    fn compile_expression(&mut self, expr: &ExprNode) {
+        let enclosing = std::mem::replace(&mut self.span, expr.span);
+        self.compile_expr(expr);
+        self.span = enclosing;
+    }
+
+    fn compile_expr(&mut self, expr: &ExprNode) {
+        match &expr.node {
             ExprType::InfixExpr(left, op, right) => {
                 if *op == Token::Equal {
-                    match left.as_ref() {
-                        ExprType::Ident(ident) => {
-                            let symbol = self.symbols.borrow_mut().resolve(ident.0.as_str());
-                            if symbol.is_none() {
-                                unimplemented!("Symbol not found: {:?}", ident);
-                            }
-                            self.compile_expression(right);
-                            self.load_symbol(symbol.unwrap().clone());
-                            return;
-                        }
-                        _ => unimplemented!("Left side of assignment not implemented: {:?}", left),
-                    }
+                    self.compile_assign(left, right);
+                    return;
+                }
+                // and/or skip the right operand once the left one decides,
+                // the left one is then the value of the expression
+                if *op == Token::And {
+                    self.compile_expression(left);
+                    let end = self.emit_return_position(Opcode::JumpNotTruthy(0));
+                    self.emit(Opcode::Pop);
+                    self.compile_expression(right);
+                    self.instructions[end] = Opcode::JumpNotTruthy(self.instructions.len());
+                    return;
+                }
+                if *op == Token::Or {
+                    self.compile_expression(left);
+                    let right_start = self.emit_return_position(Opcode::JumpNotTruthy(0));
+                    let end = self.emit_return_position(Opcode::Jump(0));
+                    self.instructions[right_start] = Opcode::JumpNotTruthy(self.instructions.len());
+                    self.emit(Opcode::Pop);
+                    self.compile_expression(right);
+                    self.instructions[end] = Opcode::Jump(self.instructions.len());
+                    return;
                 }
                 self.compile_expression(left);
                 self.compile_expression(right);
@@ -194,27 +349,40 @@ impl Compiler {
                     Token::Star => self.emit(Opcode::Multiply),
                     Token::Slash => self.emit(Opcode::Divide),
                     Token::Mod => self.emit(Opcode::Mod),
+                    Token::BitAnd => self.emit(Opcode::BitAnd),
+                    Token::BitOr => self.emit(Opcode::BitOr),
                     Token::Minus => self.emit(Opcode::Minus),
                     Token::Greater => self.emit(Opcode::GreaterThan),
                     Token::Less => self.emit(Opcode::LessThan),
+                    Token::GreaterEqual => self.emit(Opcode::GreaterThanEqual),
+                    Token::LessEqual => self.emit(Opcode::LessThanEqual),
                     Token::EqualEqual => self.emit(Opcode::EqualEqual),
-                    Token::PlusSelf => {
-                        self.emit(Opcode::Add);
-                        match left.as_ref() {
+                    Token::BangEqual => self.emit(Opcode::NotEqual),
+                    Token::PlusSelf
+                    | Token::MinusSelf
+                    | Token::StarSelf
+                    | Token::SlashSelf
+                    | Token::ModSelf => {
+                        self.emit(match op {
+                            Token::PlusSelf => Opcode::Add,
+                            Token::MinusSelf => Opcode::Minus,
+                            Token::StarSelf => Opcode::Multiply,
+                            Token::SlashSelf => Opcode::Divide,
+                            _ => Opcode::Mod,
+                        });
+                        match &left.node {
                             ExprType::Ident(ident) => {
-                                let symbol = self.symbols.borrow_mut().resolve(ident.0.as_str());
-                                if symbol.is_none() {
-                                    unimplemented!("Symbol not found: {:?}", ident);
-                                }
-                                self.emit(Opcode::SetGlobal(symbol.unwrap().index));
+                                let symbol = self.resolve_name(left.span, ident);
+                                self.store_symbol(symbol);
                             }
-                            _ => unimplemented!(
-                                "Left side of assignment not implemented: {:?}",
-                                left
+                            _ => self.error(
+                                INVALID_ASSIGNMENT,
+                                left.span,
+                                "Invalid assignment target.",
                             ),
                         }
                     }
-                    _ => unimplemented!("Operator not implemented: {:?}", op),
+                    _ => self.unsupported(expr.span, "this operator"),
                 }
             }
             ExprType::PrintExpr(expr) => {
@@ -229,7 +397,8 @@ impl Compiler {
                     Token::Minus => {
                         self.emit(Opcode::Nagetive);
                     }
-                    _ => unimplemented!("prefix expr Operator not implemented: {:?}", op),
+                    Token::Bang => self.emit(Opcode::Bang),
+                    _ => self.unsupported(expr.span, "this operator"),
                 }
             }
             ExprType::Literal(lit) => {
@@ -239,39 +408,97 @@ impl Compiler {
                     Literal::String(s) => self.constants.push(Object::String(s.clone())),
                     Literal::Bool(b) => self.constants.push(Object::Boolean(*b)),
                     Literal::Nil => self.constants.push(Object::Nil),
-                    _ => unimplemented!("Literal not implemented: {:?}", lit),
+                    Literal::Int(i) => self.constants.push(Object::Int(*i)),
+                    Literal::Array(elements) => {
+                        for element in elements.iter() {
+                            self.compile_expression(element);
+                        }
+                        self.emit(Opcode::Array(elements.len()));
+                        return;
+                    }
+                    Literal::Hash(pairs) => {
+                        for (key, value) in pairs.iter() {
+                            self.compile_expression(key);
+                            self.compile_expression(value);
+                        }
+                        self.emit(Opcode::Hash(pairs.len()));
+                        return;
+                    }
                 }
                 self.emit_load_constant(index);
             }
             ExprType::Ident(ident) => {
-                let symbol = self.symbols.borrow_mut().resolve(ident.0.as_str());
-                if symbol.is_none() {
-                    unimplemented!("Symbol not found: {:?}", ident);
-                }
-                self.load_symbol(symbol.unwrap().clone());
+                let symbol = self.resolve_name(self.span, ident);
+                self.load_symbol(symbol);
             }
             ExprType::Call { callee, args } => {
+                // a method call looks the method up on the receiver without
+                // binding it first
+                if let ExprType::Get { object, name } = &callee.node {
+                    self.compile_expression(object);
+                    for arg in args.iter() {
+                        self.compile_expression(arg);
+                    }
+                    let index = self.name_constant(&name.0);
+                    self.emit(Opcode::Invoke(index, args.len()));
+                    return;
+                }
+                // the callee sits below its arguments, the VM turns that slot
+                // and the arguments into the frame of the call
+                self.compile_expression(callee);
                 for arg in args.iter() {
                     self.compile_expression(arg);
                 }
-
-                match callee.as_ref() {
-                    ExprType::Ident(ident) => {
-                        let index = self.builtins.get_index(ident.0.as_str());
-                        if index.is_some() {
-                            self.emit(Opcode::GetBuiltin(index.unwrap()));
-                            self.emit(Opcode::Call(args.len()));
-                        } else {
-                            let symbol = self.symbols.borrow_mut().resolve(ident.0.as_str());
-                            if symbol.is_none() {
-                                unimplemented!("Symbol not found: {:?}", ident);
-                            }
-                            let index = symbol.unwrap().index;
-                            self.emit(Opcode::Closure(index, args.len()));
-                        }
+                self.emit(Opcode::Call(args.len()));
+            }
+            ExprType::ClassInit { name, args } => {
+                let symbol = self.resolve_name(self.span, name);
+                self.load_symbol(symbol);
+                for arg in args.iter() {
+                    self.compile_expression(arg);
+                }
+                self.emit(Opcode::Call(args.len()));
+            }
+            ExprType::GroupingExpr(expr) => self.compile_expression(expr),
+            ExprType::IndexExpr(left, index) => {
+                self.compile_expression(left);
+                self.compile_expression(index);
+                self.emit(Opcode::Index);
+            }
+            ExprType::Get { object, name } => {
+                self.compile_expression(object);
+                let index = self.name_constant(&name.0);
+                self.emit(Opcode::GetProperty(index));
+            }
+            ExprType::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.compile_expression(object);
+                self.compile_expression(value);
+                let index = self.name_constant(&name.0);
+                self.emit(Opcode::SetProperty(index));
+            }
+            ExprType::This => {
+                let symbol = self.resolve(&Ident("this".to_string()));
+                self.load_symbol(symbol);
+            }
+            ExprType::Super(method) => {
+                let this = self.resolve(&Ident("this".to_string()));
+                self.load_symbol(this);
+                let superclass = match &self.superclass {
+                    Some(superclass) => superclass.clone(),
+                    None => {
+                        let message = "Can't use 'super' in a class with no superclass.";
+                        self.error(INVALID_SUPER, expr.span, message);
+                        return;
                     }
-                    _ => unimplemented!("Callee not implemented: {:?}", callee),
                 };
+                let symbol = self.resolve(&superclass);
+                self.load_symbol(symbol);
+                let index = self.name_constant(&method.0);
+                self.emit(Opcode::GetSuper(index));
             }
             ExprType::If {
                 condition,
@@ -283,10 +510,10 @@ impl Compiler {
                 self.compile_expression(condition);
                 // If condition is false, jump to end of if-else
                 let jump_not_truthy = self.emit_return_position(Opcode::JumpIfFalse(0));
-                self.compile_statement(&Stmt::Block(then_branch.clone()));
+                self.compile_block(then_branch);
 
                 let mut endif = vec![];
-                let exist_else = else_branch.len() > 0;
+                let exist_else = !else_branch.is_empty();
 
                 if exist_else {
                     let pos = self.emit_return_position(Opcode::Jump(9999));
@@ -299,7 +526,7 @@ impl Compiler {
                 for (condition, block) in elseif.iter() {
                     self.compile_expression(condition);
                     let jump_not_truthy = self.emit_return_position(Opcode::JumpIfFalse(0));
-                    self.compile_statement(&Stmt::Block(block.clone()));
+                    self.compile_block(block);
 
                     if exist_else {
                         endif.push(self.instructions.len());
@@ -311,24 +538,233 @@ impl Compiler {
                 }
 
                 if exist_else {
-                    self.compile_statement(&Stmt::Block(else_branch.clone()));
+                    self.compile_block(else_branch);
                 }
 
                 for pos in endif.iter() {
-                    self.instructions[*pos] = Opcode::Jump(self.instructions.len() - 1);
+                    self.instructions[*pos] = Opcode::Jump(self.instructions.len());
                 }
             }
-            _ => unimplemented!("Expression not implemented: {:?}", expr),
+            ExprType::Function { .. } => self.unsupported(expr.span, "function expressions"),
+            _ => self.unsupported(expr.span, "this expression"),
+        }
+    }
+
+    // compile_assign stores value in a variable or an element of a hash.
+    fn compile_assign(&mut self, target: &ExprNode, value: &ExprNode) {
+        match &target.node {
+            ExprType::Ident(ident) => {
+                let symbol = self.resolve_name(target.span, ident);
+                self.compile_expression(value);
+                self.store_symbol(symbol);
+            }
+            ExprType::IndexExpr(left, index) => {
+                self.compile_expression(left);
+                self.compile_expression(index);
+                self.compile_expression(value);
+                self.emit(Opcode::SetIndex);
+            }
+            _ => self.error(
+                INVALID_ASSIGNMENT,
+                target.span,
+                "Invalid assignment target.",
+            ),
+        }
+    }
+
+    // compile_function compiles a function body on its own, appends it to the
+    // other function bodies and returns the constant index of the function.
+    fn compile_function(
+        &mut self,
+        name: &str,
+        params: &[Ident],
+        body: &[StmtNode],
+        kind: FunctionKind,
+    ) -> usize {
+        // a nested function calls itself through its frame, it can't see the
+        // local it is stored in
+        let nested = kind == FunctionKind::Function && self.symbols.borrow().outer.is_some();
+        self.enter_scope();
+        // break and continue can't reach loops outside the function
+        let loops = std::mem::take(&mut self.loops);
+        let enclosing = std::mem::replace(&mut self.function, kind);
+
+        // local 0 holds the callee, for methods that is the instance
+        let slot = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            _ => "",
+        };
+        self.symbols.borrow_mut().define(slot.to_string());
+        if nested {
+            self.symbols
+                .borrow_mut()
+                .define_function_name(name.to_string());
+        }
+        for param in params.iter() {
+            self.symbols.borrow_mut().define(param.0.clone());
+        }
+        self.compile_block(body);
+        let locals = self.symbols.borrow().names.clone();
+
+        // falling off the end of the body, or jumping past its last
+        // statement, returns nil, or the instance from init
+        if kind == FunctionKind::Initializer {
+            self.emit(Opcode::GetLocal(0));
+            self.emit(Opcode::ReturnValue);
+        } else {
+            self.emit(Opcode::Return);
+        }
+        let (instructions, lines) = self.leave_scope();
+        self.loops = loops;
+        self.function = enclosing;
+
+        // the body was compiled on its own, move its jumps to where it lands
+        // among the other functions
+        let start = self.closure_ins.len();
+        let instructions = relocate(instructions, start);
+        let len = instructions.len();
+        self.closure_ins.extend(instructions);
+        self.closure_lines.extend(&lines);
+
+        let function = FunctionInfo {
+            name: name.to_string(),
+            start,
+            len,
+            num_parameters: params.len(),
+            locals,
+        };
+        let index = self.constants.len();
+        self.constants.push(function.function());
+        self.functions.push(function);
+        index
+    }
+
+    // name_constant stores the name of a class, property or method.
+    fn name_constant(&mut self, name: &str) -> usize {
+        let index = self.constants.len();
+        self.constants.push(Object::String(name.to_string()));
+        index
+    }
+
+    // compile_loop compiles the three loop forms. Each one jumps back to
+    // its start after the body, and exits through a jump patched to the end.
+    fn compile_loop(&mut self, stmt: &StmtNode, label: Option<String>) {
+        match &stmt.node {
+            Stmt::For {
+                init,
+                conditions,
+                step,
+                block,
+            } => {
+                // the loop variable lives in a scope around the whole loop
+                let store = self.begin_block();
+                self.compile_statement(init);
+                let start = self.instructions.len();
+                self.compile_expression(conditions);
+                let exit = self.emit_return_position(Opcode::JumpIfFalse(0));
+                let jumps = self.compile_loop_body(block, label);
+                let step_start = self.instructions.len();
+                self.compile_statement(step);
+                self.emit(Opcode::Jump(start));
+                self.patch_loop(exit, jumps, step_start);
+                self.end_block(store);
+            }
+            Stmt::While(condition, block) => {
+                let start = self.instructions.len();
+                self.compile_expression(condition);
+                let exit = self.emit_return_position(Opcode::JumpIfFalse(0));
+                let jumps = self.compile_loop_body(block, label);
+                self.emit(Opcode::Jump(start));
+                self.patch_loop(exit, jumps, start);
+            }
+            Stmt::ForIn { var, iter, block } => {
+                let ident = match &var.node {
+                    Stmt::Var(ident, _) => ident,
+                    _ => {
+                        self.error(UNSUPPORTED_SYNTAX, var.span, "Expect variable in for loop.");
+                        return;
+                    }
+                };
+                // the iterator lives in a variable no Lox code can name, one
+                // per nesting level
+                self.compile_expression(iter);
+                let store = self.begin_block();
+                self.emit(Opcode::Iterator);
+                let iterator = self.define(&format!("<iterator {}>", self.loops.len()));
+                self.store_symbol(iterator.clone());
+
+                let start = self.instructions.len();
+                self.load_symbol(iterator.clone());
+                let exit = self.emit_return_position(Opcode::IterNext(0));
+                let symbol = self.define(&ident.0);
+                self.store_symbol(symbol);
+                self.store_symbol(iterator);
+                let jumps = self.compile_loop_body(block, label);
+                self.emit(Opcode::Jump(start));
+                self.patch_loop(exit, jumps, start);
+                self.end_block(store);
+            }
+            _ => self.error(UNSUPPORTED_SYNTAX, stmt.span, "Expect loop after label."),
         }
     }
 
+    // compile_loop_body compiles the body of a loop and returns the break and
+    // continue jumps in it.
+    fn compile_loop_body(&mut self, block: &[StmtNode], label: Option<String>) -> LoopJumps {
+        self.loops.push(LoopJumps {
+            label,
+            breaks: vec![],
+            continues: vec![],
+        });
+        self.compile_block(block);
+        self.loops.pop().unwrap()
+    }
+
+    // patch_loop points the exit jump of a loop and its breaks past the loop,
+    // and its continues at next, where the next iteration starts.
+    fn patch_loop(&mut self, exit: usize, jumps: LoopJumps, next: usize) {
+        let end = self.instructions.len();
+        self.instructions[exit] = match self.instructions[exit] {
+            Opcode::IterNext(_) => Opcode::IterNext(end),
+            _ => Opcode::JumpIfFalse(end),
+        };
+        for pos in jumps.continues {
+            self.instructions[pos] = Opcode::Jump(next);
+        }
+        for pos in jumps.breaks {
+            self.instructions[pos] = Opcode::Jump(end);
+        }
+    }
+
+    // enclosing_loop finds the loop a break or continue jumps out of: the
+    // innermost one, or the one with the given label.
+    fn enclosing_loop(&mut self, label: &Option<Ident>, keyword: &str) -> Option<&mut LoopJumps> {
+        let found = self.loops.iter().rposition(|jumps| match label {
+            Some(label) => jumps.label.as_deref() == Some(label.0.as_str()),
+            None => true,
+        });
+        if let Some(index) = found {
+            return Some(&mut self.loops[index]);
+        }
+        let message = match label {
+            Some(label) => format!(
+                "Can't {} to '{}': no enclosing loop has that label.",
+                keyword, label
+            ),
+            None => format!("Can't use '{}' outside of a loop.", keyword),
+        };
+        self.error(INVALID_JUMP, self.span, &message);
+        None
+    }
+
     pub fn emit(&mut self, op: Opcode) {
         self.instructions.push(op);
+        self.lines.push(self.span.line, self.span.column);
     }
 
     pub fn emit_return_position(&mut self, op: Opcode) -> usize {
         let pos = self.instructions.len();
-        self.instructions.push(op);
+        self.emit(op);
         pos
     }
 
@@ -343,45 +779,155 @@ impl Compiler {
     fn enter_scope(&mut self) {
         let symbols = SymbolTable::new_enclosed(self.symbols.borrow().clone());
         self.symbols = Rc::new(RefCell::new(symbols));
-        self.pre_instructions = self.instructions.clone();
-        self.instructions = vec![];
+        let instructions = std::mem::take(&mut self.instructions);
+        let lines = std::mem::take(&mut self.lines);
+        self.pre_instructions.push((instructions, lines));
     }
 
-    fn leave_scope(&mut self) -> Vec<Opcode> {
+    fn leave_scope(&mut self) -> (Vec<Opcode>, LineTable) {
         let symbols = self.symbols.borrow().outer.clone().unwrap();
         self.symbols = Rc::new(RefCell::new(*symbols));
-        let instructions = self.instructions.clone();
-        self.instructions = self.pre_instructions.clone();
-        self.pre_instructions = vec![];
-        instructions
+        let (instructions, lines) = self.pre_instructions.pop().unwrap();
+        (
+            std::mem::replace(&mut self.instructions, instructions),
+            std::mem::replace(&mut self.lines, lines),
+        )
+    }
+
+    // begin_block opens a block scope and returns the names visible before it.
+    fn begin_block(&mut self) -> HashMap<String, Symbol> {
+        self.depth += 1;
+        self.symbols.borrow().store.clone()
+    }
+
+    // end_block closes a block scope. The slots of its variables are not
+    // handed out again, so functions declared in it keep theirs.
+    fn end_block(&mut self, store: HashMap<String, Symbol>) {
+        self.depth -= 1;
+        self.symbols.borrow_mut().store = store;
+    }
+
+    // define declares name in the current scope. A global keeps its slot when
+    // it is declared again, code compiled before the declaration refers to it.
+    // Inside a block a declaration always gets a new slot.
+    fn define(&mut self, name: &str) -> Symbol {
+        let mut symbols = self.symbols.borrow_mut();
+        if symbols.outer.is_none() && self.depth == 0 {
+            if let Some(symbol) = symbols.store.get(name) {
+                return symbol.clone();
+            }
+        }
+        symbols.define(name.to_string())
+    }
+
+    // resolve looks name up in the enclosing scopes, then in the builtins. An
+    // undefined name is reported and then declared, so it is reported once.
+    fn resolve(&mut self, ident: &Ident) -> Symbol {
+        let symbol = self.symbols.borrow_mut().resolve(ident.0.as_str());
+        match symbol {
+            Some(symbol) => symbol,
+            None => self.resolve_builtin(ident),
+        }
+    }
+
+    // resolve_name looks up a name the resolver saw at span. A name it found
+    // no local declaration for is a global or a builtin, as in the evaluator,
+    // whatever locals of that name the enclosing functions have.
+    fn resolve_name(&mut self, span: Span, ident: &Ident) -> Symbol {
+        if self.locals.contains_key(&span) {
+            return self.resolve(ident);
+        }
+        let symbol = self.symbols.borrow().global(ident.0.as_str());
+        match symbol {
+            Some(symbol) => symbol,
+            None => self.resolve_builtin(ident),
+        }
+    }
+
+    fn resolve_builtin(&mut self, ident: &Ident) -> Symbol {
+        match self.builtins.get_index(ident.0.as_str()) {
+            Some(index) => Symbol::new(ident.0.clone(), Scope::Builtin, index),
+            None => {
+                let message = format!("Undefined variable '{}'.", ident);
+                self.error(UNDEFINED_VARIABLE, self.span, &message);
+                self.symbols.borrow_mut().define(ident.0.clone())
+            }
+        }
     }
 
     fn load_symbol(&mut self, s: Symbol) {
         match s.scope {
             Scope::Global => self.emit(Opcode::GetGlobal(s.index)),
             Scope::Local => self.emit(Opcode::GetLocal(s.index)),
-            Scope::Free => self.emit(Opcode::GetFree(s.index)),
             Scope::Builtin => self.emit(Opcode::GetBuiltin(s.index)),
             Scope::Function => self.emit(Opcode::CurrentClosure),
+            Scope::Free => self.capture_error(&s),
+        }
+    }
+
+    // capture_error reports a function using a local of an enclosing one, the
+    // vm has no closures to keep it in. Each variable is reported once.
+    fn capture_error(&mut self, s: &Symbol) {
+        let message = format!(
+            "Can't capture local variable '{}' in a compiled function.",
+            s.name
+        );
+        if !self.diagnostics.iter().any(|d| d.message == message) {
+            self.error(UNSUPPORTED_CAPTURE, self.span, &message);
         }
     }
 
-    // #[warn(dead_code)]
-    // fn is_tail_call(&self, stmt: &Stmt) -> bool {
-    //     match stmt {
-    //         Stmt::Return(_) => true,
-    //         Stmt::Expr(expr) => match expr {
-    //             ExprType::Call { .. } => true,
-    //             _ => false,
-    //         },
-    //         _ => false,
-    //     }
-    // }
+    fn store_symbol(&mut self, s: Symbol) {
+        match s.scope {
+            Scope::Global => self.emit(Opcode::SetGlobal(s.index)),
+            Scope::Local => self.emit(Opcode::SetLocal(s.index)),
+            Scope::Free => self.capture_error(&s),
+            _ => {
+                let message = format!("Can't assign to '{}'.", s.name);
+                self.error(INVALID_ASSIGNMENT, self.span, &message);
+            }
+        }
+    }
+}
+
+// leaves_value reports whether expr leaves a value on the stack. Assignments,
+// prints and ifs compile to code that consumes everything it pushes.
+fn leaves_value(expr: &ExprNode) -> bool {
+    !matches!(
+        expr.node,
+        ExprType::InfixExpr(
+            _,
+            Token::Equal
+                | Token::PlusSelf
+                | Token::MinusSelf
+                | Token::StarSelf
+                | Token::SlashSelf
+                | Token::ModSelf,
+            _
+        ) | ExprType::Set { .. }
+            | ExprType::PrintExpr(_)
+            | ExprType::If { .. }
+    )
+}
+
+// relocate moves the jump targets of code compiled on its own to where the
+// code starts in the program.
+fn relocate(mut instructions: Vec<Opcode>, start: usize) -> Vec<Opcode> {
+    for instruction in instructions.iter_mut() {
+        match instruction {
+            Opcode::Jump(pos)
+            | Opcode::JumpIfFalse(pos)
+            | Opcode::JumpNotTruthy(pos)
+            | Opcode::IterNext(pos) => *pos += start,
+            _ => {}
+        }
+    }
+    instructions
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{lexer::Lexing, parser::Parser};
+    use crate::{lexer::Lexing, parser::Parser, resolver::Resolver};
 
     use super::*;
 
@@ -490,20 +1036,42 @@ mod tests {
     #[test]
     fn test_assert() {
         let ins = test_compiler_code("assert 1 > 2, '1 is not greater than 2';");
-        println!("{:?}", ins);
         let except = vec![
             Opcode::LoadConstant(0), // 1
             Opcode::LoadConstant(1), // 2
             Opcode::GreaterThan,     // 3
-            Opcode::Assert(7),       // 4
-            Opcode::LoadConstant(2), // 5
-            Opcode::Print(1),        // 6
-            Opcode::Exit(3),         // 7
+            Opcode::Assert(2),       // 4 "Assertion failed: ..."
         ];
         assert_eq!(ins.len(), except.len());
         assert_eq!(ins, except);
     }
 
+    #[test]
+    fn test_assert_message() {
+        let program = Parser::new(Lexing::new("assert 1 > 2, 'oops';")).parse();
+        let locals = Resolver::new().resolve(&program);
+        let mut compiler = Compiler::new(program, locals);
+        compiler.compile();
+        assert_eq!(
+            compiler.constants[2],
+            Object::String("Assertion failed: (> 1.0 2.0) oops".to_string())
+        );
+    }
+
+    #[test]
+    fn test_lines() {
+        let program = Parser::new(Lexing::new("var a = 1;\nprint a +\n  2;")).parse();
+        let locals = Resolver::new().resolve(&program);
+        let mut compiler = Compiler::new(program, locals);
+        compiler.compile();
+        let lines = compiler.get_lines();
+        // the operands keep their own lines, the add is on the line of the print
+        assert_eq!(lines.line(2), Some(2));
+        assert_eq!(lines.line(3), Some(3));
+        assert_eq!(lines.line(4), Some(2));
+        assert_eq!(lines.position(5), Some((2, 1)));
+    }
+
     #[test]
     fn test_assert_with_var() {
         let ins = test_compiler_code("var a = 1; assert a > 2, '1 is not greater than 2';");
@@ -513,20 +1081,260 @@ mod tests {
             Opcode::GetGlobal(0),    // 1
             Opcode::LoadConstant(1), // 2
             Opcode::GreaterThan,     // 3
-            Opcode::Assert(9),       // 4
-            Opcode::LoadConstant(2), // 5
-            Opcode::Print(1),        // 6
-            Opcode::Exit(3),         // 7
+            Opcode::Assert(2),       // 4
         ];
         assert_eq!(ins.len(), except.len());
         assert_eq!(ins, except);
     }
 
+    #[test]
+    fn test_for_break_continue() {
+        let ins = test_compiler_code(
+            "for (var i = 0; i < 3; i += 1) { if (i == 1) { continue; } break; }",
+        );
+        let except = vec![
+            Opcode::LoadConstant(0), // 0 var i = 0
+            Opcode::SetGlobal(0),    // 1
+            Opcode::GetGlobal(0),    // 2 i < 3
+            Opcode::LoadConstant(1), // 3
+            Opcode::LessThan,        // 4
+            Opcode::JumpIfFalse(17), // 5
+            Opcode::GetGlobal(0),    // 6 i == 1
+            Opcode::LoadConstant(2), // 7
+            Opcode::EqualEqual,      // 8
+            Opcode::JumpIfFalse(11), // 9
+            Opcode::Jump(12),        // 10 continue
+            Opcode::Jump(17),        // 11 break
+            Opcode::GetGlobal(0),    // 12 i += 1
+            Opcode::LoadConstant(3), // 13
+            Opcode::Add,             // 14
+            Opcode::SetGlobal(0),    // 15
+            Opcode::Jump(2),         // 16
+        ];
+        assert_eq!(ins, except);
+    }
+
+    #[test]
+    fn test_while() {
+        let ins = test_compiler_code("var i = 0; while (i < 3) { i += 1; }");
+        let except = vec![
+            Opcode::LoadConstant(0), // 0 var i = 0
+            Opcode::SetGlobal(0),    // 1
+            Opcode::GetGlobal(0),    // 2 i < 3
+            Opcode::LoadConstant(1), // 3
+            Opcode::LessThan,        // 4
+            Opcode::JumpIfFalse(11), // 5
+            Opcode::GetGlobal(0),    // 6 i += 1
+            Opcode::LoadConstant(2), // 7
+            Opcode::Add,             // 8
+            Opcode::SetGlobal(0),    // 9
+            Opcode::Jump(2),         // 10
+        ];
+        assert_eq!(ins, except);
+    }
+
+    #[test]
+    fn test_for_in() {
+        let ins = test_compiler_code("var a = 1; for (var k in a) { k; }");
+        let except = vec![
+            Opcode::LoadConstant(0), // 0 var a = 1
+            Opcode::SetGlobal(0),    // 1
+            Opcode::GetGlobal(0),    // 2 the iterator of a
+            Opcode::Iterator,        // 3
+            Opcode::SetGlobal(1),    // 4
+            Opcode::GetGlobal(1),    // 5 the next k
+            Opcode::IterNext(12),    // 6
+            Opcode::SetGlobal(2),    // 7
+            Opcode::SetGlobal(1),    // 8
+            Opcode::GetGlobal(2),    // 9 k;
+            Opcode::Pop,             // 10
+            Opcode::Jump(5),         // 11
+        ];
+        assert_eq!(ins, except);
+    }
+
+    #[test]
+    fn test_array_hash() {
+        let ins = test_compiler_code("var a = [1, 2]; a[0] = {'k': a[1]};");
+        let except = vec![
+            Opcode::LoadConstant(0), // 0 [1, 2]
+            Opcode::LoadConstant(1), // 1
+            Opcode::Array(2),        // 2
+            Opcode::SetGlobal(0),    // 3
+            Opcode::GetGlobal(0),    // 4 a[0] =
+            Opcode::LoadConstant(2), // 5
+            Opcode::LoadConstant(3), // 6 {'k': a[1]}
+            Opcode::GetGlobal(0),    // 7
+            Opcode::LoadConstant(4), // 8
+            Opcode::Index,           // 9
+            Opcode::Hash(1),         // 10
+            Opcode::SetIndex,        // 11
+        ];
+        assert_eq!(ins, except);
+    }
+
+    #[test]
+    fn test_logical() {
+        let ins = test_compiler_code("var a = nil; a and 1; a or 2;");
+        let except = vec![
+            Opcode::LoadConstant(0),   // 0 nil
+            Opcode::SetGlobal(0),      // 1
+            Opcode::GetGlobal(0),      // 2 a and 1
+            Opcode::JumpNotTruthy(6),  // 3
+            Opcode::Pop,               // 4
+            Opcode::LoadConstant(1),   // 5
+            Opcode::Pop,               // 6
+            Opcode::GetGlobal(0),      // 7 a or 2
+            Opcode::JumpNotTruthy(10), // 8
+            Opcode::Jump(12),          // 9
+            Opcode::Pop,               // 10
+            Opcode::LoadConstant(2),   // 11
+        ];
+        assert_eq!(ins, except);
+    }
+
+    #[test]
+    fn test_labeled_break() {
+        let ins = test_compiler_code(
+            "outer: for (var i = 0; i < 2; i += 1) { for (var j = 0; j < 2; j += 1) { break outer; } }",
+        );
+        // the inner break jumps past the outer loop, not the inner one
+        let end = ins.len();
+        assert!(ins.contains(&Opcode::Jump(end)));
+        assert_eq!(ins[end - 1], Opcode::Jump(2));
+    }
+
+    #[test]
+    fn test_function() {
+        let compiler = compile_code("fun add(a, b) { var c = a + b; return c; } add(1, 2);");
+        assert_eq!(
+            compiler.closure_ins,
+            vec![
+                Opcode::GetLocal(1),
+                Opcode::GetLocal(2),
+                Opcode::Add,
+                Opcode::SetLocal(3),
+                Opcode::GetLocal(3),
+                Opcode::ReturnValue,
+                Opcode::Return,
+            ]
+        );
+        assert_eq!(
+            compiler.constants[0],
+            Object::CompiledFunction {
+                start: 0,
+                len: 7,
+                num_locals: 4,
+                num_parameters: 2,
+            }
+        );
+        assert_eq!(
+            compiler.instructions,
+            vec![
+                Opcode::LoadConstant(0),
+                Opcode::SetGlobal(0),
+                Opcode::GetGlobal(0),
+                Opcode::LoadConstant(1),
+                Opcode::LoadConstant(2),
+                Opcode::Call(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_class() {
+        let compiler = compile_code(
+            "class B {} class A < B { fun get() { return this.x; } } var a = new A(); a.get();",
+        );
+        assert_eq!(
+            compiler.closure_ins,
+            vec![
+                Opcode::GetLocal(0),
+                Opcode::GetProperty(2),
+                Opcode::ReturnValue,
+                Opcode::Return,
+            ]
+        );
+        assert_eq!(
+            compiler.instructions,
+            vec![
+                Opcode::Class(0),
+                Opcode::SetGlobal(0),
+                Opcode::Class(1),
+                Opcode::GetGlobal(0),
+                Opcode::Inherit,
+                Opcode::LoadConstant(3),
+                Opcode::Method(4),
+                Opcode::SetGlobal(1),
+                Opcode::GetGlobal(1),
+                Opcode::Call(0),
+                Opcode::SetGlobal(2),
+                Opcode::GetGlobal(2),
+                Opcode::Invoke(5, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_capture_error() {
+        let compiler = compile_code("fun f() { var n = 0; fun g() { n = n + 1; return n; } }");
+        let messages: Vec<&str> = compiler
+            .diagnostics()
+            .iter()
+            .map(|d| d.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            vec!["Can't capture local variable 'n' in a compiled function."]
+        );
+        assert!(!compile_code("var n = 0; fun g() { return n; }").has_errors());
+    }
+
+    #[test]
+    fn test_resolved_globals() {
+        // n is declared in f only after g, so g reads the global, as the
+        // resolver decided
+        let compiler =
+            compile_code("var n = 0; fun f() { fun g() { return n; } var n = 1; return g(); }");
+        assert!(!compiler.has_errors());
+        assert!(compiler.closure_ins.contains(&Opcode::GetGlobal(0)));
+    }
+
+    #[test]
+    fn test_errors() {
+        let compiler = compile_code("break; print x; len = 1; switch (1) { default: print 1; }");
+        let messages: Vec<&str> = compiler
+            .diagnostics()
+            .iter()
+            .map(|d| d.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Can't use 'break' outside of a loop.",
+                "Undefined variable 'x'.",
+                "Can't assign to 'len'.",
+                "The compiler does not support switch statements.",
+            ]
+        );
+    }
+
+    fn compile_code(code: &str) -> Compiler {
+        let lexer = Lexing::new(code);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse();
+        let locals = Resolver::new().resolve(&program);
+        let mut compiler = Compiler::new(program, locals);
+        compiler.compile();
+        compiler
+    }
+
     fn test_compiler_code(code: &str) -> Vec<Opcode> {
         let lexer = Lexing::new(code);
         let mut parser = Parser::new(lexer);
         let program = parser.parse();
-        let mut compiler = Compiler::new(program);
+        let locals = Resolver::new().resolve(&program);
+        let mut compiler = Compiler::new(program, locals);
         compiler.compile();
         compiler.instructions
     }
@@ -0,0 +1,248 @@
+use std::fmt::Display;
+
+use crate::span::{SourceMap, Span};
+
+// Diagnostic codes. Lexer errors are E00xx, parser errors E01xx, import
+// errors E02xx, runtime errors E03xx, resolver errors E04xx and compiler
+// errors E05xx.
+pub const UNEXPECTED_CHARACTER: &str = "E0001";
+pub const UNTERMINATED_STRING: &str = "E0002";
+pub const UNKNOWN_ESCAPE: &str = "E0003";
+pub const UNKNOWN_KEYWORD: &str = "E0004";
+pub const SYNTAX_ERROR: &str = "E0100";
+pub const IMPORT_NOT_FOUND: &str = "E0200";
+pub const IMPORT_FAILED: &str = "E0201";
+pub const RUNTIME_ERROR: &str = "E0300";
+pub const UNDEFINED_VARIABLE: &str = "E0400";
+pub const USE_BEFORE_DEFINE: &str = "E0401";
+pub const REDECLARED_VARIABLE: &str = "E0402";
+pub const INVALID_RETURN: &str = "E0403";
+pub const INVALID_THIS: &str = "E0404";
+pub const INVALID_SUPER: &str = "E0405";
+pub const INVALID_JUMP: &str = "E0406";
+pub const UNSUPPORTED_CAPTURE: &str = "E0500";
+pub const UNSUPPORTED_SYNTAX: &str = "E0501";
+pub const INVALID_ASSIGNMENT: &str = "E0502";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+// Label points at a span with an optional explanation printed under the carets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Label {
+    pub fn new(span: Span, message: &str) -> Self {
+        Label {
+            span,
+            message: message.to_string(),
+        }
+    }
+}
+
+// Diagnostic is a message about the source, reported by the lexer, parser,
+// import loader or evaluator. It is plain data; render turns it into text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub primary: Label,
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &'static str, message: &str, span: Span) -> Self {
+        Diagnostic {
+            severity,
+            code,
+            message: message.to_string(),
+            primary: Label::new(span, ""),
+            secondary: vec![],
+            notes: vec![],
+        }
+    }
+
+    pub fn error(code: &'static str, message: &str, span: Span) -> Self {
+        Diagnostic::new(Severity::Error, code, message, span)
+    }
+
+    pub fn warning(code: &'static str, message: &str, span: Span) -> Self {
+        Diagnostic::new(Severity::Warning, code, message, span)
+    }
+
+    pub fn with_label(mut self, message: &str) -> Self {
+        self.primary.message = message.to_string();
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, message: &str) -> Self {
+        self.secondary.push(Label::new(span, message));
+        self
+    }
+
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    pub fn span(&self) -> Span {
+        self.primary.span
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    // render formats the diagnostic rustc-style, quoting the source lines
+    // that the labels point at:
+    //
+    //   error[E0002]: Unterminated string.
+    //    --> main.lox:1:9
+    //     |
+    //   1 | var a = "abc
+    //     |         ^^^^ string starts here
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let mut out = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+        let mut labels = vec![(&self.primary, '^')];
+        labels.extend(self.secondary.iter().map(|label| (label, '-')));
+        let width = labels
+            .iter()
+            .map(|(label, _)| label.span.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let pad = " ".repeat(width);
+
+        let span = self.primary.span;
+        match sources.get(span.file) {
+            Some(file) => out.push_str(&format!(
+                "{}--> {}:{}:{}\n",
+                pad, file.name, span.line, span.column
+            )),
+            None => out.push_str(&format!("{}--> line {}:{}\n", pad, span.line, span.column)),
+        }
+
+        for (label, marker) in labels {
+            // without the source a label is only where it points
+            let line = match sources.line(label.span.file, label.span.line) {
+                Some(line) => line,
+                None if label.message.is_empty() => continue,
+                None => {
+                    out.push_str(&format!(
+                        "{} = {} at line {}:{}\n",
+                        pad, label.message, label.span.line, label.span.column
+                    ));
+                    continue;
+                }
+            };
+            let column = label.span.column.max(1) - 1;
+            // spans running past the end of the line are cut off there
+            let available = line.chars().count().saturating_sub(column).max(1);
+            let carets = label.span.len.clamp(1, available);
+            out.push_str(&format!("{} |\n", pad));
+            out.push_str(&format!("{:>width$} | {}\n", label.span.line, line, width = width));
+            out.push_str(&format!(
+                "{} | {}{}",
+                pad,
+                " ".repeat(column),
+                marker.to_string().repeat(carets)
+            ));
+            if !label.message.is_empty() {
+                out.push_str(&format!(" {}", label.message));
+            }
+            out.push('\n');
+        }
+
+        for note in &self.notes {
+            out.push_str(&format!("{} = note: {}\n", pad, note));
+        }
+        out
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[line {}] {}: {}",
+            self.primary.span.line,
+            match self.severity {
+                Severity::Error => "Error",
+                Severity::Warning => "Warning",
+                Severity::Note => "Note",
+            },
+            self.message
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::span::FileId;
+
+    #[test]
+    fn test_render() {
+        let mut sources = SourceMap::new();
+        let file = sources.add("main.lox", "var a = 1;\nvar b = \"abc\n");
+        let diagnostic = Diagnostic::error(
+            UNTERMINATED_STRING,
+            "Unterminated string.",
+            Span::new(file, 19, 4, 2, 9),
+        )
+        .with_label("string starts here")
+        .with_secondary(Span::new(file, 4, 1, 1, 5), "previous declaration")
+        .with_note("strings must end with a quote");
+        assert_eq!(
+            diagnostic.render(&sources),
+            [
+                "error[E0002]: Unterminated string.",
+                " --> main.lox:2:9",
+                "  |",
+                "2 | var b = \"abc",
+                "  |         ^^^^ string starts here",
+                "  |",
+                "1 | var a = 1;",
+                "  |     - previous declaration",
+                "  = note: strings must end with a quote",
+                "",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            diagnostic.to_string(),
+            "[line 2] Error: Unterminated string."
+        );
+        assert!(sources.get(FileId(1)).is_none());
+    }
+
+    #[test]
+    fn test_render_without_source() {
+        let span = Span::new(FileId(0), 0, 1, 2, 3);
+        let call = Span::new(FileId(0), 0, 1, 5, 1);
+        let diagnostic =
+            Diagnostic::error(RUNTIME_ERROR, "Boom.", span).with_secondary(call, "in f()");
+        assert_eq!(
+            diagnostic.render(&SourceMap::new()),
+            "error[E0300]: Boom.\n --> line 2:3\n  = in f() at line 5:1\n"
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::{
+    builtins::Builtins, bytecode::Bytecode, compiler::FunctionInfo, objects::Object,
+    opcode::Opcode, symbol::SymbolTable,
+};
+
+// disassemble renders a compiled program one function at a time, the script
+// last. Each instruction shows its address, source line, operands and what
+// they refer to: constants, globals, locals and builtins by name, and jump
+// targets as labels.
+//
+//   == add(a, b) ==
+//   0000     2  GetLocal         1        a
+//   0001     |  GetLocal         2        b
+//   0002     |  Add
+//   0003     |  ReturnValue
+pub fn disassemble(bytecode: &Bytecode, globals: &SymbolTable) -> String {
+    let disassembler = Disassembler {
+        bytecode,
+        globals: &globals.names,
+        builtins: Builtins::new(),
+    };
+    let mut functions: Vec<&FunctionInfo> = bytecode.functions.iter().collect();
+    functions.sort_by_key(|function| function.start);
+
+    let mut out = String::new();
+    for function in functions {
+        disassembler.function(&mut out, function);
+        out.push('\n');
+    }
+    let script = FunctionInfo {
+        name: "script".to_string(),
+        start: bytecode.main_start,
+        len: bytecode.instructions.len() - bytecode.main_start,
+        num_parameters: 0,
+        locals: vec![],
+    };
+    disassembler.function(&mut out, &script);
+    out
+}
+
+struct Disassembler<'a> {
+    bytecode: &'a Bytecode,
+    globals: &'a [String],
+    builtins: Builtins,
+}
+
+impl Disassembler<'_> {
+    fn function(&self, out: &mut String, function: &FunctionInfo) {
+        let params = function
+            .locals
+            .iter()
+            .skip(1)
+            .take(function.num_parameters)
+            .cloned()
+            .collect::<Vec<String>>();
+        writeln!(out, "== {}({}) ==", function.name, params.join(", ")).unwrap();
+
+        let end = function.start + function.len;
+        let mut targets: Vec<usize> = self.bytecode.instructions[function.start..end]
+            .iter()
+            .filter_map(jump_target)
+            .collect();
+        targets.sort();
+        targets.dedup();
+        let labels: HashMap<usize, String> = targets
+            .iter()
+            .enumerate()
+            .map(|(i, target)| (*target, format!("L{}", i)))
+            .collect();
+
+        let mut last_line = None;
+        for ip in function.start..end {
+            if let Some(label) = labels.get(&ip) {
+                writeln!(out, "{}:", label).unwrap();
+            }
+            let line = self.bytecode.lines.as_ref().and_then(|lines| lines.line(ip));
+            let line = match line {
+                Some(line) if last_line == Some(line) => "|".to_string(),
+                Some(line) => line.to_string(),
+                None => String::new(),
+            };
+            last_line = self.bytecode.lines.as_ref().and_then(|lines| lines.line(ip));
+
+            let instruction = &self.bytecode.instructions[ip];
+            let (name, operands, comment) = self.instruction(instruction, function, &labels);
+            let text = format!("{:04}  {:>4}  {:<16} {:<8} {}", ip, line, name, operands, comment);
+            writeln!(out, "{}", text.trim_end()).unwrap();
+        }
+        // a loop at the very end jumps past the last instruction
+        if let Some(label) = labels.get(&end) {
+            writeln!(out, "{}:", label).unwrap();
+        }
+    }
+
+    // instruction splits an instruction into its name, operands and a note
+    // on what the operands refer to.
+    fn instruction(
+        &self,
+        instruction: &Opcode,
+        function: &FunctionInfo,
+        labels: &HashMap<usize, String>,
+    ) -> (String, String, String) {
+        let debug = format!("{:?}", instruction);
+        let (name, operands) = match debug.split_once('(') {
+            Some((name, operands)) => (name.to_string(), operands.trim_end_matches(')').to_string()),
+            None => (debug, String::new()),
+        };
+        if let Some(target) = jump_target(instruction) {
+            return (name, labels[&target].clone(), String::new());
+        }
+        let comment = match instruction {
+            Opcode::LoadConstant(index) => self.constant(*index),
+            Opcode::Class(index)
+            | Opcode::Assert(index)
+            | Opcode::Method(index)
+            | Opcode::GetProperty(index)
+            | Opcode::SetProperty(index)
+            | Opcode::GetSuper(index)
+            | Opcode::Invoke(index, _) => self.constant(*index),
+            Opcode::GetGlobal(index) | Opcode::SetGlobal(index) => {
+                self.globals.get(*index).cloned().unwrap_or_default()
+            }
+            Opcode::GetLocal(index) | Opcode::SetLocal(index) => {
+                match function.locals.get(*index).map(String::as_str) {
+                    Some("") if *index == 0 => "<callee>".to_string(),
+                    Some(name) => name.to_string(),
+                    None => String::new(),
+                }
+            }
+            Opcode::GetBuiltin(index) => self.builtins.get_name(*index).unwrap_or_default(),
+            Opcode::GetFree(index) => format!("free {}", index),
+            _ => String::new(),
+        };
+        (name, operands, comment)
+    }
+
+    fn constant(&self, index: usize) -> String {
+        match self.bytecode.constants.get(index) {
+            Some(Object::String(s)) => format!("{:?}", s),
+            Some(Object::CompiledFunction { start, .. }) => {
+                match self.bytecode.functions.iter().find(|f| f.start == *start) {
+                    Some(function) => format!("<fn {}>", function.name),
+                    None => "<fn>".to_string(),
+                }
+            }
+            Some(constant) => constant.to_string(),
+            None => "<invalid constant>".to_string(),
+        }
+    }
+}
+
+// jump_target is where an instruction may jump to.
+fn jump_target(instruction: &Opcode) -> Option<usize> {
+    match instruction {
+        Opcode::Jump(pos)
+        | Opcode::JumpIfFalse(pos)
+        | Opcode::JumpNotTruthy(pos)
+        | Opcode::IterNext(pos) => Some(*pos),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler::Compiler, lexer::Lexing, parser::Parser, resolver::Resolver};
+
+    fn disassemble_code(code: &str) -> String {
+        let program = Parser::new(Lexing::new(code)).parse();
+        let locals = Resolver::new().resolve(&program);
+        let mut compiler = Compiler::new(program, locals);
+        compiler.compile();
+        let globals = compiler.symbols.borrow();
+        disassemble(&Bytecode::new(&compiler), &globals)
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let out = disassemble_code(
+            "fun add(a, b) {\n  return a + b;\n}\nvar i = 0;\nwhile (i < 3) {\n  i = add(i, 1);\n}\nprint len(\"x\");",
+        );
+        let expect = "\
+== add(a, b) ==
+0000     2  GetLocal         1        a
+0001     |  GetLocal         2        b
+0002     |  Add
+0003     |  ReturnValue
+0004     1  Return
+
+== script() ==
+0005     1  LoadConstant     0        <fn add>
+0006     |  SetGlobal        0        add
+0007     4  LoadConstant     1        0
+0008     |  SetGlobal        1        i
+L0:
+0009     5  GetGlobal        1        i
+0010     |  LoadConstant     2        3
+0011     |  LessThan
+0012     |  JumpIfFalse      L1
+0013     6  GetGlobal        0        add
+0014     |  GetGlobal        1        i
+0015     |  LoadConstant     3        1
+0016     |  Call             2
+0017     |  SetGlobal        1        i
+0018     5  Jump             L0
+L1:
+0019     8  GetBuiltin       4        len
+0020     |  LoadConstant     4        \"x\"
+0021     |  Call             1
+0022     |  Print            1
+";
+        assert_eq!(out, expect);
+
+        // variables declared in blocks keep their names after the block
+        let out = disassemble_code(
+            "fun add(a, b) {\n  var c = a + b;\n  return c;\n}\n{\n  var d = add(1, 2);\n  print d;\n}",
+        );
+        let expect = "\
+== add(a, b) ==
+0000     2  GetLocal         1        a
+0001     |  GetLocal         2        b
+0002     |  Add
+0003     |  SetLocal         3        c
+0004     3  GetLocal         3        c
+0005     |  ReturnValue
+0006     1  Return
+
+== script() ==
+0007     1  LoadConstant     0        <fn add>
+0008     |  SetGlobal        0        add
+0009     6  GetGlobal        0        add
+0010     |  LoadConstant     1        1
+0011     |  LoadConstant     2        2
+0012     |  Call             2
+0013     |  SetGlobal        1        d
+0014     7  GetGlobal        1        d
+0015     |  Print            1
+";
+        assert_eq!(out, expect);
+    }
+}
@@ -1,5 +1,6 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt::Debug;
 use std::rc::Rc;
 
 use crate::objects::Object;
@@ -11,6 +12,12 @@ pub struct Env {
     current_class: Option<Object>
 }
 
+impl Default for Env {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Env {
     pub fn new() -> Self {
         Env {
@@ -22,18 +29,14 @@ impl Env {
 
     pub fn from(store: HashMap<String, Object>) -> Self {
         Env {
-            store: store, 
+            store,
             outer: None,
             current_class: None,
         }
     }
 
     pub fn new_with_outer(outer: Rc<RefCell<Env>>) -> Self {
-        let outer_envs = outer.borrow().clone();
-        let current_class = match outer_envs.current_class {
-            Some(class_obj) => Some(class_obj.clone()),
-            None => None,
-        };
+        let current_class = outer.borrow().current_class.clone();
         Env {
             store: HashMap::new(),
             outer: Some(outer),
@@ -51,16 +54,39 @@ impl Env {
         }
     }
 
+    // get_at reads name from the scope depth levels out, as worked out by the
+    // resolver. It does not look any further out than that.
+    pub fn get_at(&self, depth: usize, name: &str) -> Option<Object> {
+        if depth == 0 {
+            return self.store.get(name).cloned();
+        }
+        self.outer.as_ref()?.borrow().get_at(depth - 1, name)
+    }
+
+    // assign_at stores value to name in the scope depth levels out. It returns
+    // false when that scope does not hold name.
+    pub fn assign_at(&mut self, depth: usize, name: &str, value: &Object) -> bool {
+        if depth == 0 {
+            return match self.store.get_mut(name) {
+                Some(slot) => {
+                    *slot = value.clone();
+                    true
+                }
+                None => false,
+            };
+        }
+        match self.outer {
+            Some(ref outer) => outer.borrow_mut().assign_at(depth - 1, name, value),
+            None => false,
+        }
+    }
+
     pub fn set(&mut self, name: String, value: &Object) {
         match self.outer {
-            Some(ref outer) => {
-                if outer.borrow().store.contains_key(&name) {
-                    outer.borrow_mut().store.insert(name, value.clone());
-                } else {
-                    self.store.insert(name, value.clone());
-                }
+            Some(ref outer) if outer.borrow().store.contains_key(&name) => {
+                outer.borrow_mut().store.insert(name, value.clone());
             }
-            None => {
+            _ => {
                 self.store.insert(name, value.clone());
             }
         }
@@ -72,10 +98,7 @@ impl Env {
     }
 
     pub fn get_current_class(&self) -> Option<Object> {
-        match &self.current_class {
-            Some(class) => Some(class.clone()),
-            None => None,
-        }
+        self.current_class.clone()
     }
 
     pub fn reset_current_class(&mut self) {
@@ -85,4 +108,33 @@ impl Env {
     pub fn set_store(&mut self, name: String, value: &Object) {
         self.store.insert(name, value.clone());
     }
+
+    // bindings returns the variables of this scope, without the outer ones.
+    pub fn bindings(&self) -> Vec<(String, Object)> {
+        let mut bindings: Vec<(String, Object)> = self
+            .store
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        bindings.sort_by(|a, b| a.0.cmp(&b.0));
+        bindings
+    }
+}
+
+// EnvRef is the scope a function or class was defined in. A function is
+// usually stored in the scope it captures, so comparing or printing an EnvRef
+// must not look inside it: two EnvRefs are equal when they are the same scope.
+#[derive(Clone)]
+pub struct EnvRef(pub Rc<RefCell<Env>>);
+
+impl PartialEq for EnvRef {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Debug for EnvRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Env")
+    }
 }
@@ -0,0 +1,158 @@
+use std::fmt::Display;
+
+use crate::diagnostic::{Diagnostic, RUNTIME_ERROR};
+use crate::span::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeErrorKind {
+    UndefinedVariable,
+    UndefinedProperty,
+    TypeError,
+    ArityMismatch,
+    NotCallable,
+    InvalidThis,
+    InvalidIndex,
+    AssertionFailed,
+    InvalidJump,
+    StackOverflow,
+    Unsupported,
+}
+
+// TraceFrame is one active Lox call: the function being run and the span of
+// the call expression that entered it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    pub function: String,
+    pub call: Span,
+}
+
+// MAX_TRACE_LABELS is how many calls a rendered runtime error quotes.
+const MAX_TRACE_LABELS: usize = 16;
+
+// RuntimeError stops the running program. trace holds the Lox call stack at
+// the point of the error, outermost call first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub message: String,
+    pub span: Span,
+    pub trace: Vec<TraceFrame>,
+}
+
+impl RuntimeError {
+    pub fn new(kind: RuntimeErrorKind, message: &str, span: Span) -> Self {
+        RuntimeError {
+            kind,
+            message: message.to_string(),
+            span,
+            trace: vec![],
+        }
+    }
+
+    pub fn with_trace(mut self, trace: Vec<TraceFrame>) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    // diagnostic labels the calls of the trace innermost first. A run of
+    // the same call, as deep recursion makes, is one label, and past
+    // MAX_TRACE_LABELS the outer calls are only counted.
+    pub fn diagnostic(&self) -> Diagnostic {
+        let mut diagnostic = Diagnostic::error(RUNTIME_ERROR, &self.message, self.span);
+        let mut runs: Vec<(&TraceFrame, usize)> = vec![];
+        for frame in self.trace.iter().rev() {
+            match runs.last_mut() {
+                Some((last, n)) if *last == frame => *n += 1,
+                _ => runs.push((frame, 1)),
+            }
+        }
+        for (frame, n) in runs.iter().take(MAX_TRACE_LABELS) {
+            let mut message = format!("in {}()", frame.function);
+            if *n > 1 {
+                message.push_str(&format!(
+                    " (… {} more calls to {}())",
+                    n - 1,
+                    frame.function
+                ));
+            }
+            diagnostic = diagnostic.with_secondary(frame.call, &message);
+        }
+        let hidden: usize = runs.iter().skip(MAX_TRACE_LABELS).map(|(_, n)| n).sum();
+        if hidden > 0 {
+            diagnostic = diagnostic.with_note(&format!("… {} more calls", hidden));
+        }
+        diagnostic
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+// A top level error prints as the message and its line. Errors inside calls
+// list every frame innermost first, the same way clox does:
+//
+//   Operands must be numbers.
+//   [line 2] in add()
+//   [line 5] in script
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.message)?;
+        if self.trace.is_empty() {
+            return write!(f, "[line {}]", self.span.line);
+        }
+        let mut line = self.span.line;
+        for frame in self.trace.iter().rev() {
+            writeln!(f, "[line {}] in {}()", line, frame.function)?;
+            line = frame.call.line;
+        }
+        write!(f, "[line {}] in script", line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::span::FileId;
+
+    #[test]
+    fn test_diagnostic_collapses_trace() {
+        let frame = |function: &str, line: usize| TraceFrame {
+            function: function.to_string(),
+            call: Span::new(FileId(0), 0, 1, line, 1),
+        };
+        let mut trace = vec![frame("f", 8)];
+        trace.extend((0..4095).map(|_| frame("f", 5)));
+        let err = RuntimeError::new(
+            RuntimeErrorKind::StackOverflow,
+            "Stack overflow.",
+            Span::default(),
+        )
+        .with_trace(trace);
+        let labels: Vec<(usize, String)> = err
+            .diagnostic()
+            .secondary
+            .iter()
+            .map(|label| (label.span.line, label.message.clone()))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                (5, "in f() (… 4094 more calls to f())".to_string()),
+                (8, "in f()".to_string()),
+            ]
+        );
+
+        let trace = (0..20)
+            .map(|i| frame(if i % 2 == 0 { "f" } else { "g" }, i))
+            .collect();
+        let err = RuntimeError::new(
+            RuntimeErrorKind::StackOverflow,
+            "Stack overflow.",
+            Span::default(),
+        )
+        .with_trace(trace);
+        let diagnostic = err.diagnostic();
+        assert_eq!(diagnostic.secondary.len(), MAX_TRACE_LABELS);
+        assert_eq!(diagnostic.secondary[0].span.line, 19);
+        assert_eq!(diagnostic.notes, vec!["… 4 more calls".to_string()]);
+    }
+}
@@ -1,912 +1,1375 @@
-use std::{cell::RefCell, collections::HashMap, process::exit, rc::Rc};
+use std::{cell::RefCell, cmp::Ordering, collections::HashMap, rc::Rc};
 
 use crate::{
-    ast::{ExprType, Literal, Program, Stmt},
+    ast::{BlockStmt, ExprNode, ExprType, Ident, Literal, Program, Stmt, StmtNode},
     builtins,
-    envs::Env,
-    objects::Object,
+    envs::{Env, EnvRef},
+    error::{RuntimeError, RuntimeErrorKind, TraceFrame},
+    objects::{arithmetic, compare, is_equal, is_truthy, position, Arithmetic, HashObject, Object},
+    output,
+    resolver::Locals,
+    span::Span,
     token::Token,
 };
 
+// MAX_CALL_DEPTH is how deeply Lox calls nest before the evaluator stops with a
+// stack overflow. Each call recurses on the Rust stack, so the evaluator needs
+// a thread with STACK_SIZE bytes of stack to get that deep.
+pub const MAX_CALL_DEPTH: usize = 4096;
+pub const STACK_SIZE: usize = 512 << 20;
+
+// Flow is how a statement finished. Anything but Normal unwinds the enclosing
+// statements until a loop or a function call handles it.
+enum Flow {
+    Normal,
+    Return(Object),
+    Break(Option<String>, Span),
+    Continue(Option<String>, Span),
+}
+
 pub struct Evaluator {
     output: bool,
     pub ast: Program,
     builtins: HashMap<String, Object>,
     envs: Rc<RefCell<Env>>,
+    globals: Rc<RefCell<Env>>,
+    // depth of every local variable use, from the resolver
+    locals: Locals,
+    call_stack: Vec<TraceFrame>,
+    // classes that define the methods being run, innermost last
+    classes: Vec<Rc<Object>>,
 }
 
 impl Evaluator {
     pub fn new(ast: Program, output: bool) -> Self {
+        let globals = Rc::new(RefCell::new(Env::new()));
         Self {
-            ast: ast,
+            ast,
             builtins: builtins::new_builtins(),
-            output: output,
-            envs: Rc::new(RefCell::new(Env::new())),
+            output,
+            envs: Rc::clone(&globals),
+            globals,
+            locals: HashMap::new(),
+            call_stack: vec![],
+            classes: vec![],
         }
     }
 
-    pub fn evaluate(&mut self) {
-        for stmt in self.ast.clone() {
-            self.evaluate_stmt(&stmt);
+    // define_locals adds the depths the resolver found for code about to run.
+    pub fn define_locals(&mut self, locals: Locals) {
+        self.locals.extend(locals);
+    }
+
+    pub fn evaluate(&mut self) -> Result<(), RuntimeError> {
+        let ast = self.ast.clone();
+        self.run(&ast).map(|_| ())
+    }
+
+    // run executes statements on top of everything evaluated so far and
+    // returns the value of the last statement when it is an expression. The
+    // depths of their locals have to be defined first. On a runtime error the
+    // evaluator is put back in the global scope, so it can keep being used.
+    pub fn run(&mut self, program: &[StmtNode]) -> Result<Option<Object>, RuntimeError> {
+        let mut value = None;
+        for (i, stmt) in program.iter().enumerate() {
+            let result = match &stmt.node {
+                Stmt::Expr(expr)
+                    if i == program.len() - 1 && !matches!(expr.node, ExprType::PrintExpr(_)) =>
+                {
+                    self.evaluate_expr(expr).map(Some)
+                }
+                _ => self.evaluate_stmt(stmt).and_then(|flow| match flow {
+                    Flow::Break(..) | Flow::Continue(..) => Err(self.invalid_jump(flow)),
+                    Flow::Return(object) => {
+                        if self.output {
+                            output::print(format_args!("{}\n", object));
+                        }
+                        Ok(None)
+                    }
+                    Flow::Normal => Ok(None),
+                }),
+            };
+            match result {
+                Ok(result) => value = result,
+                Err(err) => {
+                    self.envs = Rc::clone(&self.globals);
+                    self.call_stack.clear();
+                    self.classes.clear();
+                    return Err(err);
+                }
+            }
         }
+        Ok(value)
     }
 
-    fn evaluate_stmt(&mut self, stmt: &Stmt) -> Option<Object> {
-        match stmt {
+    // globals returns the variables defined in the global scope, sorted by name.
+    pub fn globals(&self) -> Vec<(String, Object)> {
+        self.globals.borrow().bindings()
+    }
+
+    fn evaluate_stmt(&mut self, stmt: &StmtNode) -> Result<Flow, RuntimeError> {
+        match &stmt.node {
             Stmt::Var(ident, expr) => {
                 let name = ident.0.clone();
-                let object = self.evaluate_expr(expr).unwrap();
+                let object = self.evaluate_expr(expr)?;
                 self.envs.borrow_mut().set_store(name, &object);
             }
             Stmt::Expr(expr) => {
-                let object = self.evaluate_expr(expr);
-                if let Some(object) = object {
-                    if let Object::ReturnValue(obj) = object {
-                        return Some(*obj);
-                    }
-                }
-            }
-            Stmt::Block(stmts) => {
-                let current_env = Rc::clone(&self.envs);
-                let pre_envs = Env::new_with_outer(Rc::clone(&current_env));
-                self.envs = Rc::new(RefCell::new(pre_envs));
-                for stmt in stmts {
-                    match stmt {
-                        Stmt::Return(expr) => {
-                            let object = self.evaluate_expr(expr).unwrap();
-                            self.envs = current_env;
-                            return Some(object);
-                        }
-                        _ => {
-                            let result = self.evaluate_stmt(stmt);
-                            if result.is_some() {
-                                self.envs = current_env;
-                                return result;
-                            }
-                        }
-                    }
+                if let ExprType::If { .. } = expr.node {
+                    return self.evaluate_if(expr);
                 }
-                self.envs = current_env;
+                self.evaluate_expr(expr)?;
             }
+            Stmt::Block(stmts) => return self.evaluate_block(stmts),
             Stmt::Return(expr) => {
-                let object = self.evaluate_expr(expr).unwrap();
-                if self.output {
-                    println!("{}", object);
-                }
-                return Some(object);
+                let object = self.evaluate_expr(expr)?;
+                return Ok(Flow::Return(object));
             }
             Stmt::Function(ident, args, body) => {
                 let name = ident.0.clone();
-                let object = Object::Function(args.clone(), body.clone());
+                let object =
+                    Object::Function(args.clone(), body.clone(), EnvRef(Rc::clone(&self.envs)));
                 self.envs.borrow_mut().set_store(name, &object);
             }
             Stmt::Blank => {}
             Stmt::Switch(expr, cases) => {
-                let result = self.evaluate_expr(expr).unwrap();
+                let result = self.evaluate_expr(expr)?;
                 for stmt in cases {
-                    match stmt {
+                    match &stmt.node {
                         Stmt::Case(expr, block) => {
-                            let case = self.evaluate_expr(expr).unwrap();
+                            let case = self.evaluate_expr(expr)?;
                             if case == result {
-                                for stmt in block {
-                                    let result = self.evaluate_stmt(stmt);
-                                    if result.is_some() {
-                                        return result;
-                                    }
-                                }
-                                break;
-                            }
-                        }
-                        Stmt::Default(block) => {
-                            for stmt in block {
-                                let result = self.evaluate_stmt(stmt);
-                                if result.is_some() {
-                                    return result;
-                                }
+                                return self.evaluate_block(block);
                             }
                         }
-                        _ => unimplemented!(),
+                        Stmt::Default(block) => return self.evaluate_block(block),
+                        _ => return Err(self.unsupported(stmt.span, "Expect case or default.")),
                     }
                 }
             }
-            Stmt::While(expr, block) => {
-                let mut condition = true;
-                let current_env = Rc::clone(&self.envs);
-                let pre_envs = Env::new_with_outer(Rc::clone(&current_env));
-                self.envs = Rc::new(RefCell::new(pre_envs));
-                while condition {
-                    let result = self.evaluate_expr(expr).unwrap();
-                    if let Object::Boolean(result) = result {
-                        if result {
-                            for stmt in block {
-                                let result = self.evaluate_stmt(stmt);
-                                if result.is_some() {
-                                    self.envs = current_env;
-                                    return result;
-                                }
-                            }
-                        } else {
-                            condition = false;
+            Stmt::While(..) | Stmt::For { .. } | Stmt::ForIn { .. } => {
+                return self.evaluate_loop(stmt, None);
+            }
+            Stmt::Labeled(label, stmt) => return self.evaluate_loop(stmt, Some(&label.0)),
+            Stmt::Break(label) => {
+                return Ok(Flow::Break(label.as_ref().map(|l| l.0.clone()), stmt.span));
+            }
+            Stmt::Continue(label) => {
+                return Ok(Flow::Continue(
+                    label.as_ref().map(|l| l.0.clone()),
+                    stmt.span,
+                ));
+            }
+            Stmt::ClassStmt {
+                name,
+                superclass,
+                properties,
+            } => {
+                let superclass = match superclass {
+                    Some(superclass) => match self.lookup(stmt.span, &superclass.0)? {
+                        class @ Object::Class(..) => Some(Rc::new(class)),
+                        _ => {
+                            return Err(self.runtime_error(
+                                RuntimeErrorKind::TypeError,
+                                stmt.span,
+                                "Superclass must be a class.",
+                            ))
                         }
-                    }
+                    },
+                    None => None,
+                };
+                let object = Object::Class(
+                    name.to_string(),
+                    properties.clone(),
+                    superclass,
+                    EnvRef(Rc::clone(&self.envs)),
+                );
+                self.envs.borrow_mut().set_store(name.to_string(), &object);
+            }
+            Stmt::Import(_) => {}
+            Stmt::Assert { condition, message } => {
+                let result = self.evaluate_expr(condition)?;
+                if let Object::Boolean(false) = result {
+                    return Err(self.runtime_error(
+                        RuntimeErrorKind::AssertionFailed,
+                        stmt.span,
+                        &format!("Assertion failed: {} {}", condition, message),
+                    ));
                 }
-                self.envs = current_env;
             }
-            Stmt::ClassStmt { name, properties } => {
-                let object = Object::Class(name.to_string().clone(), properties.clone());
-                self.envs
-                    .borrow_mut()
-                    .set(name.to_string().clone(), &object);
+            Stmt::Assign(ident, right) => {
+                self.evaluate_assign(ident, right)?;
+            }
+            _ => return Err(self.unsupported(stmt.span, "Unsupported statement.")),
+        }
+        Ok(Flow::Normal)
+    }
+
+    // evaluate_stmts runs stmts in the current scope, stopping at the first
+    // statement that does not finish normally.
+    fn evaluate_stmts(&mut self, stmts: &[StmtNode]) -> Result<Flow, RuntimeError> {
+        for stmt in stmts {
+            let flow = self.evaluate_stmt(stmt)?;
+            if !matches!(flow, Flow::Normal) {
+                return Ok(flow);
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn evaluate_block(&mut self, stmts: &[StmtNode]) -> Result<Flow, RuntimeError> {
+        let current_env = Rc::clone(&self.envs);
+        let pre_envs = Env::new_with_outer(Rc::clone(&current_env));
+        self.envs = Rc::new(RefCell::new(pre_envs));
+        let flow = self.evaluate_stmts(stmts);
+        self.envs = current_env;
+        flow
+    }
+
+    // evaluate_loop runs a while, for or for-in loop. label is the name the
+    // loop was given, if any, so that `break label;` can find it.
+    fn evaluate_loop(
+        &mut self,
+        stmt: &StmtNode,
+        label: Option<&str>,
+    ) -> Result<Flow, RuntimeError> {
+        match &stmt.node {
+            Stmt::While(condition, block) => {
+                while is_truthy(&self.evaluate_expr(condition)?) {
+                    if let Some(flow) = self.evaluate_iteration(block, label)? {
+                        return Ok(flow);
+                    }
+                }
             }
             Stmt::For {
                 init,
                 conditions,
                 step,
                 block,
-            } => self.evaluate_for(init, conditions, step, block),
+            } => {
+                // the loop variable lives in a scope around the whole loop
+                let current_env = Rc::clone(&self.envs);
+                let pre_envs = Env::new_with_outer(Rc::clone(&current_env));
+                self.envs = Rc::new(RefCell::new(pre_envs));
+                let flow = self.evaluate_for(init, conditions, step, block, label);
+                self.envs = current_env;
+                return flow;
+            }
             Stmt::ForIn { var, iter, block } => {
-                let iter = self.evaluate_expr(iter).unwrap();
-                let ident = match var.as_ref() {
+                let iter = self.evaluate_expr(iter)?;
+                let ident = match &var.node {
                     Stmt::Var(ident, _) => ident,
-                    _ => unimplemented!(),
+                    _ => return Err(self.unsupported(var.span, "Expect variable in for loop.")),
                 };
-                if let Object::Hash(ref hash) = iter {
-                    for (key, _) in hash.borrow().iter() {
-                        let current_env = Rc::clone(&self.envs);
-                        let pre_envs = Env::new_with_outer(Rc::clone(&current_env));
-                        self.envs = Rc::new(RefCell::new(pre_envs));
-                        self.envs.borrow_mut().set(ident.0.clone(), &key);
-                        for stmt in block {
-                            let result = self.evaluate_stmt(stmt);
-                            if result.is_some() {
-                                self.envs = current_env;
-                                return result;
-                            }
-                        }
-                        self.envs = current_env;
+                // a hash iterates its keys, an array its elements. The body may
+                // change the hash, so don't hold a borrow over it
+                let items: Vec<Object> = match iter {
+                    Object::Hash(ref hash) => hash.borrow().keys().cloned().collect(),
+                    Object::Array(ref items) => items.borrow().clone(),
+                    _ => vec![],
+                };
+                for item in items {
+                    let current_env = Rc::clone(&self.envs);
+                    let pre_envs = Env::new_with_outer(Rc::clone(&current_env));
+                    self.envs = Rc::new(RefCell::new(pre_envs));
+                    self.envs.borrow_mut().set_store(ident.0.clone(), &item);
+                    let flow = self.evaluate_iteration(block, label);
+                    self.envs = current_env;
+                    if let Some(flow) = flow? {
+                        return Ok(flow);
                     }
                 }
             }
-            Stmt::Import(_) => {}
-            Stmt::Assert { condition, message } => {
-                let result = self.evaluate_expr(condition).unwrap();
-                if let Object::Boolean(result) = result {
-                    if !result {
-                        println!("Assertion failed: {} {}", condition, message);
-                        exit(70);
-                    }
-                }
+            _ => return Err(self.unsupported(stmt.span, "Expect loop after label.")),
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn evaluate_for(
+        &mut self,
+        init: &StmtNode,
+        conditions: &ExprNode,
+        step: &StmtNode,
+        block: &[StmtNode],
+        label: Option<&str>,
+    ) -> Result<Flow, RuntimeError> {
+        self.evaluate_stmt(init)?;
+        while is_truthy(&self.evaluate_expr(conditions)?) {
+            if let Some(flow) = self.evaluate_iteration(block, label)? {
+                return Ok(flow);
             }
-            Stmt::Assign(ident, right) => match ident {
-                ExprType::Ident(ident) => {
-                    let name = ident.0.clone();
-                    let object = self.evaluate_expr(right).unwrap();
-                    self.envs.borrow_mut().set(name, &object);
-                    return Some(object);
+            self.evaluate_stmt(step)?;
+        }
+        Ok(Flow::Normal)
+    }
+
+    // evaluate_iteration runs the body of a loop once. It returns the flow that
+    // ends the loop, or None when the loop goes on.
+    fn evaluate_iteration(
+        &mut self,
+        block: &[StmtNode],
+        label: Option<&str>,
+    ) -> Result<Option<Flow>, RuntimeError> {
+        // a jump without a label belongs to the innermost loop
+        let targets = |target: &Option<String>| match target {
+            Some(target) => Some(target.as_str()) == label,
+            None => true,
+        };
+        match self.evaluate_block(block)? {
+            Flow::Normal => Ok(None),
+            Flow::Continue(target, _) if targets(&target) => Ok(None),
+            Flow::Break(target, _) if targets(&target) => Ok(Some(Flow::Normal)),
+            flow => Ok(Some(flow)),
+        }
+    }
+
+    // evaluate_if runs the branch of an if statement picked by its conditions.
+    fn evaluate_if(&mut self, expr: &ExprNode) -> Result<Flow, RuntimeError> {
+        let (condition, elseif, then_branch, else_branch) = match &expr.node {
+            ExprType::If {
+                condition,
+                elseif,
+                then_branch,
+                else_branch,
+            } => (condition, elseif, then_branch, else_branch),
+            _ => return Ok(Flow::Normal),
+        };
+        if is_truthy(&self.evaluate_expr(condition)?) {
+            return self.evaluate_block(then_branch);
+        }
+        for (condition, block) in elseif {
+            if is_truthy(&self.evaluate_expr(condition)?) {
+                return self.evaluate_block(block);
+            }
+        }
+        self.evaluate_block(else_branch)
+    }
+
+    // call runs the body of a function or method in the current scope and
+    // returns the value it returned.
+    fn call(&mut self, body: &[StmtNode]) -> Result<Object, RuntimeError> {
+        match self.evaluate_stmts(body)? {
+            Flow::Normal => Ok(Object::Nil),
+            Flow::Return(object) => Ok(object),
+            flow => Err(self.invalid_jump(flow)),
+        }
+    }
+
+    // evaluate_assign stores right into a variable, a hash entry or a field of this.
+    fn evaluate_assign(
+        &mut self,
+        left: &ExprNode,
+        right: &ExprNode,
+    ) -> Result<Object, RuntimeError> {
+        match &left.node {
+            ExprType::Ident(ident) => {
+                let object = self.evaluate_expr(right)?;
+                self.assign(left.span, &ident.0, &object)?;
+                Ok(object)
+            }
+            ExprType::IndexExpr(target, expr) => match self.evaluate_expr(target)? {
+                Object::Hash(ref hash) => {
+                    let index = self.evaluate_key(expr)?;
+                    let object = self.evaluate_expr(right)?;
+                    hash.borrow_mut().insert(index, object.clone());
+                    Ok(object)
                 }
-                ExprType::IndexExpr(ident, expr) => {
-                    if let ExprType::Ident(ident) = ident.as_ref() {
-                        let hash = self.envs.borrow_mut().get(ident.0.clone());
-                        if hash.is_none() {
-                            panic!("not found {:?}", ident);
+                Object::Array(ref elements) => {
+                    let index = self.evaluate_expr(expr)?;
+                    let object = self.evaluate_expr(right)?;
+                    let i = match position(&index) {
+                        Some(i) => i,
+                        None => {
+                            return Err(
+                                self.invalid_index(expr.span, "Array index must be a number.")
+                            )
                         }
-                        let hash_object = hash.unwrap();
-                        if let Object::Hash(ref hash) = hash_object {
-                            let index = self.evaluate_expr(expr).unwrap();
-                            let object = self.evaluate_expr(right).unwrap();
-                            hash.borrow_mut().insert(index, object.clone());
-                            self.envs
-                                .borrow_mut()
-                                .set(ident.0.clone(), &Object::Hash(hash.clone()));
-                            return Some(object);
+                    };
+                    let mut elements = elements.borrow_mut();
+                    let len = elements.len();
+                    match elements.get_mut(i) {
+                        Some(element) => *element = object.clone(),
+                        None => {
+                            return Err(self.invalid_index(
+                                expr.span,
+                                &format!(
+                                    "Index {} out of bounds for array of length {}.",
+                                    index, len
+                                ),
+                            ))
                         }
                     }
+                    Ok(object)
                 }
-                ExprType::ThisExpr(ident) => {
-                    let object = self.envs.borrow_mut().get_current_class().unwrap();
-                    if let Object::ClassInstance {
-                        name: _,
-                        ref fields,
-                        properties: _,
-                    } = object.clone()
-                    {
-                        let object = self.evaluate_expr(right).unwrap();
-                        fields.borrow_mut().insert(ident.0.clone(), object.clone());
-                        return Some(object);
-                    }
-                    return Some(object);
-                }
-                _ => unimplemented!("not found {:?}", ident),
+                _ => Err(self.runtime_error(
+                    RuntimeErrorKind::TypeError,
+                    target.span,
+                    "Only arrays and hashes support index assignment.",
+                )),
             },
-            _ => unimplemented!(),
+            _ => Err(self.unsupported(left.span, "Invalid assignment target.")),
         }
-        None
     }
 
-    fn evaluate_for(&mut self, init: &Stmt, conditions: &ExprType, step: &Stmt, block: &Vec<Stmt>) {
-        self.evaluate_stmt(init);
-        let mut condition = true;
-        while condition {
-            let result = self.evaluate_expr(conditions).unwrap();
-            if let Object::Boolean(result) = result {
-                if result {
-                    let current_env = Rc::clone(&self.envs);
-                    let pre_envs = Env::new_with_outer(Rc::clone(&current_env));
-                    self.envs = Rc::new(RefCell::new(pre_envs));
-                    for stmt in block {
-                        self.evaluate_stmt(stmt);
+    // evaluate_compound_assign applies `a op= b` to the value stored in a
+    // with the same operator as `a op b`, so `s += "x"` concatenates.
+    fn evaluate_compound_assign(
+        &mut self,
+        span: Span,
+        left: &ExprNode,
+        op: &Token,
+        right: &ExprNode,
+    ) -> Result<Object, RuntimeError> {
+        let ident = match &left.node {
+            ExprType::Ident(ident) => ident,
+            _ => return Err(self.unsupported(left.span, "Invalid assignment target.")),
+        };
+        let current = self.lookup(left.span, &ident.0)?;
+        let operand = self.evaluate_expr(right)?;
+        let op = match op {
+            Token::MinusSelf => Token::Minus,
+            Token::PlusSelf => Token::Plus,
+            Token::StarSelf => Token::Star,
+            Token::SlashSelf => Token::Slash,
+            _ => Token::Mod,
+        };
+        let object = self.evaluate_binary(span, &op, current, operand)?;
+        self.assign(left.span, &ident.0, &object)?;
+        Ok(object)
+    }
+
+    // evaluate_binary applies an operator that takes both operands evaluated.
+    fn evaluate_binary(
+        &self,
+        span: Span,
+        op: &Token,
+        left: Object,
+        right: Object,
+    ) -> Result<Object, RuntimeError> {
+        match op {
+            Token::EqualEqual => Ok(Object::Boolean(is_equal(&left, &right))),
+            Token::BangEqual => Ok(Object::Boolean(!is_equal(&left, &right))),
+            Token::Less | Token::LessEqual | Token::Greater | Token::GreaterEqual => {
+                let ordering = match compare(&left, &right) {
+                    Some(ordering) => ordering,
+                    None => return Err(self.type_error(span, "Operands must be numbers.")),
+                };
+                Ok(Object::Boolean(match op {
+                    Token::Less => ordering == Some(Ordering::Less),
+                    Token::LessEqual => {
+                        matches!(ordering, Some(Ordering::Less | Ordering::Equal))
                     }
-                    self.envs = current_env;
-                    self.evaluate_stmt(step);
-                } else {
-                    condition = false;
+                    Token::Greater => ordering == Some(Ordering::Greater),
+                    _ => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                }))
+            }
+            Token::Star | Token::Slash | Token::Minus | Token::Mod => {
+                let op = match op {
+                    Token::Star => Arithmetic::Multiply,
+                    Token::Slash => Arithmetic::Divide,
+                    Token::Mod => Arithmetic::Mod,
+                    _ => Arithmetic::Subtract,
+                };
+                match arithmetic(op, &left, &right) {
+                    Some(object) => Ok(object),
+                    None => Err(self.type_error(span, "Operands must be numbers.")),
+                }
+            }
+            Token::BitAnd | Token::BitOr => {
+                let op = match op {
+                    Token::BitAnd => Arithmetic::BitAnd,
+                    _ => Arithmetic::BitOr,
+                };
+                match arithmetic(op, &left, &right) {
+                    Some(object) => Ok(object),
+                    None => Err(self.type_error(span, "Operands must be integers.")),
                 }
             }
+            Token::Plus => match (left, right) {
+                (left, right) if left.is_number() && right.is_number() => {
+                    Ok(arithmetic(Arithmetic::Add, &left, &right).unwrap())
+                }
+                (Object::String(left), Object::String(right)) => Ok(Object::String(left + &right)),
+                _ => Err(self.type_error(span, "Operands must be two numbers or two strings.")),
+            },
+            _ => Err(self.unsupported(span, &format!("Unsupported operator {}.", op))),
         }
     }
 
-    fn evaluate_expr(&mut self, expr: &ExprType) -> Option<Object> {
-        match expr {
+    fn evaluate_expr(&mut self, expr: &ExprNode) -> Result<Object, RuntimeError> {
+        let span = expr.span;
+        match &expr.node {
             ExprType::Literal(lit) => match lit {
-                Literal::Number(n) => Some(Object::Number(*n)),
-                Literal::Bool(v) => Some(Object::Boolean(*v)),
-                Literal::Nil => Some(Object::Nil),
-                Literal::String(s) => Some(Object::String(s.clone())),
+                Literal::Number(n) => Ok(Object::Number(*n)),
+                Literal::Int(i) => Ok(Object::Int(*i)),
+                Literal::Bool(v) => Ok(Object::Boolean(*v)),
+                Literal::Nil => Ok(Object::Nil),
+                Literal::String(s) => Ok(Object::String(s.clone())),
                 Literal::Array(arr) => {
                     let mut elements = Vec::new();
                     for elem in arr {
-                        elements.push(self.evaluate_expr(elem).unwrap());
+                        elements.push(self.evaluate_expr(elem)?);
                     }
-                    Some(Object::Array(elements))
+                    Ok(Object::Array(Rc::new(RefCell::new(elements))))
                 }
                 Literal::Hash(hash) => {
-                    let mut hash_map = HashMap::new();
+                    let mut hash_map = HashObject::new();
                     for (key, value) in hash {
-                        let key = self.evaluate_expr(key).unwrap();
-                        let value = self.evaluate_expr(value).unwrap();
+                        let key = self.evaluate_key(key)?;
+                        let value = self.evaluate_expr(value)?;
                         hash_map.insert(key, value);
                     }
-                    Some(Object::Hash(Rc::new(RefCell::new(hash_map))))
+                    Ok(Object::Hash(Rc::new(RefCell::new(hash_map))))
                 }
-                Literal::Index(index) => Some(Object::Index(*index)),
             },
-            ExprType::Ident(v) => {
-                if let Some(builtin) = self.builtins.get(&v.0) {
-                    return Some(builtin.clone());
-                } else if let Some(env) = self.envs.borrow_mut().get(v.0.clone()) {
-                    return Some(env.clone());
-                }
-                println!("Undefined variable '{}'.", v.0);
-                exit(70);
-            }
+            ExprType::Ident(v) => self.lookup(span, &v.0),
             ExprType::GroupingExpr(expr) => self.evaluate_expr(expr),
             ExprType::PrefixExpr(op, expr) => {
-                let expr = self.evaluate_expr(expr).unwrap();
+                let expr = self.evaluate_expr(expr)?;
                 match op {
-                    Token::Minus => {
-                        if let Object::Number(expr) = expr {
-                            return Some(Object::Number(-expr));
-                        }
-                        exit(70);
-                    }
-                    Token::Bang => {
-                        if let Object::Boolean(expr) = expr {
-                            return Some(Object::Boolean(!expr));
-                        } else if let Object::Nil = expr {
-                            return Some(Object::Boolean(true));
-                        } else if let Object::Number(expr) = expr {
-                            return Some(Object::Boolean(expr == 0.0));
-                        } else if let Object::String(expr) = expr {
-                            return Some(Object::Boolean(expr.is_empty()));
-                        } else if let Object::ReturnValue(expr) = expr {
-                            match *expr {
-                                Object::Boolean(expr) => return Some(Object::Boolean(!expr)),
-                                Object::Nil => return Some(Object::Boolean(true)),
-                                Object::Number(expr) => return Some(Object::Boolean(expr == 0.0)),
-                                Object::String(expr) => {
-                                    return Some(Object::Boolean(expr.is_empty()))
-                                }
-                                _ => unimplemented!(),
-                            }
-                        }
-                        return None;
+                    Token::Minus => match expr.negate() {
+                        Some(object) => Ok(object),
+                        None => Err(self.type_error(span, "Operand must be a number.")),
+                    },
+                    Token::Bang => Ok(Object::Boolean(!is_truthy(&expr))),
+                    _ => {
+                        Err(self.unsupported(span, &format!("Unsupported prefix operator {}.", op)))
                     }
-                    _ => unimplemented!(),
                 }
             }
             ExprType::InfixExpr(left, op, right) => {
-                if let Token::Equal = op {
-                    match left.as_ref() {
-                        ExprType::Ident(ident) => {
-                            let name = ident.0.clone();
-                            let object = self.evaluate_expr(right).unwrap();
-                            self.envs.borrow_mut().set(name, &object);
-                            return Some(object);
-                        }
-                        ExprType::IndexExpr(ident, expr) => {
-                            if let ExprType::Ident(ident) = ident.as_ref() {
-                                let hash = self.envs.borrow_mut().get(ident.0.clone());
-                                if hash.is_none() {
-                                    panic!("not found {:?}", ident);
-                                }
-                                let hash_object = hash.unwrap();
-                                if let Object::Hash(ref hash) = hash_object {
-                                    let index = self.evaluate_expr(expr).unwrap();
-                                    let object = self.evaluate_expr(right).unwrap();
-                                    hash.borrow_mut().insert(index, object.clone());
-                                    self.envs
-                                        .borrow_mut()
-                                        .set(ident.0.clone(), &Object::Hash(hash.clone()));
-                                    return Some(object);
-                                }
-                            }
-                        }
-                        ExprType::ThisExpr(ident) => {
-                            let object = self.envs.borrow_mut().get_current_class().unwrap();
-                            if let Object::ClassInstance {
-                                name: _,
-                                ref fields,
-                                properties: _,
-                            } = object.clone()
-                            {
-                                let object = self.evaluate_expr(right).unwrap();
-                                fields.borrow_mut().insert(ident.0.clone(), object.clone());
-                                return Some(object);
-                            }
-                            return Some(object);
-                        }
-                        _ => {}
-                    }
-                }
-
-                match &op {
-                    Token::MinusSelf => {
-                        if let ExprType::Ident(ident) = *left.clone() {
-                            let object = self.evaluate_expr(right).unwrap();
-                            let object = match object {
-                                Object::Number(n) => n,
-                                _ => unimplemented!(),
-                            };
-                            let object = match self.envs.borrow_mut().get(ident.0.clone()) {
-                                Some(Object::Number(n)) => Object::Number(n - object.clone()),
-                                _ => unimplemented!(),
-                            };
-                            self.envs.borrow_mut().set(ident.0.clone(), &object);
-                            return Some(object);
-                        }
-                        return Some(Object::Nil);
-                    }
-                    Token::PlusSelf => {
-                        if let ExprType::Ident(ident) = *left.clone() {
-                            let object = self.evaluate_expr(right).unwrap();
-                            let object = match object {
-                                Object::Number(n) => n,
-                                _ => unimplemented!(),
-                            };
-                            let object = match self.envs.borrow_mut().get(ident.0.clone()) {
-                                Some(Object::Number(n)) => Object::Number(n + object.clone()),
-                                _ => unimplemented!(),
-                            };
-                            self.envs.borrow_mut().set(ident.0.clone(), &object);
-                            return Some(object);
-                        }
-                        return Some(Object::Nil);
-                    }
-                    Token::StarSelf => {
-                        if let ExprType::Ident(ident) = *left.clone() {
-                            let object = self.evaluate_expr(right).unwrap();
-                            let object = match object {
-                                Object::Number(n) => n,
-                                _ => unimplemented!(),
-                            };
-                            let object = match self.envs.borrow_mut().get(ident.0.clone()) {
-                                Some(Object::Number(n)) => Object::Number(n * object.clone()),
-                                _ => unimplemented!(),
-                            };
-                            self.envs.borrow_mut().set(ident.0.clone(), &object);
-                            return Some(object);
+                match op {
+                    Token::Equal => {
+                        if let ExprType::Ident(_) | ExprType::IndexExpr(..) = &left.node {
+                            return self.evaluate_assign(left, right);
                         }
-                        return Some(Object::Nil);
                     }
-                    Token::SlashSelf => {
-                        if let ExprType::Ident(ident) = *left.clone() {
-                            let object = self.evaluate_expr(right).unwrap();
-                            let object = match object {
-                                Object::Number(n) => n,
-                                _ => unimplemented!(),
-                            };
-                            let object = match self.envs.borrow_mut().get(ident.0.clone()) {
-                                Some(Object::Number(n)) => Object::Number(n / object.clone()),
-                                _ => unimplemented!(),
-                            };
-                            self.envs.borrow_mut().set(ident.0.clone(), &object);
-                            return Some(object);
-                        }
-                        return Some(Object::Nil);
+                    Token::MinusSelf
+                    | Token::PlusSelf
+                    | Token::StarSelf
+                    | Token::SlashSelf
+                    | Token::ModSelf => {
+                        return self.evaluate_compound_assign(span, left, op, right)
                     }
-                    Token::ModSelf => {
-                        if let ExprType::Ident(ident) = *left.clone() {
-                            let object = self.evaluate_expr(right).unwrap();
-                            let object = match object {
-                                Object::Number(n) => n,
-                                _ => unimplemented!(),
-                            };
-                            let object = match self.envs.borrow_mut().get(ident.0.clone()) {
-                                Some(Object::Number(n)) => Object::Number(n % object.clone()),
-                                _ => unimplemented!(),
-                            };
-                            self.envs.borrow_mut().set(ident.0.clone(), &object);
-                            return Some(object);
+                    // and/or skip the right operand once the left one
+                    // decides, the deciding operand is the value
+                    Token::And | Token::Or => {
+                        let left = self.evaluate_expr(left)?;
+                        if is_truthy(&left) == (*op == Token::Or) {
+                            return Ok(left);
                         }
-                        return Some(Object::Nil);
+                        return self.evaluate_expr(right);
                     }
                     _ => {}
                 }
 
-                let left = self.evaluate_expr(left);
-                let right = self.evaluate_expr(right);
-                match op {
-                    Token::EqualEqual => {
-                        if let Object::Number(left) = left.clone().unwrap() {
-                            if let Object::Number(right) = right.unwrap() {
-                                return Some(Object::Boolean(left == right));
-                            }
-                        } else if let Object::Boolean(left) = left.clone().unwrap() {
-                            if let Object::Boolean(right) = right.unwrap() {
-                                return Some(Object::Boolean(left == right));
-                            }
-                        } else if let Object::String(left) = left.clone().unwrap() {
-                            if let Object::String(right) = right.unwrap() {
-                                return Some(Object::Boolean(left == right));
-                            }
-                        } else if let Object::Nil = left.clone().unwrap() {
-                            if let Object::Nil = right.unwrap() {
-                                return Some(Object::Boolean(true));
-                            }
-                        }
-                        return Some(Object::Boolean(false));
-                    }
-                    Token::BangEqual => {
-                        if let Object::Number(left) = left.clone().unwrap() {
-                            if let Object::Number(right) = right.unwrap() {
-                                return Some(Object::Boolean(left != right));
-                            }
-                        } else if let Object::Boolean(left) = left.clone().unwrap() {
-                            if let Object::Boolean(right) = right.unwrap() {
-                                return Some(Object::Boolean(left != right));
-                            }
-                        } else if let Object::String(left) = left.clone().unwrap() {
-                            if let Object::String(right) = right.unwrap() {
-                                return Some(Object::Boolean(left != right));
-                            }
-                        }
-                        return Some(Object::Boolean(false));
-                    }
-                    Token::Less => {
-                        if let Object::Number(left) = left.clone().unwrap() {
-                            if let Object::Number(right) = right.unwrap() {
-                                return Some(Object::Boolean(left < right));
-                            }
-                        }
-                        exit(70);
-                    }
-                    Token::LessEqual => {
-                        if let Object::Number(left) = left.clone().unwrap() {
-                            if let Object::Number(right) = right.unwrap() {
-                                return Some(Object::Boolean(left <= right));
-                            }
-                        }
-                        exit(70);
-                    }
-                    Token::Greater => {
-                        if let Object::Number(left) = left.clone().unwrap() {
-                            if let Object::Number(right) = right.unwrap() {
-                                return Some(Object::Boolean(left > right));
-                            }
-                        }
-                        exit(70);
-                    }
-                    Token::GreaterEqual => {
-                        if let Object::Number(left) = left.clone().unwrap() {
-                            if let Object::Number(right) = right.unwrap() {
-                                return Some(Object::Boolean(left >= right));
-                            }
-                        }
-                        exit(70);
-                    }
-                    Token::Star => {
-                        if let Object::Number(left) = left.clone().unwrap() {
-                            if let Object::Number(right) = right.unwrap() {
-                                return Some(Object::Number(left * right));
-                            }
-                        }
-                        exit(70);
-                    }
-                    Token::Slash => {
-                        if let Object::Number(left) = left.clone().unwrap() {
-                            if let Object::Number(right) = right.unwrap() {
-                                return Some(Object::Number(left / right));
-                            }
-                        }
-                        exit(70);
-                    }
-                    Token::Minus => {
-                        if let Object::Number(left) = left.clone().unwrap() {
-                            if let Object::Number(right) = right.unwrap() {
-                                return Some(Object::Number(left - right));
-                            }
-                        }
-                        exit(70);
-                    }
-                    Token::Plus => {
-                        if let Object::Number(left) = left.clone().unwrap() {
-                            if let Object::Number(right) = right.clone().unwrap() {
-                                return Some(Object::Number(left + right));
-                            }
-                        } else if let Object::String(left) = left.clone().unwrap() {
-                            if let Object::String(right) = right.clone().unwrap() {
-                                return Some(Object::String(left + &right));
-                            }
-                        }
-                        panic!("token plus not found {:?} {:?}", left, right);
-                    }
-                    Token::And => {
-                        if let Object::Boolean(left) = left.unwrap() {
-                            if let Object::Boolean(right) = right.unwrap() {
-                                return Some(Object::Boolean(left && right));
-                            }
-                        }
-                        exit(70);
-                    }
-                    Token::Or => {
-                        if let Object::Boolean(left) = left.unwrap() {
-                            if let Object::Boolean(right) = right.unwrap() {
-                                return Some(Object::Boolean(left || right));
-                            }
-                        }
-                        exit(70);
-                    }
-                    _ => {
-                        println!("not found {:?} {:?} {:?}", left, op, right);
-                        unimplemented!()
-                    }
-                }
+                let left = self.evaluate_expr(left)?;
+                let right = self.evaluate_expr(right)?;
+                self.evaluate_binary(span, op, left, right)
             }
             ExprType::PrintExpr(expr) => {
                 for expr in expr.iter() {
-                    let object = self.evaluate_expr(expr).unwrap();
-                    print!("{}", object);
+                    let object = self.evaluate_expr(expr)?;
+                    output::print(format_args!("{}", object));
                 }
-                return Some(Object::Nil);
+                Ok(Object::Nil)
             }
-            ExprType::If {
-                condition,
-                elseif,
-                then_branch,
-                else_branch,
-            } => {
-                let condition = self.evaluate_expr(condition).unwrap();
-                if let Object::Boolean(condition) = condition {
-                    if condition {
-                        for stmt in then_branch {
-                            match stmt {
-                                Stmt::Return(expr) => {
-                                    let object = self.evaluate_expr(expr).unwrap();
-                                    return Some(Object::ReturnValue(Box::new(object)));
-                                }
-                                _ => {
-                                    let result = self.evaluate_stmt(stmt);
-                                    if result.is_some() {
-                                        return result;
-                                    }
-                                }
-                            }
-                        }
-                    } else {
-                        let mut condition = false;
-                        for (cond, block) in elseif {
-                            let cond = self.evaluate_expr(cond).unwrap();
-                            if let Object::Boolean(cond) = cond {
-                                if cond {
-                                    for stmt in block {
-                                        match stmt {
-                                            Stmt::Return(expr) => {
-                                                let object = self.evaluate_expr(expr).unwrap();
-                                                return Some(Object::ReturnValue(Box::new(object)));
-                                            }
-                                            _ => {
-                                                let result = self.evaluate_stmt(stmt);
-                                                if result.is_some() {
-                                                    return result;
-                                                }
-                                            }
-                                        }
-                                    }
-                                    condition = true;
-                                    break;
-                                }
-                            }
-                        }
-                        if !condition {
-                            for stmt in else_branch {
-                                match stmt {
-                                    Stmt::Return(expr) => {
-                                        let object = self.evaluate_expr(expr).unwrap();
-                                        return Some(Object::ReturnValue(Box::new(object)));
-                                    }
-                                    _ => {
-                                        if let Some(result) = self.evaluate_stmt(stmt) {
-                                            return Some(result);
-                                        }
-                                    }
-                                }
-                            }
-                        }
+            // an if used as a value; statement ifs go through evaluate_stmt
+            ExprType::If { .. } => match self.evaluate_if(expr)? {
+                Flow::Normal => Ok(Object::Nil),
+                Flow::Return(object) => Ok(object),
+                flow => Err(self.invalid_jump(flow)),
+            },
+            ExprType::Function { params, body } => Ok(Object::Function(
+                params.clone(),
+                body.clone(),
+                EnvRef(Rc::clone(&self.envs)),
+            )),
+            ExprType::This => self.current_class(span),
+            ExprType::Super(method) => {
+                let instance = self.current_class(span)?;
+                let superclass = match self.classes.last().map(|class| class.as_ref()) {
+                    Some(Object::Class(_, _, Some(superclass), _)) => Rc::clone(superclass),
+                    Some(_) => {
+                        return Err(self.runtime_error(
+                            RuntimeErrorKind::InvalidThis,
+                            span,
+                            "Can't use 'super' in a class with no superclass.",
+                        ))
+                    }
+                    None => {
+                        return Err(self.runtime_error(
+                            RuntimeErrorKind::InvalidThis,
+                            span,
+                            "Can't use 'super' outside of a class.",
+                        ))
                     }
+                };
+                match self.bind(instance, &superclass, &method.0) {
+                    Some(method) => Ok(method),
+                    None => Err(self.undefined_property(span, &method.0)),
                 }
-                return Some(Object::Nil);
             }
-            ExprType::Function { params, body } => {
-                return Some(Object::Function(params.clone(), body.clone()));
-            }
-            ExprType::ThisCall { method, args } => {
-                let class = self.envs.borrow_mut().get_current_class();
-                if class.is_none() {
-                    panic!("not found this class call context: {:?}", method);
-                }
-                let class = class.unwrap();
-                if let Object::ClassInstance {
-                    name: _,
-                    fields: _,
-                    ref properties,
-                } = class.clone()
-                {
-                    let current_env = Rc::clone(&self.envs);
-                    let pre_envs = Env::new_with_outer(Rc::clone(&current_env));
-                    self.envs = Rc::new(RefCell::new(pre_envs));
-
-                    if let Object::Function(params, stmts) =
-                        properties.borrow().get(&method.0).unwrap()
-                    {
-                        for (i, param) in params.iter().enumerate() {
-                            let arg = self.evaluate_expr(&args[i]).unwrap();
-                            self.envs.borrow_mut().set_store(param.0.clone(), &arg);
-                        }
-                        for stmt in stmts {
-                            let result = self.evaluate_stmt(stmt);
-                            if result.is_some() {
-                                self.envs = current_env;
-                                return result;
-                            }
-                        }
+            ExprType::Get { object, name } => {
+                let instance = self.evaluate_expr(object)?;
+                let (class, fields) = match instance {
+                    Object::ClassInstance {
+                        ref class,
+                        ref fields,
+                        ..
+                    } => (Rc::clone(class), Rc::clone(fields)),
+                    _ => {
+                        return Err(self.type_error(span, "Only instances have properties."));
                     }
-                    self.envs = current_env;
+                };
+                if let Some(value) = fields.borrow().get(&name.0) {
+                    return Ok(value.clone());
                 }
-                None
+                // unset fields read as nil
+                Ok(self.bind(instance, &class, &name.0).unwrap_or(Object::Nil))
+            }
+            ExprType::Set {
+                object,
+                name,
+                value,
+            } => {
+                let fields = match self.evaluate_expr(object)? {
+                    Object::ClassInstance { fields, .. } => fields,
+                    _ => return Err(self.type_error(span, "Only instances have fields.")),
+                };
+                let value = self.evaluate_expr(value)?;
+                fields.borrow_mut().insert(name.0.clone(), value.clone());
+                Ok(value)
             }
             ExprType::Call { callee, args } => {
-                let callee = self.evaluate_expr(&callee);
-                match callee {
-                    Some(Object::Builtin(name, argc, fun)) => {
+                let function = self.evaluate_expr(callee)?;
+                match function {
+                    Object::Builtin(name, argc, fun) => {
                         let mut args_vec = Vec::new();
                         for arg in args {
-                            let result = self.evaluate_expr(arg);
-                            if result.is_some() {
-                                args_vec.push(result.unwrap());
-                            }
+                            args_vec.push(self.evaluate_expr(arg)?);
                         }
                         let real_argc = args_vec.len() as i32;
                         if argc != -1 && real_argc != argc {
-                            println!(
-                                "fun {}: Expected {} arguments but got {}.",
-                                name, argc, real_argc
-                            );
-                            exit(70);
+                            return Err(self.runtime_error(
+                                RuntimeErrorKind::ArityMismatch,
+                                span,
+                                &format!(
+                                    "fun {}: Expected {} arguments but got {}.",
+                                    name, argc, real_argc
+                                ),
+                            ));
                         }
-                        return Some(fun(args_vec));
+                        Ok(fun(args_vec))
                     }
-                    Some(Object::Function(ident, stmts)) => {
-                        let current_env = Rc::clone(&self.envs);
-                        let pre_envs = Env::new_with_outer(Rc::clone(&current_env));
-                        self.envs = Rc::new(RefCell::new(pre_envs));
+                    Object::BoundMethod {
+                        receiver,
+                        class,
+                        method,
+                        ..
+                    } => {
+                        let (params, body) = match *method {
+                            Object::Function(params, body, _) => (params, body),
+                            _ => return Ok(Object::Nil),
+                        };
+                        let name = callee.to_string();
+                        self.check_arity(span, &name, params.len(), args.len())?;
+                        let mut values = vec![];
+                        for arg in args {
+                            values.push(self.evaluate_expr(arg)?);
+                        }
+                        self.call_method(*receiver, class, &params, &body, values, name, span)
+                    }
+                    Object::Function(params, stmts, closure) => {
+                        let name = callee.to_string();
+                        self.check_arity(span, &name, params.len(), args.len())?;
                         let mut values = HashMap::new();
-                        for (i, param) in ident.iter().enumerate() {
-                            let arg: Object = self.evaluate_expr(&args[i]).unwrap();
+                        for (param, arg) in params.iter().zip(args) {
+                            let arg = self.evaluate_expr(arg)?;
                             values.insert(param.0.clone(), arg);
                         }
+                        // the body runs in the scope the function was defined in
+                        let current_env = Rc::clone(&self.envs);
+                        let pre_envs = Env::new_with_outer(closure.0);
+                        self.envs = Rc::new(RefCell::new(pre_envs));
                         for (key, value) in values {
                             self.envs.borrow_mut().set_store(key, &value);
                         }
 
-                        for stmt in stmts {
-                            let block_result = self.evaluate_stmt(&stmt);
-                            if block_result.is_some() {
-                                self.envs = current_env;
-                                return block_result;
-                            }
-                        }
+                        self.enter(name, span)?;
+                        let result = self.call(&stmts)?;
+                        self.leave();
                         self.envs = current_env;
+                        Ok(result)
                     }
-                    _ => unimplemented!("not found {:?}({:?})", callee, args),
+                    _ => Err(self.runtime_error(
+                        RuntimeErrorKind::NotCallable,
+                        callee.span,
+                        "Can only call functions and classes.",
+                    )),
                 }
-
-                None
             }
             ExprType::ClassInit { name, args } => {
-                // println!("{:?} {:?}", name, args);
-                let class_name = name.clone().to_string();
-                let class = self.envs.borrow_mut().get(class_name).unwrap();
+                let class_name = name.to_string();
+                let class = match self.lookup(span, &class_name)? {
+                    class @ Object::Class(..) => Rc::new(class),
+                    _ => {
+                        return Err(self.runtime_error(
+                            RuntimeErrorKind::NotCallable,
+                            span,
+                            &format!("'{}' is not a class.", class_name),
+                        ))
+                    }
+                };
 
+                // fields and methods of superclasses first, so a subclass overrides them
+                let mut chain = vec![];
+                let mut next = Some(Rc::clone(&class));
+                while let Some(class) = next {
+                    next = match class.as_ref() {
+                        Object::Class(_, _, superclass, _) => superclass.clone(),
+                        _ => None,
+                    };
+                    chain.push(class);
+                }
+                let current_env = Rc::clone(&self.envs);
                 let mut props = HashMap::new();
                 let mut fields = HashMap::new();
-                if let Object::Class(_, properties) = class.clone() {
+                for class in chain.iter().rev() {
+                    // field initializers run in the scope the class was defined in
+                    let (properties, closure) = match class.as_ref() {
+                        Object::Class(_, properties, _, closure) => (properties, closure),
+                        _ => continue,
+                    };
+                    self.envs = Rc::clone(&closure.0);
                     for property in properties.iter() {
-                        match property {
+                        match &property.node {
                             Stmt::Var(ident, expr) => {
                                 let name = ident.0.clone();
-                                let object = self.evaluate_expr(&expr).unwrap();
+                                let object = self.evaluate_expr(expr)?;
                                 fields.insert(name, object);
                             }
                             Stmt::Function(ident, params, body) => {
                                 let name = ident.0.clone();
-                                let object = Object::Function(params.clone(), body.clone());
+                                let object =
+                                    Object::Function(params.clone(), body.clone(), closure.clone());
                                 props.insert(name, object);
                             }
-                            _ => unimplemented!(),
-                        };
-                    }
-                    let instance = Object::ClassInstance {
-                        name: name.clone().to_string(),
-                        fields: Rc::new(RefCell::new(fields)),
-                        properties: Rc::new(RefCell::new(props.clone())),
-                    };
-                    if let Some(init_func) = props.clone().get("init") {
-                        let current_env = Rc::clone(&self.envs);
-                        let pre_envs = Env::new_with_outer(Rc::clone(&current_env));
-                        self.envs = Rc::new(RefCell::new(pre_envs));
-                        if let Object::Function(params, body) = init_func {
-                            for (i, arg) in args.iter().enumerate() {
-                                let arg: Object = self.evaluate_expr(&arg).unwrap();
-                                let ident = params[i].0.clone();
-                                self.envs.borrow_mut().set(ident, &arg);
-                            }
-                            if args.len() == 0 {
-                                for param in params {
-                                    self.envs.borrow_mut().set(param.0.clone(), &Object::Nil);
-                                }
+                            _ => {
+                                return Err(self.unsupported(
+                                    property.span,
+                                    "Expect field or method in class body.",
+                                ))
                             }
-                            self.envs.borrow_mut().set_current_class(instance.clone());
-                            for stmt in body {
-                                self.evaluate_stmt(stmt);
-                            }
-                            self.envs.borrow_mut().reset_current_class();
-                        }
-                        self.envs = current_env;
-                    }
-                    return Some(instance);
-                }
-
-                return Some(Object::Nil);
-            }
-            ExprType::ClassCall {
-                callee,
-                method,
-                args,
-            } => {
-                let class = self.envs.borrow_mut().get(callee.to_string()).unwrap();
-                if let Object::ClassInstance {
-                    name: _,
-                    fields: _,
-                    ref properties,
-                } = class.clone()
-                {
-                    let current_env = Rc::clone(&self.envs);
-                    let pre_envs = Env::new_with_outer(Rc::clone(&current_env));
-                    self.envs = Rc::new(RefCell::new(pre_envs));
-
-                    self.envs.borrow_mut().set_current_class(class.clone());
-
-                    if let Object::Function(params, stmts) =
-                        properties.borrow().get(&method.0).unwrap()
-                    {
-                        for (i, param) in args.iter().enumerate() {
-                            let arg: Object = self.evaluate_expr(&param).unwrap();
-                            self.envs.borrow_mut().set_store(params[i].0.clone(), &arg);
-                        }
-                        for stmt in stmts {
-                            let result = self.evaluate_stmt(stmt);
-                            if result.is_some() {
-                                self.envs = current_env;
-                                self.envs.borrow_mut().reset_current_class();
-                                return result;
-                            }
-                        }
+                        };
                     }
-                    self.envs = current_env;
-                    self.envs.borrow_mut().reset_current_class();
                 }
-                return Some(Object::Nil);
-            }
-            ExprType::ThisExpr(ident) => {
-                let object = self.envs.borrow_mut().get_current_class();
-                if object.is_none() {
-                    panic!("not found this class context: {:?}", ident);
-                }
-                let object = object.unwrap();
-                if let Object::ClassInstance {
-                    name: _,
-                    ref fields,
-                    properties: _,
-                } = object.clone()
-                {
-                    if let Some(object) = fields.borrow().get(&ident.0) {
-                        return Some(object.clone());
+                self.envs = current_env;
+                let instance = Object::ClassInstance {
+                    name: class_name.clone(),
+                    class: Rc::clone(&class),
+                    fields: Rc::new(RefCell::new(fields)),
+                    properties: Rc::new(RefCell::new(props)),
+                };
+                // init may be called with fewer arguments than it has params, the rest are nil
+                if let Some((params, body, owner)) = self.find_method(&class, "init") {
+                    let name = format!("{}.init", class_name);
+                    if args.len() > params.len() {
+                        self.check_arity(span, &name, params.len(), args.len())?;
                     }
-                    return Some(Object::Nil);
-                }
-                return Some(Object::Nil);
-            }
-            ExprType::ClassGet { callee, prop } => {
-                let class = self.envs.borrow_mut().get(callee.to_string()).unwrap();
-                if let Object::ClassInstance {
-                    name: _,
-                    fields,
-                    properties: _,
-                } = class.clone()
-                {
-                    if let Some(object) = fields.borrow().get(&prop.0) {
-                        return Some(object.clone());
+                    let mut values = vec![];
+                    for arg in args {
+                        values.push(self.evaluate_expr(arg)?);
                     }
-                    return Some(Object::Nil);
+                    values.resize(params.len(), Object::Nil);
+                    self.call_method(instance.clone(), owner, &params, &body, values, name, span)?;
                 }
-                return Some(class.clone());
+                Ok(instance)
             }
             ExprType::IndexExpr(ident, index) => {
-                let left = self.evaluate_expr(ident).unwrap();
-                let index = self.evaluate_expr(index).unwrap();
-                return self.eval_index_expr(left, index);
+                let left = self.evaluate_expr(ident)?;
+                let index = self.evaluate_expr(index)?;
+                self.eval_index_expr(span, left, index)
             }
-            _ => {
-                println!("not found2 {:?}", expr);
-                return Some(Object::Nil);
+            _ => Err(self.unsupported(span, "Unsupported expression.")),
+        }
+    }
+
+    // invalid_jump reports a break or continue that no loop handled.
+    fn invalid_jump(&self, flow: Flow) -> RuntimeError {
+        let (keyword, label, span) = match flow {
+            Flow::Break(label, span) => ("break", label, span),
+            Flow::Continue(label, span) => ("continue", label, span),
+            _ => ("return", None, Span::default()),
+        };
+        let message = match label {
+            Some(label) => format!(
+                "Can't {} to '{}': no enclosing loop has that label.",
+                keyword, label
+            ),
+            None => format!("Can't use '{}' outside of a loop.", keyword),
+        };
+        self.runtime_error(RuntimeErrorKind::InvalidJump, span, &message)
+    }
+
+    // find_method looks name up in class and then its superclasses. It returns
+    // the method with the class that defines it, which is where super starts.
+    fn find_method(
+        &self,
+        class: &Rc<Object>,
+        name: &str,
+    ) -> Option<(Vec<Ident>, BlockStmt, Rc<Object>)> {
+        let mut next = Some(Rc::clone(class));
+        while let Some(class) = next {
+            let (properties, superclass) = match class.as_ref() {
+                Object::Class(_, properties, superclass, _) => (properties, superclass.clone()),
+                _ => return None,
+            };
+            for property in properties.iter().rev() {
+                if let Stmt::Function(ident, params, body) = &property.node {
+                    if ident.0 == name {
+                        return Some((params.clone(), body.clone(), Rc::clone(&class)));
+                    }
+                }
             }
+            next = superclass;
         }
+        None
+    }
+
+    // bind looks method up from class and returns it bound to instance.
+    fn bind(&self, instance: Object, class: &Rc<Object>, method: &str) -> Option<Object> {
+        let (params, body, owner) = self.find_method(class, method)?;
+        let closure = match owner.as_ref() {
+            Object::Class(_, _, _, closure) => closure.clone(),
+            _ => return None,
+        };
+        Some(Object::BoundMethod {
+            name: method.to_string(),
+            receiver: Box::new(instance),
+            class: owner,
+            method: Box::new(Object::Function(params, body, closure)),
+        })
+    }
+
+    // call_method runs a method of class with this bound to instance, in the
+    // scope the class was defined in.
+    #[allow(clippy::too_many_arguments)]
+    fn call_method(
+        &mut self,
+        instance: Object,
+        class: Rc<Object>,
+        params: &[Ident],
+        body: &[StmtNode],
+        values: Vec<Object>,
+        name: String,
+        span: Span,
+    ) -> Result<Object, RuntimeError> {
+        let closure = match class.as_ref() {
+            Object::Class(_, _, _, closure) => Rc::clone(&closure.0),
+            _ => Rc::clone(&self.globals),
+        };
+        let current_env = Rc::clone(&self.envs);
+        let mut env = Env::new_with_outer(closure);
+        env.set_current_class(instance);
+        for (param, value) in params.iter().zip(&values) {
+            env.set_store(param.0.clone(), value);
+        }
+        self.envs = Rc::new(RefCell::new(env));
+        self.classes.push(class);
+        self.enter(name, span)?;
+        let result = self.call(body)?;
+        self.leave();
+        self.classes.pop();
+        self.envs = current_env;
+        Ok(result)
+    }
+
+    // lookup reads the variable name used at span. Locals are read from the
+    // scope the resolver found them in, everything else from the globals.
+    fn lookup(&self, span: Span, name: &str) -> Result<Object, RuntimeError> {
+        let value = match self.locals.get(&span) {
+            Some(depth) => self.envs.borrow().get_at(*depth, name),
+            None => match self.builtins.get(name) {
+                Some(builtin) => Some(builtin.clone()),
+                None => self.globals.borrow().get_at(0, name),
+            },
+        };
+        value.ok_or_else(|| self.undefined_variable(span, name))
+    }
+
+    // assign stores value to the variable name used at span, which must
+    // already be declared.
+    fn assign(&mut self, span: Span, name: &str, value: &Object) -> Result<(), RuntimeError> {
+        let assigned = match self.locals.get(&span) {
+            Some(depth) => self.envs.borrow_mut().assign_at(*depth, name, value),
+            None => self.globals.borrow_mut().assign_at(0, name, value),
+        };
+        if !assigned {
+            return Err(self.undefined_variable(span, name));
+        }
+        Ok(())
+    }
+
+    fn enter(&mut self, function: String, call: Span) -> Result<(), RuntimeError> {
+        if self.call_stack.len() == MAX_CALL_DEPTH {
+            let kind = RuntimeErrorKind::StackOverflow;
+            return Err(self.runtime_error(kind, call, "Stack overflow."));
+        }
+        self.call_stack.push(TraceFrame { function, call });
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.call_stack.pop();
+    }
+
+    fn current_class(&self, span: Span) -> Result<Object, RuntimeError> {
+        match self.envs.borrow().get_current_class() {
+            Some(class) => Ok(class),
+            None => Err(self.runtime_error(
+                RuntimeErrorKind::InvalidThis,
+                span,
+                "Can't use 'this' outside of a class.",
+            )),
+        }
+    }
+
+    fn check_arity(
+        &self,
+        span: Span,
+        name: &str,
+        expected: usize,
+        got: usize,
+    ) -> Result<(), RuntimeError> {
+        if expected == got {
+            return Ok(());
+        }
+        Err(self.runtime_error(
+            RuntimeErrorKind::ArityMismatch,
+            span,
+            &format!(
+                "fun {}: Expected {} arguments but got {}.",
+                name, expected, got
+            ),
+        ))
+    }
+
+    // runtime_error builds an error at span carrying the current call stack.
+    fn runtime_error(&self, kind: RuntimeErrorKind, span: Span, message: &str) -> RuntimeError {
+        RuntimeError::new(kind, message, span).with_trace(self.call_stack.clone())
+    }
+
+    // evaluate_key evaluates expr to a hash key.
+    fn evaluate_key(&mut self, expr: &ExprNode) -> Result<Object, RuntimeError> {
+        let key = self.evaluate_expr(expr)?;
+        self.hash_key(expr.span, key)
+    }
+
+    fn hash_key(&self, span: Span, key: Object) -> Result<Object, RuntimeError> {
+        key.key().map_err(|message| self.type_error(span, &message))
     }
 
-    fn eval_index_expr(&mut self, left: Object, index: Object) -> Option<Object> {
-        match left.clone() {
-            Object::Array(arr) => {
-                if let Object::Index(index) = index {
-                    return Some(arr[index].clone());
-                } else if let Object::Number(index) = index {
-                    return Some(arr[index as usize].clone());
+    fn invalid_index(&self, span: Span, message: &str) -> RuntimeError {
+        self.runtime_error(RuntimeErrorKind::InvalidIndex, span, message)
+    }
+
+    fn type_error(&self, span: Span, message: &str) -> RuntimeError {
+        self.runtime_error(RuntimeErrorKind::TypeError, span, message)
+    }
+
+    fn unsupported(&self, span: Span, message: &str) -> RuntimeError {
+        self.runtime_error(RuntimeErrorKind::Unsupported, span, message)
+    }
+
+    fn undefined_variable(&self, span: Span, name: &str) -> RuntimeError {
+        self.runtime_error(
+            RuntimeErrorKind::UndefinedVariable,
+            span,
+            &format!("Undefined variable '{}'.", name),
+        )
+    }
+
+    fn undefined_property(&self, span: Span, name: &str) -> RuntimeError {
+        self.runtime_error(
+            RuntimeErrorKind::UndefinedProperty,
+            span,
+            &format!("Undefined property '{}'.", name),
+        )
+    }
+
+    fn eval_index_expr(
+        &mut self,
+        span: Span,
+        left: Object,
+        index: Object,
+    ) -> Result<Object, RuntimeError> {
+        match left {
+            Object::Array(ref arr) => {
+                let i = match position(&index) {
+                    Some(i) => i,
+                    None => return Err(self.invalid_index(span, "Array index must be a number.")),
+                };
+                let arr = arr.borrow();
+                match arr.get(i) {
+                    Some(object) => Ok(object.clone()),
+                    None => Err(self.invalid_index(
+                        span,
+                        &format!(
+                            "Index {} out of bounds for array of length {}.",
+                            index,
+                            arr.len()
+                        ),
+                    )),
                 }
-                return Some(Object::Nil);
             }
             Object::Hash(hash) => {
+                let index = self.hash_key(span, index)?;
                 if let Some(value) = hash.borrow().get(&index) {
-                    return Some(value.clone());
-                }
-                return Some(Object::Nil);
-            }
-            Object::String(s) => {
-                if let Object::Number(index) = index {
-                    if let Some(str) = s.chars().nth(index as usize) {
-                        return Some(Object::String(str.to_string()));
-                    }
-                    return Some(Object::Nil);
-                } else if let Object::Index(idx) = index {
-                    return Some(Object::String(s.chars().nth(idx).unwrap().to_string()));
-                } else if let Object::String(index) = index {
-                    panic!("not support string index {:?}[{:?}]", left, index);
+                    return Ok(value.clone());
                 }
-                return Some(Object::Nil);
+                Ok(Object::Nil)
             }
-            _ => unimplemented!(
-                "index only support array, hash and string. not support {:?}, index: {:?}",
-                left,
-                index
-            ),
+            Object::String(s) => match (position(&index), &index) {
+                // past the end of a string is nil, scanners rely on it
+                (Some(i), _) if i < usize::MAX => match s.chars().nth(i) {
+                    Some(c) => Ok(Object::String(c.to_string())),
+                    None => Ok(Object::Nil),
+                },
+                (Some(_), _) => Err(self.invalid_index(
+                    span,
+                    &format!(
+                        "Index {} out of bounds for string of length {}.",
+                        index,
+                        s.chars().count()
+                    ),
+                )),
+                (None, Object::String(_)) => Err(self.runtime_error(
+                    RuntimeErrorKind::InvalidIndex,
+                    span,
+                    "String index must be a number.",
+                )),
+                (None, _) => Ok(Object::Nil),
+            },
+            _ => Err(self.type_error(span, "Only arrays, hashes and strings can be indexed.")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexing;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+
+    fn evaluate(input: &str) -> Result<(), RuntimeError> {
+        let lex = Lexing::new(input);
+        let mut parse = Parser::new(lex);
+        let program = parse.parse();
+        assert!(!parse.has_errors());
+        let locals = Resolver::new().resolve(&program);
+        let mut evaluator = Evaluator::new(program, false);
+        evaluator.define_locals(locals);
+        evaluator.evaluate()
+    }
+
+    #[test]
+    fn test_runtime_error() {
+        let err = evaluate("var a = 1;\nvar b = -\"a\";").unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::TypeError);
+        assert_eq!(err.message, "Operand must be a number.");
+        assert_eq!(err.span.line, 2);
+        assert!(err.trace.is_empty());
+        assert_eq!(err.to_string(), "Operand must be a number.\n[line 2]");
+
+        let err = evaluate("print b;").unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::UndefinedVariable);
+
+        let err = evaluate("this.a;").unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::InvalidThis);
+
+        let err = evaluate("var a = [1];\na[0] += 5;").unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::Unsupported);
+        assert_eq!(err.message, "Invalid assignment target.");
+        assert_eq!(err.span.line, 2);
+    }
+
+    fn evaluate_value(input: &str) -> Object {
+        let lex = Lexing::new(input);
+        let mut parse = Parser::new(lex);
+        let program = parse.parse();
+        assert!(!parse.has_errors());
+        let mut evaluator = Evaluator::new(vec![], false);
+        evaluator.define_locals(Resolver::new().resolve(&program));
+        evaluator.run(&program).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_break_continue() {
+        let input = r#"
+var sum = 0;
+for (var i = 0; i < 10; i = i + 1) {
+    if (i == 5) {
+        break;
+    }
+    if (i == 2) {
+        continue;
+    }
+    sum = sum + i;
+}
+sum
+"#;
+        assert_eq!(evaluate_value(input), Object::Number(8.0));
+
+        let input = r#"
+var n = 0;
+var count = 0;
+while (n < 10) {
+    n = n + 1;
+    if (n < 4) {
+        continue;
+    }
+    count = count + 1;
+}
+count
+"#;
+        assert_eq!(evaluate_value(input), Object::Number(7.0));
+
+        let input = r#"
+var seen = {};
+outer: for (var i = 0; i < 5; i = i + 1) {
+    for (var j = 0; j < 5; j = j + 1) {
+        if (j == 2) {
+            continue outer;
+        }
+        if (i == 3) {
+            break outer;
         }
+        seen[i * 10 + j] = true;
+    }
+}
+len(seen)
+"#;
+        assert_eq!(evaluate_value(input), Object::Number(6.0));
+
+        let err = evaluate("fun f() {\n    break;\n}\nf();").unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::InvalidJump);
+        assert_eq!(err.span.line, 2);
+        let err = evaluate("while (true) {\n    continue outer;\n}").unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::InvalidJump);
+    }
+
+    #[test]
+    fn test_runtime_error_trace() {
+        let input = r#"
+fun add(a, b) {
+    return a - b;
+}
+fun outer() {
+    return add(1, "x");
+}
+outer();
+"#;
+        let err = evaluate(input).unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::TypeError);
+        let trace: Vec<(&str, usize)> = err
+            .trace
+            .iter()
+            .map(|frame| (frame.function.as_str(), frame.call.line))
+            .collect();
+        assert_eq!(trace, vec![("outer", 8), ("add", 6)]);
+        assert_eq!(
+            err.to_string(),
+            "Operands must be numbers.\n[line 3] in add()\n[line 6] in outer()\n[line 8] in script"
+        );
+    }
+
+    #[test]
+    fn test_inheritance() {
+        let input = r#"
+class A {
+    var name = "a";
+
+    fun init(n) {
+        this.n = n;
+    }
+
+    fun describe() {
+        return this.name + this.kind();
+    }
+
+    fun kind() {
+        return "A";
+    }
+}
+
+class B < A {
+    fun init(n, m) {
+        super.init(n);
+        this.m = m;
+    }
+
+    fun kind() {
+        return "B" + super.kind();
+    }
+}
+
+class C < B {}
+
+var c = new C(1, 2);
+c.describe() + c.kind()
+"#;
+        assert_eq!(evaluate_value(input), Object::String("aBABA".to_string()));
+
+        let input = r#"
+class A {
+    fun init(n) {
+        this.n = n;
+    }
+}
+
+class B < A {
+    fun sum(m) {
+        return this.n + m;
+    }
+}
+
+var b = new B(3);
+b.sum(4)
+"#;
+        assert_eq!(evaluate_value(input), Object::Number(7.0));
+
+        let err = evaluate("var A = 1;\nclass B < A {}").unwrap_err();
+        assert_eq!(err.message, "Superclass must be a class.");
+
+        let err = evaluate(
+            "class A {\n  fun f() {\n    return super.f();\n  }\n}\nvar a = new A();\na.f();",
+        )
+        .unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::InvalidThis);
+        assert_eq!(
+            err.message,
+            "Can't use 'super' in a class with no superclass."
+        );
+    }
+
+    #[test]
+    fn test_property_access() {
+        let input = r#"
+class Node {
+    fun init(value, next) {
+        this.value = value;
+        this.next = next;
+    }
+
+    fun last() {
+        if (this.next == nil) {
+            return this;
+        }
+        return this.next.last();
+    }
+
+    fun get() {
+        return this.value;
+    }
+}
+
+fun make() {
+    return new Node(1, new Node(2, new Node(3, nil)));
+}
+
+var list = [make()];
+var a = make();
+a.next.next.value = 4;
+var f = list[0].last().get;
+a.next.next.value + make().next.value + list[0].last().get() + f()
+"#;
+        assert_eq!(evaluate_value(input), Object::Number(12.0));
+
+        let input = r#"
+class Counter {
+    var n = 0;
+
+    fun incr() {
+        this.n = this.n + 1;
+        return this;
+    }
+}
+
+var c = new Counter();
+var incr = c.incr;
+incr();
+incr().incr();
+c.n
+"#;
+        assert_eq!(evaluate_value(input), Object::Number(3.0));
+
+        let err = evaluate("var a = 1;\na.b;").unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::TypeError);
+        assert_eq!(err.message, "Only instances have properties.");
+
+        let err = evaluate("var a = \"s\";\na.b = 1;").unwrap_err();
+        assert_eq!(err.message, "Only instances have fields.");
+    }
+
+    #[test]
+    fn test_closures() {
+        let input = r#"
+fun counter() {
+    var n = 0;
+    fun incr() {
+        n = n + 1;
+        return n;
+    }
+    return incr;
+}
+
+var a = counter();
+var b = counter();
+a();
+a();
+b();
+a() * 10 + b()
+"#;
+        assert_eq!(evaluate_value(input), Object::Number(32.0));
+
+        // show keeps reading the global a, even after the block declares its own
+        let input = r#"
+var a = "global";
+var seen = "";
+{
+    fun show() {
+        seen = seen + a;
+    }
+    show();
+    var a = "block";
+    show();
+}
+seen
+"#;
+        assert_eq!(
+            evaluate_value(input),
+            Object::String("globalglobal".to_string())
+        );
+
+        let input = r#"
+fun apply(f, x) {
+    var n = 100;
+    return f(x);
+}
+
+fun adder(n) {
+    return fun (x) {
+        return x + n;
+    };
+}
+
+apply(adder(2), 3)
+"#;
+        assert_eq!(evaluate_value(input), Object::Number(5.0));
+
+        let err = evaluate("fun f() {\n  x = 1;\n}\nf();").unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::UndefinedVariable);
     }
 }
@@ -2,8 +2,10 @@ use std::{collections::HashMap, fs, path::PathBuf};
 
 use crate::{
     ast::{self, Program, Stmt},
+    diagnostic::{Diagnostic, IMPORT_FAILED, IMPORT_NOT_FOUND},
     lexer::Lexing,
     parser::Parser,
+    span::SourceMap,
 };
 
 pub struct Imports {
@@ -21,8 +23,15 @@ impl Imports {
         }
     }
 
-    pub fn load(&mut self) -> Program{
-        let imports = self.load_all(self.program.clone());
+    // load returns the program with every imported file prepended, or the
+    // diagnostics explaining why an import could not be loaded. Imported
+    // sources are added to sources so their diagnostics can be rendered.
+    pub fn load(&mut self, sources: &mut SourceMap) -> Result<Program, Vec<Diagnostic>> {
+        let mut errors = vec![];
+        let imports = self.load_all(self.program.clone(), sources, &mut errors);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
         if let Some(imports) = imports {
             for (k, v) in imports {
                 self.insert(k, v);
@@ -33,45 +42,67 @@ impl Imports {
             progs.extend(v);
         }
         progs.extend(self.program.clone());
-        progs
+        Ok(progs)
     }
 
-    fn load_all(&mut self, program: ast::Program) -> Option<HashMap<String, Program>> {
-        let imports = program.iter().filter(|stmt| match stmt {
-            Stmt::Import(_) => true,
-            _ => false,
-        });
+    fn load_all(
+        &mut self,
+        program: ast::Program,
+        sources: &mut SourceMap,
+        errors: &mut Vec<Diagnostic>,
+    ) -> Option<HashMap<String, Program>> {
+        let imports = program
+            .iter()
+            .filter(|stmt| matches!(stmt.node, Stmt::Import(_)));
         if imports.clone().count() == 0 {
             return None;
         }
         let mut progs = HashMap::new();
         for import in imports.clone() {
-            match import {
-                Stmt::Import(s) => {
-                    let filename = format!("{}.lox", s);
-                    let file_contents =
-                        fs::read_to_string(self.current_dir.join(filename.clone())).unwrap();
-                    if file_contents.is_empty() {
-                        panic!("Error reading file: {}", filename.clone());
-                    }
-                    if self.imports.contains_key(&filename) {
+            if let Stmt::Import(s) = &import.node {
+                let filename = format!("{}.lox", s);
+                let path = self.current_dir.join(filename.clone());
+                let file_contents = match fs::read_to_string(&path) {
+                    Ok(contents) if !contents.is_empty() => contents,
+                    _ => {
+                        errors.push(
+                            Diagnostic::error(
+                                IMPORT_NOT_FOUND,
+                                &format!("Cannot read imported file '{}'.", filename),
+                                import.span,
+                            )
+                            .with_label("imported here")
+                            .with_note(&format!("looked for {}", path.display())),
+                        );
                         continue;
                     }
-                    let lex = Lexing::new(&file_contents);
-                    let mut parse = Parser::new(lex);
-                    let program = parse.parse();
-                    if parse.has_errors() {
-                        panic!("Error parsing file: {}", filename);
-                    }
-                    let imports = self.load_all(program.clone());
-                    if let Some(imports) = imports {
-                        for (k, v) in imports {
-                            progs.insert(k, v);
-                        }
+                };
+                if self.imports.contains_key(&filename) {
+                    continue;
+                }
+                let file = sources.add(&path.display().to_string(), &file_contents);
+                let lex = Lexing::with_file(&file_contents, file);
+                let mut parse = Parser::new(lex);
+                let program = parse.parse();
+                if parse.has_errors() {
+                    errors.push(
+                        Diagnostic::error(
+                            IMPORT_FAILED,
+                            &format!("Imported file '{}' has errors.", filename),
+                            import.span,
+                        )
+                        .with_label("imported here"),
+                    );
+                    errors.extend(parse.diagnostics().iter().cloned());
+                    continue;
+                }
+                let imports = self.load_all(program.clone(), sources, errors);
+                if let Some(imports) = imports {
+                    for (k, v) in imports {
+                        progs.insert(k, v);
                     }
-                    progs.insert(filename.clone(), program);
                 }
-                _ => {}
+                progs.insert(filename.clone(), program);
             }
         }
         Some(progs)
@@ -1,3 +1,8 @@
+use crate::diagnostic::{
+    Diagnostic, SYNTAX_ERROR, UNEXPECTED_CHARACTER, UNKNOWN_ESCAPE, UNKNOWN_KEYWORD,
+    UNTERMINATED_STRING,
+};
+use crate::span::{FileId, Span, Spanned};
 use crate::token::Token;
 use std::collections::HashMap;
 use std::str::Chars;
@@ -7,12 +12,20 @@ pub struct Lexing<'a> {
     position: usize,
     l: usize,
     lines: usize,
-    pub errors: Vec<String>,
+    file: FileId,
+    offset: usize, // byte offset of the next char
+    column: usize, // 0-based column of the next char
+    start: Span,   // where the token being scanned begins
+    pub errors: Vec<Diagnostic>,
     keywords: HashMap<&'static str, Token>,
 }
 
 impl<'a> Lexing<'a> {
-    pub fn new(input: &str) -> Lexing {
+    pub fn new(input: &str) -> Lexing<'_> {
+        Lexing::with_file(input, FileId::default())
+    }
+
+    pub fn with_file(input: &str, file: FileId) -> Lexing<'_> {
         let l = input.chars().count();
         let input = input.chars();
         Lexing {
@@ -20,6 +33,10 @@ impl<'a> Lexing<'a> {
             position: 0,
             l,
             lines: 0,
+            file,
+            offset: 0,
+            column: 0,
+            start: Span::new(file, 0, 0, 1, 1),
             errors: Vec::new(),
             keywords: HashMap::from([
                 ("var", Token::Var),
@@ -45,6 +62,8 @@ impl<'a> Lexing<'a> {
                 ("in", Token::In),
                 ("import", Token::Import),
                 ("assert", Token::Assert),
+                ("break", Token::Break),
+                ("continue", Token::Continue),
             ]),
         }
     }
@@ -56,32 +75,67 @@ impl<'a> Lexing<'a> {
     fn get_char(&mut self) -> char {
         self.position += 1;
         let c = self.input.nth(0).unwrap();
+        self.offset += c.len_utf8();
         if c == '\n' {
             self.lines += 1;
+            self.column = 0;
+        } else {
+            self.column += 1;
         }
         c
     }
 
+    // here returns an empty span at the next char.
+    fn here(&self) -> Span {
+        Span::new(self.file, self.offset, 0, self.lines + 1, self.column + 1)
+    }
+
+    fn mark_start(&mut self) {
+        self.start = self.here();
+    }
+
+    fn span_from(&self, start: Span) -> Span {
+        Span {
+            len: self.offset - start.offset,
+            ..start
+        }
+    }
+
+    // span returns the span of the token that has just been scanned.
+    fn span(&self) -> Span {
+        self.span_from(self.start)
+    }
+
     fn peek(&mut self) -> char {
         if self.l > self.position {
             return self.input.clone().nth(0).unwrap();
         }
-        return '\0';
+        '\0'
     }
 
     fn peek_n(&mut self, n: usize) -> char {
         if self.l > self.position + n - 1 {
             return self.input.clone().nth(n - 1).unwrap();
         }
-        return '\0';
+        '\0'
     }
 
     pub fn has_errors(&self) -> bool {
-        self.errors.len() > 0
+        !self.errors.is_empty()
+    }
+
+    pub fn next_kind(&mut self) -> Token {
+        self.next_token().node
+    }
+
+    pub fn next_token(&mut self) -> Spanned<Token> {
+        let token = self.scan();
+        Spanned::new(token, self.span())
     }
 
-    pub fn next(&mut self) -> Token {
+    fn scan(&mut self) -> Token {
         while self.l > self.position {
+            self.mark_start();
             let c = self.peek();
             match c {
                 ' ' | '\n' | '\t' => {
@@ -146,6 +200,7 @@ impl<'a> Lexing<'a> {
                     while self.l > self.position {
                         let mut c = self.peek();
                         if c == '\\' {
+                            let escape = self.here();
                             self.get_char();
                             let c = self.get_char();
                             if c == '\'' {
@@ -160,19 +215,12 @@ impl<'a> Lexing<'a> {
                                 s.push('\0');
                             } else if c == '\\' {
                                 s.push('\\');
-                            } else if c == '\'' {
-                                s.push('\'');
                             } else {
-                                self.errors.push(format!(
-                                    "[line {}] Error: Unknown escape character: {}",
-                                    self.lines + 1,
-                                    c
+                                self.errors.push(Diagnostic::error(
+                                    UNKNOWN_ESCAPE,
+                                    &format!("Unknown escape character: {}", c),
+                                    self.span_from(escape),
                                 ));
-                                eprintln!(
-                                    "[line {}] Error: Unknown escape character: {}",
-                                    self.lines + 1,
-                                    c
-                                );
                             }
                         }
                         c = self.peek();
@@ -182,11 +230,10 @@ impl<'a> Lexing<'a> {
                         }
                         s.push(self.get_char());
                     }
-                    self.errors.push(format!(
-                        "[line {}] Error: Unterminated string.",
-                        self.lines + 1
-                    ));
-                    eprintln!("[line {}] Error: Unterminated string.", self.lines + 1);
+                    self.errors.push(
+                        Diagnostic::error(UNTERMINATED_STRING, "Unterminated string.", self.span())
+                            .with_label("string starts here"),
+                    );
                 }
                 '"' => {
                     let mut s = String::new();
@@ -194,6 +241,7 @@ impl<'a> Lexing<'a> {
                     while self.l > self.position {
                         let mut c = self.peek();
                         if c == '\\' {
+                            let escape = self.here();
                             self.get_char();
                             let c = self.get_char();
                             if c == '"' {
@@ -211,16 +259,11 @@ impl<'a> Lexing<'a> {
                             } else if c == '\'' {
                                 s.push('\'');
                             } else {
-                                self.errors.push(format!(
-                                    "[line {}] Error: Unknown escape character: {}",
-                                    self.lines + 1,
-                                    c
+                                self.errors.push(Diagnostic::error(
+                                    UNKNOWN_ESCAPE,
+                                    &format!("Unknown escape character: {}", c),
+                                    self.span_from(escape),
                                 ));
-                                eprintln!(
-                                    "[line {}] Error: Unknown escape character: {}",
-                                    self.lines + 1,
-                                    c
-                                );
                             }
                         }
                         c = self.peek();
@@ -230,25 +273,25 @@ impl<'a> Lexing<'a> {
                         }
                         s.push(self.get_char());
                     }
-                    self.errors.push(format!(
-                        "[line {}] Error: Unterminated string.",
-                        self.lines + 1
-                    ));
-                    eprintln!("[line {}] Error: Unterminated string.", self.lines + 1);
+                    self.errors.push(
+                        Diagnostic::error(UNTERMINATED_STRING, "Unterminated string.", self.span())
+                            .with_label("string starts here"),
+                    );
                 }
                 '0'..='9' => {
                     let mut s = String::new();
                     while self.l > self.position {
                         let c = self.peek();
-                        if c.is_numeric() {
-                            s.push(self.get_char());
-                        } else if c == '.' && self.peek_n(2).is_numeric() {
+                        if c.is_numeric() || (c == '.' && self.peek_n(2).is_numeric()) {
                             s.push(self.get_char());
                         } else {
                             break;
                         }
                     }
-                    return Token::Number(s);
+                    if s.contains('.') {
+                        return Token::Number(s);
+                    }
+                    return Token::Integer(s);
                 }
                 '(' => {
                     self.get_char();
@@ -358,16 +401,11 @@ impl<'a> Lexing<'a> {
                         match self.keywords.get(s.to_lowercase().as_str()) {
                             Some(t) => return t.clone(),
                             None => {
-                                self.errors.push(format!(
-                                    "[line {}] Error: Unknown keyword: {}",
-                                    self.lines + 1,
-                                    s
+                                self.errors.push(Diagnostic::error(
+                                    UNKNOWN_KEYWORD,
+                                    &format!("Unknown keyword: {}", s),
+                                    self.span(),
                                 ));
-                                eprintln!(
-                                    "[line {}] Error: Unknown keyword: {}",
-                                    self.lines + 1,
-                                    s
-                                );
                             }
                         }
                     } else {
@@ -376,41 +414,31 @@ impl<'a> Lexing<'a> {
                 }
                 _ => {
                     let c = self.get_char();
-                    self.errors.push(format!(
-                        "[line {}] Error: Unexpected character: {}",
-                        self.lines + 1,
-                        c
+                    self.errors.push(Diagnostic::error(
+                        UNEXPECTED_CHARACTER,
+                        &format!("Unexpected character: {}", c),
+                        self.span(),
                     ));
-                    eprintln!(
-                        "[line {}] Error: Unexpected character: {}",
-                        self.lines + 1,
-                        c
-                    );
                 }
             }
         }
-        return Token::Eof;
+        self.mark_start();
+        Token::Eof
     }
 
-    pub fn log_error(&mut self, token: Token, message: &str) {
-        let token = match token {
-            Token::Identifier(s) => s,
-            Token::String(s) => s,
-            Token::Number(s) => s,
-            Token::RightParen => ")".to_string(),
-            _ => "".to_string(),
+    pub fn log_error(&mut self, token: Token, span: Span, message: &str) {
+        let found = match token {
+            Token::Eof => "end of file".to_string(),
+            Token::Identifier(s) | Token::Number(s) | Token::Integer(s) => format!("'{}'", s),
+            Token::String(s) => format!("'\"{}\"'", s),
+            token => match token.to_string().split(' ').nth(1) {
+                Some(lexeme) if !lexeme.is_empty() => format!("'{}'", lexeme),
+                _ => format!("{:?}", token),
+            },
         };
-        self.errors.push(format!(
-            "[line {}] Error at '{}': {}.",
-            self.lines + 1,
-            token,
-            message
-        ));
-        eprintln!(
-            "[line {}] Error at '{}': {}.",
-            self.lines + 1,
-            token,
-            message
+        self.errors.push(
+            Diagnostic::error(SYNTAX_ERROR, &format!("{}.", message), span)
+                .with_label(&format!("found {}", found)),
         );
     }
 }
@@ -425,7 +453,7 @@ mod test {
         let mut lex = Lexing::new(input);
         let mut tokens = Vec::new();
         loop {
-            let tok = lex.next();
+            let tok = lex.next_kind();
             tokens.push(tok.clone());
             if tok == Token::Eof {
                 break;
@@ -437,10 +465,42 @@ mod test {
                 Token::Var,
                 Token::Identifier("a".to_string()),
                 Token::Equal,
-                Token::Number("10".to_string()),
+                Token::Integer("10".to_string()),
                 Token::Semicolon,
                 Token::Eof,
             ]
         );
     }
+
+    #[test]
+    fn test_token_spans() {
+        use crate::lexer::Lexing;
+        use crate::span::Span;
+        use crate::token::Token;
+        let input = "var a = 10;\n  print \"hi\";";
+        let mut lex = Lexing::new(input);
+        let mut spans = Vec::new();
+        loop {
+            let tok = lex.next_token();
+            spans.push((tok.span.line, tok.span.column, tok.span.offset, tok.span.len));
+            if tok.node == Token::Eof {
+                break;
+            }
+        }
+        assert_eq!(
+            spans,
+            vec![
+                (1, 1, 0, 3),
+                (1, 5, 4, 1),
+                (1, 7, 6, 1),
+                (1, 9, 8, 2),
+                (1, 11, 10, 1),
+                (2, 3, 14, 5),
+                (2, 9, 20, 4),
+                (2, 13, 24, 1),
+                (2, 14, 25, 0),
+            ]
+        );
+        assert_eq!(Span::default().to(Span::default()).len, 0);
+    }
 }
@@ -12,4 +12,13 @@ pub mod opcode;
 pub mod symbol;
 pub mod vm;
 pub mod frame;
-pub mod callstack;
\ No newline at end of file
+pub mod callstack;
+pub mod span;
+pub mod diagnostic;
+pub mod error;
+pub mod repl;
+pub mod resolver;
+pub mod output;
+pub mod bytecode;
+pub mod lines;
+pub mod disasm;
@@ -0,0 +1,79 @@
+// LineTable maps instructions to the source line and column they were
+// compiled from. Consecutive instructions from the same place share one run
+// of (count, line, column).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LineTable {
+    runs: Vec<(usize, usize, usize)>,
+}
+
+impl LineTable {
+    pub fn new() -> Self {
+        LineTable { runs: vec![] }
+    }
+
+    pub fn from_runs(runs: Vec<(usize, usize, usize)>) -> Self {
+        LineTable { runs }
+    }
+
+    pub fn runs(&self) -> &[(usize, usize, usize)] {
+        &self.runs
+    }
+
+    // push records the line and column of the next instruction.
+    pub fn push(&mut self, line: usize, column: usize) {
+        match self.runs.last_mut() {
+            Some((count, l, c)) if (*l, *c) == (line, column) => *count += 1,
+            _ => self.runs.push((1, line, column)),
+        }
+    }
+
+    // extend appends the lines of instructions placed after these.
+    pub fn extend(&mut self, other: &LineTable) {
+        for &(count, line, column) in &other.runs {
+            match self.runs.last_mut() {
+                Some((last_count, l, c)) if (*l, *c) == (line, column) => *last_count += count,
+                _ => self.runs.push((count, line, column)),
+            }
+        }
+    }
+
+    // position returns the line and column of the instruction at ip.
+    pub fn position(&self, ip: usize) -> Option<(usize, usize)> {
+        let mut end = 0;
+        for &(count, line, column) in &self.runs {
+            end += count;
+            if ip < end {
+                return Some((line, column));
+            }
+        }
+        None
+    }
+
+    pub fn line(&self, ip: usize) -> Option<usize> {
+        self.position(ip).map(|(line, _)| line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_table() {
+        let mut lines = LineTable::new();
+        for (line, column) in [(1, 1), (1, 1), (2, 3), (2, 3), (2, 5), (4, 1)] {
+            lines.push(line, column);
+        }
+        assert_eq!(lines.runs(), &[(2, 1, 1), (2, 2, 3), (1, 2, 5), (1, 4, 1)]);
+        let mut more = LineTable::new();
+        more.push(4, 1);
+        more.push(5, 2);
+        lines.extend(&more);
+        assert_eq!(lines.runs(), &[(2, 1, 1), (2, 2, 3), (1, 2, 5), (2, 4, 1), (1, 5, 2)]);
+        assert_eq!(lines.line(0), Some(1));
+        assert_eq!(lines.position(4), Some((2, 5)));
+        assert_eq!(lines.line(6), Some(4));
+        assert_eq!(lines.position(7), Some((5, 2)));
+        assert_eq!(lines.line(8), None);
+    }
+}
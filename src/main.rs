@@ -1,115 +1,200 @@
 use std::env;
 use std::fs;
-use std::io::{self, Write};
 use std::path::Path;
 use std::process::exit;
+use std::thread;
 
+use codecrafters_interpreter::ast::Program;
+use codecrafters_interpreter::bytecode::Bytecode;
 use codecrafters_interpreter::compiler::Compiler;
-use codecrafters_interpreter::evaluator::Evaluator;
+use codecrafters_interpreter::diagnostic::Diagnostic;
+use codecrafters_interpreter::disasm::disassemble;
+use codecrafters_interpreter::error::RuntimeError;
+use codecrafters_interpreter::evaluator::{Evaluator, STACK_SIZE};
 use codecrafters_interpreter::imports::Imports;
 use codecrafters_interpreter::lexer::Lexing;
-use codecrafters_interpreter::opcode::Opcode;
 use codecrafters_interpreter::parser::Parser;
+use codecrafters_interpreter::repl::Repl;
+use codecrafters_interpreter::resolver::{Locals, Resolver};
+use codecrafters_interpreter::span::SourceMap;
 use codecrafters_interpreter::token::Token;
 use codecrafters_interpreter::vm::VM;
 
+// Format is how a command writes errors to stderr. The codecrafters commands,
+// tokenize, parse and evaluate, keep the `[line N] Error: ...` lines the
+// course tester reads, the others quote the source.
+#[derive(Clone, Copy)]
+enum Format {
+    Line,
+    Rendered,
+}
+
+fn report(diagnostics: &[Diagnostic], sources: &SourceMap, format: Format) {
+    for diagnostic in diagnostics {
+        match format {
+            Format::Line => eprintln!("{}", diagnostic),
+            Format::Rendered => eprint!("{}", diagnostic.render(sources)),
+        }
+    }
+}
+
+fn report_runtime_error(err: &RuntimeError, sources: &SourceMap, format: Format) {
+    match format {
+        Format::Line => eprintln!("{}", err),
+        Format::Rendered => report(&[err.diagnostic()], sources, format),
+    }
+}
+
+// parse_source parses the entry file, reporting any errors and exiting with 65.
+fn parse_source(filename: &str, source: &str, sources: &mut SourceMap, format: Format) -> Program {
+    let file = sources.add(filename, source);
+    let lex = Lexing::with_file(source, file);
+    let mut parse = Parser::new(lex);
+    let program = parse.parse();
+    if parse.has_errors() {
+        report(parse.diagnostics(), sources, format);
+        exit(65);
+    }
+    program
+}
+
+// resolve checks the program before either backend runs it, reporting any
+// errors and exiting with 65. Both backends look locals up by the depths it
+// returns.
+fn resolve(program: &Program, sources: &SourceMap, format: Format) -> Locals {
+    let mut resolver = Resolver::new();
+    let locals = resolver.resolve(program);
+    if resolver.has_errors() {
+        report(resolver.diagnostics(), sources, format);
+        exit(65);
+    }
+    locals
+}
+
+// compile translates the program to bytecode, reporting what the compiler
+// can't translate and exiting with 65.
+fn compile(program: Program, locals: Locals, sources: &SourceMap) -> Compiler {
+    let mut compiler = Compiler::new(program, locals);
+    compiler.compile();
+    if compiler.has_errors() {
+        report(compiler.diagnostics(), sources, Format::Rendered);
+        exit(65);
+    }
+    compiler
+}
+
+// execute runs compiled bytecode on the vm, exiting with 70 on a runtime error.
+// sources are the files it was compiled from, if they are at hand.
+fn execute(bytecode: Bytecode, sources: &SourceMap) {
+    let mut vm = VM::new((bytecode.main_start, bytecode.instructions.iter().collect()));
+    vm.define_constants(bytecode.constants);
+    vm.define_functions(&bytecode.functions);
+    if let Some(lines) = bytecode.lines {
+        vm.define_lines(lines);
+    }
+    // like run, only what the program prints goes to stdout
+    if let Err(err) = vm.run() {
+        report_runtime_error(&err, sources, Format::Rendered);
+        exit(70);
+    }
+}
+
 fn main() {
+    // the evaluator recurses on every Lox call, give it room to
+    let interpreter = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(interpret)
+        .unwrap();
+    if interpreter.join().is_err() {
+        exit(101);
+    }
+}
+
+fn interpret() {
     let args: Vec<String> = env::args().collect();
+    if args.len() == 2 && args[1] == "repl" {
+        if let Err(err) = Repl::new().run() {
+            eprintln!("repl: {}", err);
+            exit(74);
+        }
+        return;
+    }
     if args.len() < 3 {
-        writeln!(io::stderr(), "Usage: {} tokenize <filename>", args[0]).unwrap();
+        eprintln!("Usage: {} tokenize <filename>", args[0]);
         return;
     }
 
     let command = &args[1];
-    let filename = &args[2];
+    // compile -o <out.loxc> <file> writes the bytecode instead of running it
+    let (output, filename) = match (command.as_str(), args[2].as_str()) {
+        ("compile", "-o") if args.len() > 4 => (Some(&args[3]), &args[4]),
+        _ => (None, &args[2]),
+    };
+
+    if command == "exec" {
+        let bytes = fs::read(filename).unwrap_or_else(|err| {
+            eprintln!("Failed to read file {}: {}", filename, err);
+            exit(66);
+        });
+        match Bytecode::decode(&bytes) {
+            Ok(bytecode) => execute(bytecode, &SourceMap::new()),
+            Err(err) => {
+                eprintln!("{}: {}", filename, err);
+                exit(65);
+            }
+        }
+        return;
+    }
 
     let file_contents = fs::read_to_string(filename).unwrap_or_else(|_| {
-        writeln!(io::stderr(), "Failed to read file {}", filename).unwrap();
+        eprintln!("Failed to read file {}", filename);
         String::new()
     });
 
     if file_contents.is_empty() {
-        writeln!(io::stderr(), "File is empty").unwrap();
+        eprintln!("File is empty");
         return;
     }
 
     match command.as_str() {
         "dump" => {
-            let lex = Lexing::new(&file_contents);
-            let mut parse = Parser::new(lex);
-            let program = parse.parse();
-            if parse.has_errors() {
-                exit(65);
-            }
-            let mut compile = Compiler::new(program);
-            compile.compile();
-
-            println!("symbols:");
-            for (i, symbol) in compile.symbols.borrow().store.iter().enumerate() {
-                println!("\t{:04} {:?}", i, symbol);
-            }
-
-            println!("\nconstants:");
-            for (i, constant) in compile.constants.iter().enumerate() {
-                match constant {
-                    // Object::CompiledFunction { instructions, num_locals, num_parameters } => {
-                    //     println!("\t{:04}", i, constant);
-                    //     // for (j, instruction) in instructions.iter().enumerate() {
-                    //     //     println!("\t\t{:04} {:?}", j, instruction);
-                    //     // }
-                    // }
-                    _ => println!("\t{:04} {:?}", i, constant)
-                }
-            }
-
-            println!("\nclosures:");
-            for (i, closure) in compile.closure_ins.iter().enumerate() {
-                println!("\t{:04} {:?}", i, closure);
-            }
-
-            println!("\ninstructions:");
-            for (i, instruction) in compile.instructions.iter().enumerate() {
-                match instruction {
-                    Opcode::GetBuiltin(index) => {
-                        println!("\t{:04} {:?}\t\t# {:020}", i, instruction, compile.builtins.get_name(*index).unwrap());
-                    }
-                    Opcode::LoadConstant(index) => {
-                        println!("\t{:04} {:?}\t\t# {:?}", i, instruction, compile.constants[*index]);
-                    }
-                    _ =>  println!("\t{:04} {:?}", i, instruction)
-                }
-            }
-
+            let mut sources = SourceMap::new();
+            let program = parse_source(filename, &file_contents, &mut sources, Format::Rendered);
+            let locals = resolve(&program, &sources, Format::Rendered);
+            let compiler = compile(program, locals, &sources);
+            let globals = compiler.symbols.borrow();
+            print!("{}", disassemble(&Bytecode::new(&compiler), &globals));
         }
         "compile" => {
-            let lex = Lexing::new(&file_contents);
-            let mut parse = Parser::new(lex);
-            let program = parse.parse();
-            if parse.has_errors() {
-                exit(65);
+            let mut sources = SourceMap::new();
+            let program = parse_source(filename, &file_contents, &mut sources, Format::Rendered);
+            let locals = resolve(&program, &sources, Format::Rendered);
+            let compiler = compile(program, locals, &sources);
+            let bytecode = Bytecode::new(&compiler);
+            match output {
+                Some(output) => {
+                    let bytes = bytecode.encode().unwrap_or_else(|err| {
+                        eprintln!("{}", err);
+                        exit(70);
+                    });
+                    if let Err(err) = fs::write(output, bytes) {
+                        eprintln!("Failed to write file {}: {}", output, err);
+                        exit(74);
+                    }
+                }
+                None => execute(bytecode, &sources),
             }
-            let mut compiler = Compiler::new(program);
-            compiler.compile();
-            let (l, codes) = compiler.get_instructions();
-            let mut vm = VM::new((l, codes.iter().map(|x|x).collect()));
-            vm.define_constants(compiler.constants);
-            let result = vm.run();
-            println!("result: {:?}", result);
         }
         "parse" => {
             let file_contents = fs::read_to_string(filename).unwrap_or_else(|_| {
-                writeln!(io::stderr(), "Failed to read file {}", filename).unwrap();
+                eprintln!("Failed to read file {}", filename);
                 String::new()
             });
 
             // Uncomment this block to pass the first stage
             if !file_contents.is_empty() {
-                let lex = Lexing::new(&file_contents);
-                let mut parse = Parser::new(lex);
-                let program = parse.parse();
-                if parse.has_errors() {
-                    exit(65);
-                }
+                let mut sources = SourceMap::new();
+                let program = parse_source(filename, &file_contents, &mut sources, Format::Line);
                 for stmt in &program {
                     println!("{}", stmt);
                 }
@@ -119,60 +204,69 @@ fn main() {
         }
         "evaluate" => {
             let file_contents = fs::read_to_string(filename).unwrap_or_else(|_| {
-                writeln!(io::stderr(), "Failed to read file {}", filename).unwrap();
+                eprintln!("Failed to read file {}", filename);
                 String::new()
             });
 
             // Uncomment this block to pass the first stage
             if !file_contents.is_empty() {
-                let lex = Lexing::new(&file_contents);
-                let mut parse = Parser::new(lex);
-                let program = parse.parse();
-                if parse.has_errors() {
-                    exit(65);
-                }
+                let mut sources = SourceMap::new();
+                let program = parse_source(filename, &file_contents, &mut sources, Format::Line);
+                let locals = resolve(&program, &sources, Format::Line);
                 let mut evaluator = Evaluator::new(program, true);
-                evaluator.evaluate();
+                evaluator.define_locals(locals);
+                if let Err(err) = evaluator.evaluate() {
+                    report_runtime_error(&err, &sources, Format::Line);
+                    exit(70);
+                }
             } else {
                 println!("EOF  null"); // Placeholder, remove this line when implementing the scanner
             }
         }
         "run" => {
             let file_contents = fs::read_to_string(filename).unwrap_or_else(|_| {
-                writeln!(io::stderr(), "Failed to read file {}", filename).unwrap();
+                eprintln!("Failed to read file {}", filename);
                 String::new()
             });
 
             let file_current_dir = Path::new(filename).parent().unwrap();
             // Uncomment this block to pass the first stage
             if !file_contents.is_empty() {
-                let lex = Lexing::new(&file_contents);
-                let mut parse = Parser::new(lex);
-                let program = parse.parse();
-                if parse.has_errors() {
+                let mut sources = SourceMap::new();
+                let program =
+                    parse_source(filename, &file_contents, &mut sources, Format::Rendered);
+                let mut import = Imports::new(program, file_current_dir.to_path_buf());
+                let program = import.load(&mut sources).unwrap_or_else(|errors| {
+                    report(&errors, &sources, Format::Rendered);
                     exit(65);
+                });
+                let locals = resolve(&program, &sources, Format::Rendered);
+                let mut evaluator = Evaluator::new(program, false);
+                evaluator.define_locals(locals);
+                if let Err(err) = evaluator.evaluate() {
+                    report_runtime_error(&err, &sources, Format::Rendered);
+                    exit(70);
                 }
-                let mut import = Imports::new(program, file_current_dir.to_path_buf());
-                let mut evaluator = Evaluator::new(import.load(), false);
-                evaluator.evaluate();
             } else {
                 println!("EOF  null"); // Placeholder, remove this line when implementing the scanner
             }
         }
         "tokenize" => {
             // You can use print statements as follows for debugging, they'll be visible when running tests.
-            // writeln!(io::stderr(), "Logs from your program will appear here!").unwrap();
+            // eprintln!("Logs from your program will appear here!");
 
             let file_contents = fs::read_to_string(filename).unwrap_or_else(|_| {
-                writeln!(io::stderr(), "Failed to read file {}", filename).unwrap();
+                eprintln!("Failed to read file {}", filename);
                 String::new()
             });
 
             // Uncomment this block to pass the first stage
             if !file_contents.is_empty() {
-                let mut lex = Lexing::new(&file_contents);
+                let mut sources = SourceMap::new();
+                let file = sources.add(filename, &file_contents);
+                let mut lex = Lexing::with_file(&file_contents, file);
                 loop {
-                    let token = lex.next();
+                    let token = lex.next_kind();
                     match token {
                         Token::Eof => {
                             println!("{}", token);
@@ -185,7 +279,8 @@ fn main() {
                     }
                 }
                 let mut return_code = 0;
-                if lex.errors.len() > 0 {
+                if lex.has_errors() {
+                    report(&lex.errors, &sources, Format::Line);
                     return_code = 65;
                 }
                 exit(return_code);
@@ -194,8 +289,7 @@ fn main() {
             }
         }
         _ => {
-            writeln!(io::stderr(), "Unknown command: {}", command).unwrap();
-            return;
+            eprintln!("Unknown command: {}", command);
         }
     }
 }
@@ -1,29 +1,50 @@
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
 use std::{cell::RefCell, fmt::Display, rc::Rc};
 
+use indexmap::IndexMap;
+
 use crate::ast;
+use crate::envs::EnvRef;
 
 pub type BuiltinFunc = fn(Vec<Object>) -> Object;
 
-#[derive(PartialEq, Clone, Debug)]
+// HashObject is what a Lox hash holds. Printing it and for-in go over the
+// keys in the order they were first inserted, assigning to a key that is
+// already there keeps its place. Nothing removes keys yet; whatever does
+// should shift_remove so the keys after it keep their order, and a removed
+// key that is assigned again goes last.
+pub type HashObject = IndexMap<Object, Object>;
+
+#[derive(Clone, Debug)]
 pub enum Object {
     Boolean(bool),
     Nil,
     Number(f64),
-    Index(usize),
+    Int(i64),
     String(String),
-    Array(Vec<Object>),
+    // arrays are shared like hashes, a change made through one variable
+    // shows through every other that holds the same array
+    Array(Rc<RefCell<Vec<Object>>>),
     ReturnValue(Box<Object>),
-    Hash(Rc<RefCell<HashMap<Object, Object>>>),
+    Hash(Rc<RefCell<HashObject>>),
     Builtin(String, i32, BuiltinFunc),
-    Function(Vec<ast::Ident>, ast::BlockStmt),
-    Class(String, Vec<ast::Stmt>),
+    Function(Vec<ast::Ident>, ast::BlockStmt, EnvRef), // params, body, defining scope
+    Class(String, Vec<ast::StmtNode>, Option<Rc<Object>>, EnvRef), // name, body, superclass, defining scope
     ClassInstance {
         name: String,
+        class: Rc<Object>,
         fields: Rc<RefCell<HashMap<String, Object>>>,
         properties: Rc<RefCell<HashMap<String, Object>>>,
     },
+    // a method read off an instance, called with this bound to receiver
+    BoundMethod {
+        name: String,
+        receiver: Box<Object>,
+        class: Rc<Object>, // the class that defines the method
+        method: Box<Object>,
+    },
     CompiledFunction {
         start: usize,
         len: usize,
@@ -33,14 +54,99 @@ pub enum Object {
     Closure {
         func: Rc<Object>,
     },
+    // the items a for-in loop goes over in the VM and the index of the next one
+    Iterator(Rc<Vec<Object>>, usize),
+    // a class compiled to bytecode, its methods are CompiledFunctions that
+    // keep the instance in local 0
+    CompiledClass {
+        name: String,
+        superclass: Option<Rc<Object>>,
+        methods: Rc<RefCell<HashMap<String, Object>>>,
+    },
+}
+
+// Ints and floats compare by value, 1 == 1.0, instances by identity and
+// everything else the way it would derived. Builtins are the same builtin
+// when they share a name.
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        use Object::*;
+        match (self, other) {
+            (Boolean(l), Boolean(r)) => l == r,
+            (Nil, Nil) => true,
+            (Number(l), Number(r)) => l == r,
+            (Int(l), Int(r)) => l == r,
+            (Int(i), Number(n)) | (Number(n), Int(i)) => whole(*n) == Some(*i),
+            (String(l), String(r)) => l == r,
+            (Array(l), Array(r)) => l == r,
+            (ReturnValue(l), ReturnValue(r)) => l == r,
+            (Hash(l), Hash(r)) => l == r,
+            (Builtin(l, la, _), Builtin(r, ra, _)) => l == r && la == ra,
+            (Function(lp, lb, le), Function(rp, rb, re)) => lp == rp && lb == rb && le == re,
+            (Class(ln, lb, ls, le), Class(rn, rb, rs, re)) => {
+                ln == rn && lb == rb && ls == rs && le == re
+            }
+            (ClassInstance { fields: l, .. }, ClassInstance { fields: r, .. }) => Rc::ptr_eq(l, r),
+            (
+                BoundMethod {
+                    name: ln,
+                    receiver: lr,
+                    class: lc,
+                    method: lm,
+                },
+                BoundMethod {
+                    name: rn,
+                    receiver: rr,
+                    class: rc,
+                    method: rm,
+                },
+            ) => ln == rn && lr == rr && lc == rc && lm == rm,
+            (
+                CompiledFunction {
+                    start: ls,
+                    len: ll,
+                    num_locals: lo,
+                    num_parameters: lp,
+                },
+                CompiledFunction {
+                    start: rs,
+                    len: rl,
+                    num_locals: ro,
+                    num_parameters: rp,
+                },
+            ) => ls == rs && ll == rl && lo == ro && lp == rp,
+            (Closure { func: l }, Closure { func: r }) => l == r,
+            (Iterator(li, ln), Iterator(ri, rn)) => li == ri && ln == rn,
+            (
+                CompiledClass {
+                    name: ln,
+                    superclass: ls,
+                    methods: lm,
+                },
+                CompiledClass {
+                    name: rn,
+                    superclass: rs,
+                    methods: rm,
+                },
+            ) => ln == rn && ls == rs && lm == rm,
+            _ => false,
+        }
+    }
 }
 
 impl Eq for Object {}
 
+// A float with a whole value hashes like the int it equals, an instance by
+// where it lives. Only what key accepts is ever hashed.
 impl Hash for Object {
     fn hash<H: Hasher>(&self, state: &mut H) {
         match *self {
-            Object::Index(ref i) => i.hash(state),
+            Object::ClassInstance { ref fields, .. } => Rc::as_ptr(fields).hash(state),
+            Object::Int(ref i) => i.hash(state),
+            Object::Number(n) => match whole(n) {
+                Some(i) => i.hash(state),
+                None => n.to_bits().hash(state),
+            },
             Object::Boolean(ref b) => b.hash(state),
             Object::String(ref s) => s.hash(state),
             _ => "".hash(state),
@@ -56,7 +162,7 @@ impl Display for Object {
             Object::Number(n) => write!(f, "{}", n),
             Object::String(s) => write!(f, "{}", s),
             Object::Builtin(s, c, func) => write!(f, "builtin function: {} {} {:?}", s, c, func),
-            Object::Function(params, body) => {
+            Object::Function(params, body, _) => {
                 let mut params_str = String::new();
                 for (i, param) in params.iter().enumerate() {
                     if i > 0 {
@@ -69,7 +175,7 @@ impl Display for Object {
             Object::ReturnValue(obj) => write!(f, "{}", obj),
             Object::Array(elements) => {
                 let mut elements_str = String::new();
-                for (i, elem) in elements.iter().enumerate() {
+                for (i, elem) in elements.borrow().iter().enumerate() {
                     if i > 0 {
                         elements_str.push_str(", ");
                     }
@@ -84,9 +190,9 @@ impl Display for Object {
                 }
                 write!(f, "{{{}}}", hash_str)
             }
-            Object::Index(i) => write!(f, "{}", i),
-            Object::Class(name, properties) => {
-                write!(f, "class {} {{\n", name)?;
+            Object::Int(i) => write!(f, "{}", i),
+            Object::Class(name, properties, ..) => {
+                writeln!(f, "class {} {{", name)?;
                 for prop in properties {
                     writeln!(f, "\t{}", prop)?;
                 }
@@ -96,8 +202,9 @@ impl Display for Object {
                 name,
                 fields,
                 properties,
+                ..
             } => {
-                write!(f, "instance of class {} {{\n", name)?;
+                writeln!(f, "instance of class {} {{", name)?;
                 for (key, value) in fields.borrow().iter() {
                     writeln!(f, "\t{}: {}", key, value)?;
                 }
@@ -106,6 +213,12 @@ impl Display for Object {
                 }
                 write!(f, "}}")
             }
+            Object::BoundMethod { name, class, .. } => match class.as_ref() {
+                Object::Class(class, ..) | Object::CompiledClass { name: class, .. } => {
+                    write!(f, "bound method {}.{}", class, name)
+                }
+                _ => write!(f, "bound method {}", name),
+            },
             Object::CompiledFunction {
                 num_locals,
                 num_parameters,
@@ -121,6 +234,289 @@ impl Display for Object {
             Object::Closure { func } => {
                 write!(f, "closure of {:?}", func)
             }
+            Object::CompiledClass { name, .. } => write!(f, "class {}", name),
+            Object::Iterator(..) => write!(f, "iterator"),
+        }
+    }
+}
+
+impl Object {
+    pub fn is_number(&self) -> bool {
+        matches!(self, Object::Number(_) | Object::Int(_))
+    }
+
+    // number is the value of an int or a float as a float.
+    pub fn number(&self) -> Option<f64> {
+        match self {
+            Object::Number(n) => Some(*n),
+            Object::Int(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+
+    // key is self as a hash key. Nil, booleans, numbers, strings and
+    // instances are keys, a float with a whole value becomes the int it
+    // equals so 1 and 1.0 are one key. NaN never equals itself and the other
+    // values can change while they are in the hash, they are errors.
+    pub fn key(self) -> Result<Object, String> {
+        let what = match self {
+            Object::Number(n) if n.is_nan() => "NaN",
+            Object::Number(n) => return Ok(whole(n).map(Object::Int).unwrap_or(self)),
+            Object::Nil
+            | Object::Boolean(_)
+            | Object::Int(_)
+            | Object::String(_)
+            | Object::ClassInstance { .. } => return Ok(self),
+            Object::Array(_) => "an array",
+            Object::Hash(_) => "a hash",
+            Object::Class(..) | Object::CompiledClass { .. } => "a class",
+            Object::Builtin(..)
+            | Object::Function(..)
+            | Object::BoundMethod { .. }
+            | Object::CompiledFunction { .. }
+            | Object::Closure { .. } => "a function",
+            _ => "this value",
+        };
+        Err(format!("Can't use {} as a hash key.", what))
+    }
+
+    // negate is -self for numbers, -i64::MIN becomes a float.
+    pub fn negate(&self) -> Option<Object> {
+        match self {
+            Object::Int(i) => Some(match i.checked_neg() {
+                Some(i) => Object::Int(i),
+                None => Object::Number(-(*i as f64)),
+            }),
+            Object::Number(n) => Some(Object::Number(-n)),
+            _ => None,
+        }
+    }
+}
+
+// is_truthy follows Lox: nil and false are falsy, everything else is truthy.
+// Both backends decide conditions, `!`, `and` and `or` with it.
+pub fn is_truthy(obj: &Object) -> bool {
+    !matches!(obj, Object::Nil | Object::Boolean(false))
+}
+
+// is_equal is Lox `==`: numbers, booleans, strings and nil compare by value,
+// arrays, hashes, instances, classes and functions by identity. Values of
+// different types are never equal.
+pub fn is_equal(left: &Object, right: &Object) -> bool {
+    use Object::*;
+    match (left, right) {
+        (l, r) if l.is_number() && r.is_number() => l == r,
+        (Boolean(l), Boolean(r)) => l == r,
+        (String(l), String(r)) => l == r,
+        (Nil, Nil) => true,
+        (Array(l), Array(r)) => Rc::ptr_eq(l, r),
+        (Hash(l), Hash(r)) => Rc::ptr_eq(l, r),
+        (CompiledClass { methods: l, .. }, CompiledClass { methods: r, .. }) => Rc::ptr_eq(l, r),
+        // instances by their fields, functions and classes by their
+        // declaration and defining scope
+        (ClassInstance { .. }, ClassInstance { .. })
+        | (Function(..), Function(..))
+        | (Class(..), Class(..))
+        | (Builtin(..), Builtin(..))
+        | (CompiledFunction { .. }, CompiledFunction { .. })
+        | (Closure { .. }, Closure { .. }) => left == right,
+        _ => false,
+    }
+}
+
+// position is index as a position in an array or string, None when it isn't
+// a number. A negative or fractional number is usize::MAX, out of bounds of
+// anything.
+pub fn position(index: &Object) -> Option<usize> {
+    let i = match index {
+        Object::Int(i) => *i,
+        Object::Number(n) => whole(*n).unwrap_or(-1),
+        _ => return None,
+    };
+    Some(usize::try_from(i).unwrap_or(usize::MAX))
+}
+
+// whole is n as an int when it has no fraction and fits in one.
+pub fn whole(n: f64) -> Option<i64> {
+    if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 {
+        Some(n as i64)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arithmetic {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Mod,
+    BitAnd,
+    BitOr,
+}
+
+// arithmetic applies op to two numbers, None when they aren't numbers the
+// operator takes. Two ints give an int unless the result overflows, then
+// they give a float like any pair with a float in it. Division always
+// divides floats, 7 / 2 is 3.5. The bitwise operators only take ints.
+pub fn arithmetic(op: Arithmetic, left: &Object, right: &Object) -> Option<Object> {
+    if let (Object::Int(l), Object::Int(r)) = (left, right) {
+        let result = match op {
+            Arithmetic::Add => l.checked_add(*r),
+            Arithmetic::Subtract => l.checked_sub(*r),
+            Arithmetic::Multiply => l.checked_mul(*r),
+            Arithmetic::Divide => None,
+            Arithmetic::Mod => l.checked_rem(*r),
+            Arithmetic::BitAnd => Some(l & r),
+            Arithmetic::BitOr => Some(l | r),
+        };
+        if let Some(result) = result {
+            return Some(Object::Int(result));
         }
     }
+    let (l, r) = (left.number()?, right.number()?);
+    Some(Object::Number(match op {
+        Arithmetic::Add => l + r,
+        Arithmetic::Subtract => l - r,
+        Arithmetic::Multiply => l * r,
+        Arithmetic::Divide => l / r,
+        Arithmetic::Mod => l % r,
+        Arithmetic::BitAnd | Arithmetic::BitOr => return None,
+    }))
+}
+
+// compare orders two numbers, None when they aren't both numbers. Ints
+// compare exactly, a comparison with NaN has no order.
+pub fn compare(left: &Object, right: &Object) -> Option<Option<Ordering>> {
+    match (left, right) {
+        (Object::Int(l), Object::Int(r)) => Some(Some(l.cmp(r))),
+        (Object::Int(i), Object::Number(n)) => Some(compare_int(*i, *n)),
+        (Object::Number(n), Object::Int(i)) => Some(compare_int(*i, *n).map(Ordering::reverse)),
+        _ => Some(left.number()?.partial_cmp(&right.number()?)),
+    }
+}
+
+// compare_int orders i against n without rounding i to a float first.
+fn compare_int(i: i64, n: f64) -> Option<Ordering> {
+    if n.is_nan() {
+        None
+    } else if n >= i64::MAX as f64 {
+        Some(Ordering::Less)
+    } else if n < i64::MIN as f64 {
+        Some(Ordering::Greater)
+    } else {
+        let whole = n.trunc();
+        Some(
+            i.cmp(&(whole as i64))
+                .then(0.0.partial_cmp(&(n - whole)).unwrap()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+
+    fn hash(object: &Object) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        object.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_numbers() {
+        let (one, two) = (Object::Int(1), Object::Int(2));
+        assert_eq!(
+            arithmetic(Arithmetic::Add, &one, &two),
+            Some(Object::Int(3))
+        );
+        assert!(matches!(
+            arithmetic(Arithmetic::Add, &one, &Object::Number(0.5)),
+            Some(Object::Number(n)) if n == 1.5
+        ));
+        assert!(matches!(
+            arithmetic(Arithmetic::Divide, &one, &two),
+            Some(Object::Number(n)) if n == 0.5
+        ));
+        assert!(matches!(
+            arithmetic(Arithmetic::Multiply, &Object::Int(i64::MAX), &two),
+            Some(Object::Number(_))
+        ));
+        assert_eq!(
+            arithmetic(Arithmetic::Mod, &Object::Int(7), &Object::Int(-3)),
+            Some(Object::Int(1))
+        );
+        assert_eq!(
+            arithmetic(Arithmetic::BitAnd, &Object::Int(6), &Object::Int(3)),
+            Some(Object::Int(2))
+        );
+        assert_eq!(
+            arithmetic(Arithmetic::BitOr, &Object::Int(6), &Object::Int(3)),
+            Some(Object::Int(7))
+        );
+        assert_eq!(
+            arithmetic(Arithmetic::BitOr, &one, &Object::Number(1.0)),
+            None
+        );
+        assert_eq!(arithmetic(Arithmetic::Add, &one, &Object::Nil), None);
+        assert_eq!(
+            Object::Int(i64::MIN).negate(),
+            Some(Object::Number(9223372036854775808.0))
+        );
+
+        assert_eq!(
+            compare(&one, &Object::Number(1.5)),
+            Some(Some(Ordering::Less))
+        );
+        assert_eq!(compare(&one, &Object::Number(f64::NAN)), Some(None));
+        assert_eq!(compare(&one, &Object::Nil), None);
+        assert_eq!(
+            compare(&Object::Int(i64::MAX), &Object::Number(i64::MAX as f64)),
+            Some(Some(Ordering::Less))
+        );
+        assert_eq!(
+            compare(&Object::Number(-1.5), &Object::Int(-1)),
+            Some(Some(Ordering::Less))
+        );
+
+        assert_eq!(Object::Int(3), Object::Number(3.0));
+        assert_ne!(Object::Int(3), Object::Number(3.5));
+        assert_eq!(hash(&Object::Int(3)), hash(&Object::Number(3.0)));
+        assert_eq!(hash(&Object::Int(0)), hash(&Object::Number(-0.0)));
+    }
+
+    // Instances hash by the address of their fields, so mutating them
+    // doesn't move them in the map.
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn test_keys() {
+        assert_eq!(Object::Number(2.0).key(), Ok(Object::Int(2)));
+        assert!(matches!(Object::Number(-0.0).key(), Ok(Object::Int(0))));
+        assert!(matches!(Object::Number(0.5).key(), Ok(Object::Number(n)) if n == 0.5));
+        assert_eq!(Object::Nil.key(), Ok(Object::Nil));
+        assert_eq!(
+            Object::Number(f64::NAN).key(),
+            Err("Can't use NaN as a hash key.".to_string())
+        );
+        assert_eq!(
+            Object::Array(Rc::new(RefCell::new(vec![]))).key(),
+            Err("Can't use an array as a hash key.".to_string())
+        );
+
+        let instance = || Object::ClassInstance {
+            name: "A".to_string(),
+            class: Rc::new(Object::Nil),
+            fields: Rc::new(RefCell::new(HashMap::new())),
+            properties: Rc::new(RefCell::new(HashMap::new())),
+        };
+        let (a, b) = (instance(), instance());
+        assert_eq!(a, a.clone());
+        assert_ne!(a, b);
+        let mut keys = HashMap::new();
+        keys.insert(a.clone().key().unwrap(), 1);
+        keys.insert(b.clone().key().unwrap(), 2);
+        assert_eq!((keys[&a], keys[&b]), (1, 2));
+    }
 }
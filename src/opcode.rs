@@ -46,4 +46,15 @@ pub enum Opcode {
     Assert(usize),
     EqualEqual,
     Exit(usize),
+    Class(usize),
+    Inherit,
+    Method(usize),
+    GetProperty(usize),
+    SetProperty(usize),
+    Invoke(usize, usize),
+    GetSuper(usize),
+    Iterator,
+    IterNext(usize),
+    BitAnd,
+    BitOr,
 }
@@ -0,0 +1,47 @@
+use std::cell::RefCell;
+use std::fmt::Arguments;
+use std::io::{self, Write};
+
+thread_local! {
+    // CAPTURED holds what Lox programs print on this thread while capture
+    // runs. None sends it straight to stdout.
+    static CAPTURED: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+// print writes what a Lox program prints. Both backends and the print
+// builtins go through it, so the output can be captured.
+pub fn print(args: Arguments) {
+    CAPTURED.with(|captured| match captured.borrow_mut().as_mut() {
+        Some(out) => out.push_str(&args.to_string()),
+        None => {
+            io::stdout().write_fmt(args).unwrap();
+        }
+    });
+}
+
+// capture runs f and returns everything it printed along with its result.
+// Captures on the same thread nest; the inner one takes the output.
+pub fn capture<T>(f: impl FnOnce() -> T) -> (String, T) {
+    let outer = CAPTURED.with(|captured| captured.replace(Some(String::new())));
+    let result = f();
+    let out = CAPTURED.with(|captured| captured.replace(outer));
+    (out.unwrap_or_default(), result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture() {
+        let (out, value) = capture(|| {
+            print(format_args!("a{}", 1));
+            let (inner, _) = capture(|| print(format_args!("b")));
+            assert_eq!(inner, "b");
+            print(format_args!("\n"));
+            2
+        });
+        assert_eq!(out, "a1\n");
+        assert_eq!(value, 2);
+    }
+}
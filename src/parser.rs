@@ -1,9 +1,10 @@
 use std::collections::HashMap;
-use std::process::exit;
 use std::{fs, vec};
 
-use crate::ast::{BlockStmt, ExprType, Ident, Literal, Precedence, Program, Stmt};
+use crate::ast::{BlockStmt, ExprNode, ExprType, Ident, Literal, Precedence, Program, Stmt, StmtNode};
+use crate::diagnostic::Diagnostic;
 use crate::lexer::Lexing;
+use crate::span::{Span, Spanned};
 use crate::token::{self, Token};
 
 pub struct Parser<'a> {
@@ -11,81 +12,162 @@ pub struct Parser<'a> {
     current: Token,
     next: Token,
     prev: Token,
+    span: Span,      // span of current
+    next_span: Span, // span of next
+    prev_span: Span, // span of prev
+    panicking: bool, // set after an error until the parser resynchronizes
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(mut lex: Lexing<'a>) -> Parser {
+    pub fn new(mut lex: Lexing<'a>) -> Parser<'a> {
         let prev = Token::Eof;
-        let current = lex.next();
-        let next = lex.next();
+        let current = Self::lex_token(&mut lex);
+        let next = Self::lex_token(&mut lex);
         Parser {
             lex,
-            current,
-            next,
+            current: current.node,
+            next: next.node,
             prev,
+            span: current.span,
+            next_span: next.span,
+            prev_span: current.span,
+            panicking: false,
         }
     }
 
+    // lex_token reads the next token that is not a comment.
+    fn lex_token(lex: &mut Lexing<'a>) -> Spanned<Token> {
+        loop {
+            let token = lex.next_token();
+            if !matches!(token.node, Token::Comment(_)) {
+                return token;
+            }
+        }
+    }
+
+    // parse parses the whole file. Statements with syntax errors are skipped,
+    // so the returned program may be partial; check has_errors.
     pub fn parse(&mut self) -> Program {
-        let mut program: Program = vec![];
-        while self.current != Token::Eof {
+        self.parse_stmts(&[])
+    }
+
+    // parse_stmts parses statements until one of the end tokens or Eof. When a
+    // statement fails to parse the parser synchronizes and carries on, so every
+    // error in the file gets reported.
+    fn parse_stmts(&mut self, end: &[Token]) -> Program {
+        let mut stmts: Program = vec![];
+        while self.current != Token::Eof && !end.contains(&self.current) {
+            let start = self.span;
             match self.parse_stmt() {
                 Some(stmt) => {
-                    program.push(stmt);
+                    // the statement recovered from its own error, if it had one
+                    self.panicking = false;
+                    stmts.push(stmt);
                 }
                 None => {
-                    break;
+                    self.synchronize(start);
                 }
-            };
+            }
+        }
+        stmts
+    }
+
+    // synchronize skips tokens until the start of the next statement: past a
+    // ';', or up to a '}' or a statement keyword.
+    fn synchronize(&mut self, start: Span) {
+        self.panicking = false;
+        // always make progress, or a stray token would be reported forever
+        if self.span == start && self.current != Token::Eof {
+            self.next();
+        }
+        loop {
+            match self.current {
+                Token::Eof
+                | Token::RightBrace
+                | Token::Var
+                | Token::Fun
+                | Token::Class
+                | Token::If
+                | Token::While
+                | Token::For
+                | Token::Return => return,
+                Token::Semicolon => {
+                    self.next();
+                    return;
+                }
+                _ => {
+                    self.next();
+                }
+            }
         }
-        program
     }
 
     pub fn get_imports(&mut self, program: Program) -> Option<HashMap<String, String>> {
-        let imports = program.iter().filter(|stmt| match stmt {
-            Stmt::Import(_) => true,
-            _ => false,
-        });
+        let imports = program
+            .iter()
+            .filter(|stmt| matches!(stmt.node, Stmt::Import(_)));
         if imports.clone().count() == 0 {
             return None;
         }
         let mut progs = HashMap::new();
         for import in imports.clone() {
-            match import {
-                Stmt::Import(s) => {
-                    let current_dir = std::env::current_dir().unwrap();
-                    let filename = format!("{}.lox", s);
-                    println!("current_dir: {:?}", current_dir.join(filename.clone()));
-                    let file_contents = fs::read_to_string(current_dir.join(filename)).unwrap();
-                    if !file_contents.is_empty() {
-                        progs.insert(s.to_string(), file_contents);
-                    }
+            if let Stmt::Import(s) = &import.node {
+                let current_dir = std::env::current_dir().unwrap();
+                let filename = format!("{}.lox", s);
+                println!("current_dir: {:?}", current_dir.join(filename.clone()));
+                let file_contents = fs::read_to_string(current_dir.join(filename)).unwrap();
+                if !file_contents.is_empty() {
+                    progs.insert(s.to_string(), file_contents);
                 }
-                _ => {}
             }
         }
         Some(progs)
     }
 
+    // node wraps a parsed node with the span running from start to the last consumed token.
+    fn node<T>(&self, node: T, start: Span) -> Spanned<T> {
+        Spanned::new(node, start.to(self.prev_span))
+    }
+
+    // error reports a syntax error at the current token. Only the first error
+    // of a statement is reported; the rest are usually knock-on errors. So is
+    // only the first error at a token: a file ending inside nested blocks
+    // would get one from each block otherwise.
+    fn error(&mut self, message: &str) {
+        if self.panicking {
+            return;
+        }
+        self.panicking = true;
+        let span = self.span;
+        if self.lex.errors.iter().any(|error| error.span() == span) {
+            return;
+        }
+        self.lex.log_error(self.current.clone(), self.span, message);
+    }
+
     fn parse_ident(&mut self) -> Option<Ident> {
         match self.current.clone() {
             Token::Identifier(s) => Some(Ident(s)),
-            _ => None,
+            _ => {
+                self.error("Expect identifier");
+                None
+            }
         }
     }
 
-    fn parse_stmt(&mut self) -> Option<Stmt> {
+    fn parse_stmt(&mut self) -> Option<StmtNode> {
+        let start = self.span;
         match self.current.clone() {
-            Token::Eof => {
-                return None;
-            }
+            Token::Eof => None,
             Token::Fun => {
                 self.next();
-                let indent = self.parse_ident().unwrap();
-                if let Some(ExprType::Function { params, body }) = self.parse_function() {
-                    return Some(Stmt::Function(indent, params, body));
+                let indent = self.parse_ident()?;
+                if let Some(ExprType::Function { params, body }) =
+                    self.parse_function().map(|f| f.node)
+                {
+                    return Some(self.node(Stmt::Function(indent, params, body), start));
                 }
-                return None;
+                None
             }
             Token::Import => self.parse_import_stmt(),
             Token::Return => self.parse_return(),
@@ -95,8 +177,11 @@ impl<'a> Parser<'a> {
             Token::Class => self.parse_class(),
             Token::For => self.parse_for_loop(),
             Token::Assert => self.parse_assert_expr(),
+            Token::Break | Token::Continue => self.parse_jump(),
             Token::Identifier(_) => {
-                if self.next == Token::Equal {
+                if self.next == Token::Colon {
+                    self.parse_labeled()
+                } else if self.next == Token::Equal {
                     self.parse_assign()
                 } else {
                     self.parse_expr_stmt()
@@ -104,42 +189,29 @@ impl<'a> Parser<'a> {
             }
             Token::LeftBrace => {
                 self.next();
-                let mut stmts: Program = vec![];
-                while self.current != Token::RightBrace {
-                    match self.parse_stmt() {
-                        Some(stmt) => {
-                            stmts.push(stmt);
-                        }
-                        None => {
-                            break;
-                        }
-                    }
-                }
+                let stmts = self.parse_stmts(&[Token::RightBrace]);
                 if self.current != Token::RightBrace {
-                    exit(65);
+                    self.error("Expect '}' after block");
+                    return None;
                 }
                 self.next();
-                return Some(Stmt::Block(stmts));
+                Some(self.node(Stmt::Block(stmts), start))
             }
             _ => self.parse_expr_stmt(),
         }
     }
 
-    fn parse_expr_stmt(&mut self) -> Option<Stmt> {
-        match self.parse_expr(Precedence::Lowest) {
-            Some(expr) => {
-                if self.current == Token::Semicolon {
-                    self.next();
-                }
-                return Some(Stmt::Expr(expr));
-            }
-            None => {
-                return None;
-            }
+    fn parse_expr_stmt(&mut self) -> Option<StmtNode> {
+        let start = self.span;
+        let expr = self.parse_expr(Precedence::Lowest)?;
+        if self.current == Token::Semicolon {
+            self.next();
         }
+        Some(self.node(Stmt::Expr(expr), start))
     }
 
-    fn parse_var_stmt(&mut self) -> Option<Stmt> {
+    fn parse_var_stmt(&mut self) -> Option<StmtNode> {
+        let start = self.span;
         self.next();
         let ident = match self.current.clone() {
             Token::Identifier(s) => {
@@ -147,222 +219,254 @@ impl<'a> Parser<'a> {
                 Ident(s)
             }
             _ => {
+                self.error("Expect variable name");
                 return None;
             }
         };
         if self.current == Token::Semicolon {
+            let nil = self.node(ExprType::Literal(Literal::Nil), self.prev_span);
             self.next();
-            return Some(Stmt::Var(ident, ExprType::Literal(Literal::Nil)));
+            return Some(self.node(Stmt::Var(ident, nil), start));
         } else if self.current == Token::In {
-            return Some(Stmt::Var(ident, ExprType::Literal(Literal::Nil)));
+            let nil = self.node(ExprType::Literal(Literal::Nil), self.prev_span);
+            return Some(self.node(Stmt::Var(ident, nil), start));
         }
         self.next();
-        let expr = self.parse_expr(Precedence::Lowest).unwrap();
+        let expr = self.parse_expr(Precedence::Lowest)?;
         if self.current != Token::Semicolon {
-            eprintln!(
-                "self.current != Token::Semicolon Unexpected token: {:?}",
-                self.current
-            );
-            exit(0);
+            self.error("Expect ';' after variable declaration");
+            return None;
         }
         self.next();
-        Some(Stmt::Var(ident, expr))
+        Some(self.node(Stmt::Var(ident, expr), start))
     }
 
-    fn parse_for_loop(&mut self) -> Option<Stmt> {
+    fn parse_for_loop(&mut self) -> Option<StmtNode> {
+        let start = self.span;
         self.next();
         if self.current != Token::LeftParen {
-            self.lex
-                .log_error(self.current.clone(), "Expect '(' after for");
+            self.error("Expect '(' after for");
             return None;
         }
         self.next();
         let init = match self.current {
-            Token::Var => self.parse_var_stmt().unwrap(),
+            Token::Var => self.parse_var_stmt()?,
             _ => {
-                self.lex
-                    .log_error(self.current.clone(), "Expect var after for");
+                self.error("Expect var after for");
                 return None;
             }
         };
         if self.current == Token::In {
             self.next();
-            let expr = self.parse_expr(Precedence::Lowest).unwrap();
+            let expr = self.parse_expr(Precedence::Lowest)?;
             if self.current != Token::RightParen {
-                self.lex
-                    .log_error(self.current.clone(), "Expect ')' after for condition");
+                self.error("Expect ')' after for condition");
                 return None;
             }
             self.next();
-            let block = self.parse_block().unwrap();
-            return Some(Stmt::ForIn {
-                var: Box::new(init),
-                iter: Box::new(expr),
-                block: block,
-            });
+            let block = self.parse_block()?;
+            return Some(self.node(
+                Stmt::ForIn {
+                    var: Box::new(init),
+                    iter: Box::new(expr),
+                    block,
+                },
+                start,
+            ));
         }
-        let condition = self.parse_expr(Precedence::Lowest).unwrap();
+        let condition = self.parse_expr(Precedence::Lowest)?;
         if self.current != Token::Semicolon {
-            self.lex
-                .log_error(self.current.clone(), "Expect ';' after for condition");
+            self.error("Expect ';' after for condition");
             return None;
         }
         self.next();
 
-        let step = self.parse_expr(Precedence::Lowest).unwrap();
+        let step_start = self.span;
+        let step = self.parse_expr(Precedence::Lowest)?;
+        let step = self.node(Stmt::Expr(step), step_start);
         if self.current != Token::RightParen {
-            self.lex
-                .log_error(self.current.clone(), "Expect ')' after for condition");
+            self.error("Expect ')' after for condition");
+            return None;
+        }
+        self.next();
+        let block = self.parse_block()?;
+        Some(self.node(
+            Stmt::For {
+                init: Box::new(init),
+                conditions: Box::new(condition),
+                step: Box::new(step),
+                block,
+            },
+            start,
+        ))
+    }
+
+    // parse_jump parses `break;` and `continue;`, optionally naming the loop to leave.
+    fn parse_jump(&mut self) -> Option<StmtNode> {
+        let start = self.span;
+        let keyword = self.current.clone();
+        self.next();
+        let label = match self.current.clone() {
+            Token::Identifier(s) => {
+                self.next();
+                Some(Ident(s))
+            }
+            _ => None,
+        };
+        if self.current != Token::Semicolon {
+            if keyword == Token::Break {
+                self.error("Expect ';' after break");
+            } else {
+                self.error("Expect ';' after continue");
+            }
             return None;
         }
         self.next();
-        let block = self.parse_block().unwrap();
-        Some(Stmt::For {
-            init: Box::new(init),
-            conditions: Box::new(condition),
-            step: Box::new(Stmt::Expr(step)),
-            block: block,
-        })
+        let stmt = match keyword {
+            Token::Break => Stmt::Break(label),
+            _ => Stmt::Continue(label),
+        };
+        Some(self.node(stmt, start))
     }
 
-    fn parse_class(&mut self) -> Option<Stmt> {
+    // parse_labeled parses a loop with a label: `outer: while (...) { ... }`.
+    fn parse_labeled(&mut self) -> Option<StmtNode> {
+        let start = self.span;
+        let label = self.parse_ident()?;
         self.next();
-        let ident = self.parse_ident().unwrap();
         self.next();
+        let stmt = match self.current {
+            Token::While => self.parse_while()?,
+            Token::For => self.parse_for_loop()?,
+            _ => {
+                self.error("Expect loop after label");
+                return None;
+            }
+        };
+        Some(self.node(Stmt::Labeled(label, Box::new(stmt)), start))
+    }
+
+    fn parse_class(&mut self) -> Option<StmtNode> {
+        let start = self.span;
+        self.next();
+        let ident = self.parse_ident()?;
+        self.next();
+        let mut superclass = None;
+        if self.current == Token::Less {
+            self.next();
+            superclass = Some(self.parse_ident()?);
+            self.next();
+        }
         if self.current != Token::LeftBrace {
-            self.lex
-                .log_error(self.current.clone(), "Expect '{' after class name");
+            self.error("Expect '{' after class name");
             return None;
         }
-        let properties = self.parse_block().unwrap();
-
-        Some(Stmt::ClassStmt {
-            name: ident,
-            properties: properties,
-        })
+        let properties = self.parse_block()?;
+
+        Some(self.node(
+            Stmt::ClassStmt {
+                name: ident,
+                superclass,
+                properties,
+            },
+            start,
+        ))
     }
 
-    fn parse_while(&mut self) -> Option<Stmt> {
+    fn parse_while(&mut self) -> Option<StmtNode> {
+        let start = self.span;
         self.next();
         if self.current != Token::LeftParen {
-            self.lex
-                .log_error(self.current.clone(), "Expect '(' after while");
+            self.error("Expect '(' after while");
             return None;
         }
         self.next();
-        let expr = self.parse_expr(Precedence::Lowest).unwrap();
+        let expr = self.parse_expr(Precedence::Lowest)?;
         if self.current != Token::RightParen {
-            self.lex
-                .log_error(self.current.clone(), "Expect ')' after while condition");
+            self.error("Expect ')' after while condition");
             return None;
         }
         self.next();
         if self.current != Token::LeftBrace {
-            self.lex
-                .log_error(self.current.clone(), "Expect '{' after while condition");
+            self.error("Expect '{' after while condition");
             return None;
         }
-        let body = self.parse_block().unwrap();
-        Some(Stmt::While(expr, body))
+        let body = self.parse_block()?;
+        Some(self.node(Stmt::While(expr, body), start))
     }
 
     fn parse_case_block(&mut self) -> Option<BlockStmt> {
-        let mut stmts = vec![];
-        while self.current != Token::RightBrace
-            && self.current != Token::Case
-            && self.current != Token::Default
-        {
-            match self.parse_stmt() {
-                Some(stmt) => {
-                    stmts.push(stmt);
-                }
-                None => {
-                    break;
-                }
-            }
-        }
-        Some(stmts)
+        Some(self.parse_stmts(&[Token::RightBrace, Token::Case, Token::Default]))
     }
 
     fn parse_block(&mut self) -> Option<BlockStmt> {
         self.next();
-        let mut stmts: Program = vec![];
-        while self.current != Token::RightBrace {
-            match self.parse_stmt() {
-                Some(stmt) => {
-                    stmts.push(stmt);
-                }
-                None => {
-                    break;
-                }
-            }
-        }
-
+        let stmts = self.parse_stmts(&[Token::RightBrace]);
         if self.current != Token::RightBrace {
-            eprintln!("self.current != Token::RightBrace {:?}", self.current);
-            exit(0);
+            self.error("Expect '}' after block");
+            return None;
         }
         self.next();
         Some(stmts)
     }
 
-    fn parse_if(&mut self) -> Option<ExprType> {
+    fn parse_if(&mut self) -> Option<ExprNode> {
+        let start = self.span;
         self.next();
         if self.current != Token::LeftParen {
-            self.lex
-                .log_error(self.current.clone(), "Expect '(' after if");
+            self.error("Expect '(' after if");
             return None;
         }
         self.next();
         let condition = self.parse_expr(Precedence::Lowest);
         if self.current != Token::RightParen {
-            self.lex
-                .log_error(self.current.clone(), "Expect ')' after if condition");
+            self.error("Expect ')' after if condition");
             return None;
         }
         self.next();
-        let then_branch: Program = self.parse_block().unwrap();
-        let mut elseif: Vec<(Box<ExprType>, Program)> = vec![];
+        let then_branch: Program = self.parse_block()?;
+        let mut elseif: Vec<(Box<ExprNode>, Program)> = vec![];
         let mut else_branch: Program = vec![];
 
         while self.current == Token::Else && self.next == Token::If {
             self.next();
             self.next();
             if self.current != Token::LeftParen {
-                self.lex
-                    .log_error(self.current.clone(), "Expect '(' after if");
+                self.error("Expect '(' after if");
                 return None;
             }
             self.next();
             let condition = self.parse_expr(Precedence::Lowest);
             if self.current != Token::RightParen {
-                self.lex
-                    .log_error(self.current.clone(), "Expect ')' after if condition");
+                self.error("Expect ')' after if condition");
                 return None;
             }
             self.next();
-            let block = self.parse_block().unwrap();
-            elseif.push((Box::new(condition.unwrap()), block));
+            let block = self.parse_block()?;
+            elseif.push((Box::new(condition?), block));
         }
 
         if self.current == Token::Else {
             self.next();
-            else_branch = self.parse_block().unwrap();
+            else_branch = self.parse_block()?;
         }
 
-        Some(ExprType::If {
-            condition: Box::new(condition.unwrap()),
-            elseif: elseif,
-            then_branch: then_branch,
-            else_branch: else_branch,
-        })
+        Some(self.node(
+            ExprType::If {
+                condition: Box::new(condition?),
+                elseif,
+                then_branch,
+                else_branch,
+            },
+            start,
+        ))
     }
 
-    fn parse_function(&mut self) -> Option<ExprType> {
+    fn parse_function(&mut self) -> Option<ExprNode> {
+        let start = self.span;
         self.next();
         if self.current != Token::LeftParen {
-            self.lex
-                .log_error(self.current.clone(), "Expect '(' after function");
+            self.error("Expect '(' after function");
             return None;
         }
         self.next();
@@ -374,8 +478,7 @@ impl<'a> Parser<'a> {
                     Ident(s)
                 }
                 _ => {
-                    self.lex
-                        .log_error(self.current.clone(), "Expect identifier");
+                    self.error("Expect identifier");
                     return None;
                 }
             };
@@ -385,27 +488,21 @@ impl<'a> Parser<'a> {
             }
         }
         self.next();
-        let body = self.parse_block().unwrap();
-        Some(ExprType::Function { params, body })
+        let body = self.parse_block()?;
+        Some(self.node(ExprType::Function { params, body }, start))
     }
 
-    fn parse_call(&mut self, left: ExprType) -> Option<ExprType> {
+    fn parse_call(&mut self, left: ExprNode) -> Option<ExprNode> {
+        let start = left.span;
         self.next();
-        let mut args = vec![];
-        while self.current != Token::RightParen {
-            let arg = self.parse_expr(Precedence::Lowest);
-            if let Some(arg) = arg {
-                args.push(arg);
-            }
-            if self.current == Token::Comma {
-                self.next();
-            }
-        }
-        self.next();
-        Some(ExprType::Call {
-            callee: Box::new(left),
-            args: args,
-        })
+        let args = self.parse_args_list()?;
+        Some(self.node(
+            ExprType::Call {
+                callee: Box::new(left),
+                args,
+            },
+            start,
+        ))
     }
 
     fn current_token_precedence(&self) -> Precedence {
@@ -419,6 +516,8 @@ impl<'a> Parser<'a> {
     fn token_precedence(&self, token: Token) -> Precedence {
         match token {
             Token::And | Token::Or => Precedence::And,
+            Token::BitOr => Precedence::BitOr,
+            Token::BitAnd => Precedence::BitAnd,
             Token::EqualEqual | Token::BangEqual | Token::Equal => Precedence::Equals,
             Token::Less | Token::LessEqual | Token::Greater | Token::GreaterEqual => {
                 Precedence::LessGreater
@@ -435,137 +534,130 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_return(&mut self) -> Option<Stmt> {
+    fn parse_return(&mut self) -> Option<StmtNode> {
+        let start = self.span;
         self.next();
         let value = self.parse_expr(Precedence::Lowest);
         if self.current != Token::Semicolon {
-            self.lex.log_error(
-                self.current.clone(),
-                format!("Expect ';' after return, {:?}", self.current).as_str(),
-            );
+            self.error("Expect ';' after return");
             return None;
         }
         self.next();
-        Some(Stmt::Return(value.unwrap()))
+        Some(self.node(Stmt::Return(value?), start))
     }
 
-    fn parse_switch(&mut self) -> Option<Stmt> {
+    fn parse_switch(&mut self) -> Option<StmtNode> {
+        let start = self.span;
         self.next();
-        let expr = self.parse_expr(Precedence::Lowest).unwrap();
+        let expr = self.parse_expr(Precedence::Lowest)?;
         let mut cases = vec![];
         if self.current != Token::LeftBrace {
-            self.lex
-                .log_error(self.current.clone(), "Expect '{' after switch");
+            self.error("Expect '{' after switch");
             return None;
         }
         self.next();
 
         while self.current == Token::Case {
-            let case = self.parse_case().unwrap();
+            let case = self.parse_case()?;
             cases.push(case);
         }
 
         if self.current == Token::Default {
-            let default = self.parse_default().unwrap();
+            let default = self.parse_default()?;
             cases.push(default);
         }
 
         if self.current != Token::RightBrace {
-            self.lex
-                .log_error(self.current.clone(), "Expect '}' after switch");
+            self.error("Expect '}' after switch");
             return None;
         }
         self.next();
 
-        Some(Stmt::Switch(expr, cases))
+        Some(self.node(Stmt::Switch(expr, cases), start))
     }
 
-    fn parse_case(&mut self) -> Option<Stmt> {
+    fn parse_case(&mut self) -> Option<StmtNode> {
+        let start = self.span;
         self.next();
         let left = self.parse_expr(Precedence::Lowest);
         if self.current != Token::Colon {
-            self.lex.log_error(self.current.clone(), "Expect Colon");
+            self.error("Expect Colon");
             return None;
         }
         self.next();
-        let body = self.parse_case_block().unwrap();
-        Some(Stmt::Case(left.unwrap(), body))
+        let body = self.parse_case_block()?;
+        Some(self.node(Stmt::Case(left?, body), start))
     }
 
-    fn parse_default(&mut self) -> Option<Stmt> {
+    fn parse_default(&mut self) -> Option<StmtNode> {
+        let start = self.span;
         self.next();
         if self.current != Token::Colon {
-            self.lex.log_error(self.current.clone(), "Expect Colon");
+            self.error("Expect Colon");
             return None;
         }
         self.next();
-        let body = self.parse_case_block().unwrap();
-        Some(Stmt::Default(body))
+        let body = self.parse_case_block()?;
+        Some(self.node(Stmt::Default(body), start))
     }
 
-    fn parse_expr(&mut self, precedence: Precedence) -> Option<ExprType> {
+    fn parse_expr(&mut self, precedence: Precedence) -> Option<ExprNode> {
         // println!("parse_expr: {:?} {:?}", self.current, precedence);
+        let start = self.span;
         // prefix
         let mut left = match self.current.clone() {
             Token::Bang | Token::Plus | Token::Minus => self.parse_prefix_expr(),
             Token::LeftParen => self.parse_grouped_expr(),
-            Token::Number(_) => self.parse_number_literal(),
+            Token::Number(_) | Token::Integer(_) => self.parse_number_literal(),
             Token::Identifier(ident) => {
                 self.next();
-                Some(ExprType::Ident(Ident(ident)))
+                Some(self.node(ExprType::Ident(Ident(ident)), start))
             }
             Token::True => {
                 self.next();
-                Some(ExprType::Literal(Literal::Bool(true)))
+                Some(self.node(ExprType::Literal(Literal::Bool(true)), start))
             }
             Token::False => {
                 self.next();
-                Some(ExprType::Literal(Literal::Bool(false)))
+                Some(self.node(ExprType::Literal(Literal::Bool(false)), start))
             }
             Token::String(s) => {
                 self.next();
-                Some(ExprType::Literal(Literal::String(s)))
+                Some(self.node(ExprType::Literal(Literal::String(s)), start))
             }
             Token::LeftBracket => {
                 self.next();
                 let mut elements = vec![];
                 while self.current != Token::RightBracket {
-                    let element = self.parse_expr(Precedence::Lowest);
-                    if let Some(element) = element {
-                        elements.push(element);
-                    }
+                    let element = self.parse_expr(Precedence::Lowest)?;
+                    elements.push(element);
                     if self.current == Token::Comma {
                         self.next();
                     }
                 }
                 self.next();
-                Some(ExprType::Literal(Literal::Array(elements)))
+                Some(self.node(ExprType::Literal(Literal::Array(elements)), start))
             }
             Token::LeftBrace => self.parse_hash_literal(),
             Token::Nil => {
                 self.next();
-                Some(ExprType::Literal(Literal::Nil))
+                Some(self.node(ExprType::Literal(Literal::Nil), start))
             }
             Token::New => self.parse_new_class(),
             Token::This => {
+                self.next();
+                Some(self.node(ExprType::This, start))
+            }
+            Token::Super => {
                 self.next();
                 if self.current != Token::Dot {
-                    self.lex
-                        .log_error(self.current.clone(), "Expect '.' after this");
+                    self.error("Expect '.' after 'super'");
                     return None;
                 }
                 self.next();
-                let ident = self.parse_ident().unwrap();
+                let method = self.parse_ident()?;
                 self.next();
-                if self.current == Token::LeftParen {
-                    self.next();
-                    let args = self.parse_args_list();
-                    return Some(ExprType::ThisCall {
-                        method: ident,
-                        args: args.unwrap(),
-                    });
-                }
-                Some(ExprType::ThisExpr(ident))
+                Some(self.node(ExprType::Super(method), start))
             }
             Token::If => self.parse_if(),
             Token::Fun => self.parse_function(),
@@ -573,20 +665,16 @@ impl<'a> Parser<'a> {
                 self.next();
                 let mut exprs = vec![];
                 while self.current != Token::Semicolon {
-                    let expr = self.parse_expr(Precedence::Lowest);
-                    if let Some(expr) = expr {
-                        exprs.push(expr);
-                    }
+                    let expr = self.parse_expr(Precedence::Lowest)?;
+                    exprs.push(expr);
                     if self.current == Token::Comma {
                         self.next();
                     }
                 }
-                return Some(ExprType::PrintExpr(Box::new(exprs)));
+                return Some(self.node(ExprType::PrintExpr(Box::new(exprs)), start));
             }
             _ => {
-                println!("Unexpected token in parse_expr: {:?}", self.current);
-                self.lex
-                    .log_error(self.current.clone(), "Expect expression");
+                self.error("Expect expression");
                 return None;
             }
         };
@@ -620,43 +708,29 @@ impl<'a> Parser<'a> {
                 | Token::SlashSelf
                 | Token::ModSelf
                 | Token::Mod
+                | Token::BitAnd
+                | Token::BitOr
                 | Token::GreaterEqual => {
-                    left = self.parse_infix_expr(left.unwrap());
+                    left = self.parse_infix_expr(left?);
                 }
                 Token::LeftParen => {
-                    left = self.parse_call(left.unwrap());
+                    left = self.parse_call(left?);
                 }
                 Token::LeftBracket => {
-                    left = self.parse_index_expr(left.unwrap());
+                    left = self.parse_index_expr(left?);
                 }
                 Token::Dot => {
-                    let class_name = match left {
-                        Some(ExprType::Ident(ident)) => ident,
-                        _ => {
-                            self.lex
-                                .log_error(self.current.clone(), "Expect identifier before '.'");
-                            return None;
-                        }
-                    };
-
+                    let object = left?;
                     self.next();
-                    let ident = self.parse_ident().unwrap();
+                    let name = self.parse_ident()?;
                     self.next();
-                    if self.current != Token::LeftParen {
-                        left = Some(ExprType::ClassGet {
-                            callee: class_name,
-                            prop: ident,
-                        });
-                    } else {
-                        self.next();
-                        let args = self.parse_args_list();
-
-                        left = Some(ExprType::ClassCall {
-                            callee: class_name,
-                            method: ident,
-                            args: args.unwrap(),
-                        });
-                    }
+                    left = Some(self.node(
+                        ExprType::Get {
+                            object: Box::new(object),
+                            name,
+                        },
+                        start,
+                    ));
                 }
                 _ => return left,
             }
@@ -666,68 +740,60 @@ impl<'a> Parser<'a> {
         left
     }
 
-    fn parse_hash_literal(&mut self) -> Option<ExprType> {
+    fn parse_hash_literal(&mut self) -> Option<ExprNode> {
+        let start = self.span;
         self.next();
         let mut hash = vec![];
         while self.current != Token::RightBrace {
-            let key = self.parse_expr(Precedence::Lowest).unwrap();
+            let key = self.parse_expr(Precedence::Lowest)?;
             if self.current != Token::Colon {
-                self.lex
-                    .log_error(self.current.clone(), "Expect ':' after hash key");
+                self.error("Expect ':' after hash key");
                 return None;
             }
             self.next();
-            let value = self.parse_expr(Precedence::Lowest).unwrap();
+            let value = self.parse_expr(Precedence::Lowest)?;
             if self.current == Token::Comma {
                 self.next();
             }
             hash.push((key, value));
         }
         self.next();
-        Some(ExprType::Literal(Literal::Hash(hash)))
+        Some(self.node(ExprType::Literal(Literal::Hash(hash)), start))
     }
 
-    fn parse_import_stmt(&mut self) -> Option<Stmt> {
+    fn parse_import_stmt(&mut self) -> Option<StmtNode> {
+        let start = self.span;
         self.next();
         if let Token::String(s) = self.current.clone() {
             self.next();
             if self.current != Token::Semicolon {
-                self.lex
-                    .log_error(self.current.clone(), "Expect ';' after import");
+                self.error("Expect ';' after import");
                 return None;
             }
             self.next();
-            return Some(Stmt::Import(s));
+            return Some(self.node(Stmt::Import(s), start));
         }
-        self.lex
-            .log_error(self.current.clone(), "Expect string after import");
-        return None;
+        self.error("Expect string after import");
+        None
     }
 
-    fn parse_index_expr(&mut self, left: ExprType) -> Option<ExprType> {
+    fn parse_index_expr(&mut self, left: ExprNode) -> Option<ExprNode> {
+        let start = left.span;
         self.next();
 
-        let index = self.parse_expr(Precedence::Lowest).unwrap();
+        let index = self.parse_expr(Precedence::Lowest)?;
 
         if self.current == Token::RightBracket {
             self.next();
         } else {
-            self.lex
-                .log_error(self.current.clone(), "Expect ']' after index");
+            self.error("Expect ']' after index");
             return None;
         }
 
-        if let ExprType::Literal(Literal::Number(v)) = index {
-            return Some(ExprType::IndexExpr(
-                Box::new(left),
-                Box::new(ExprType::Literal(Literal::Index(v as usize))),
-            ));
-        }
-
-        Some(ExprType::IndexExpr(Box::new(left), Box::new(index)))
+        Some(self.node(ExprType::IndexExpr(Box::new(left), Box::new(index)), start))
     }
 
-    fn parse_args_list(&mut self) -> Option<Vec<ExprType>> {
+    fn parse_args_list(&mut self) -> Option<Vec<ExprNode>> {
         let mut args = vec![];
         while self.current != Token::RightParen {
             let arg = self.parse_expr(Precedence::Lowest);
@@ -742,13 +808,13 @@ impl<'a> Parser<'a> {
         Some(args)
     }
 
-    fn parse_new_class(&mut self) -> Option<ExprType> {
+    fn parse_new_class(&mut self) -> Option<ExprNode> {
+        let start = self.span;
         self.next();
-        let ident = self.parse_ident().unwrap();
+        let ident = self.parse_ident()?;
         self.next();
         if self.current != Token::LeftParen {
-            self.lex
-                .log_error(self.current.clone(), "Expect '(' after new");
+            self.error("Expect '(' after new");
             return None;
         }
         self.next();
@@ -763,62 +829,66 @@ impl<'a> Parser<'a> {
             }
         }
         self.next();
-        Some(ExprType::ClassInit {
-            name: ident,
-            args: args,
-        })
+        Some(self.node(ExprType::ClassInit { name: ident, args }, start))
     }
 
-    fn parse_grouped_expr(&mut self) -> Option<ExprType> {
+    fn parse_grouped_expr(&mut self) -> Option<ExprNode> {
+        let start = self.span;
         self.next();
 
         let expr = self.parse_expr(Precedence::Lowest);
         if self.current == Token::RightParen {
             self.next();
         } else {
-            println!("Unexpected token: {:?}", self.current);
-            self.lex
-                .log_error(self.current.clone(), "Expect expression");
+            self.error("Expect ')' after expression");
             return None;
         }
         match expr {
-            Some(expr) => Some(ExprType::GroupingExpr(Box::new(expr))),
+            Some(expr) => Some(self.node(ExprType::GroupingExpr(Box::new(expr)), start)),
             None => {
-                println!("Unexpected token: {:?}", self.current);
-                self.lex
-                    .log_error(self.current.clone(), "Expect expression");
-                return None;
+                self.error("Expect expression");
+                None
             }
         }
     }
 
-    fn parse_prefix_expr(&mut self) -> Option<ExprType> {
+    fn parse_prefix_expr(&mut self) -> Option<ExprNode> {
+        let start = self.span;
         let op = self.current.clone();
         self.next();
         let right = self.parse_expr(Precedence::Prefix);
         if let Some(right) = right {
-            return Some(ExprType::PrefixExpr(op, Box::new(right)));
+            return Some(self.node(ExprType::PrefixExpr(op, Box::new(right)), start));
         }
-        println!("Unexpected token: {:?}", self.current);
-        self.lex
-            .log_error(self.current.clone(), "Expect expression");
-        return None;
+        self.error("Expect expression");
+        None
     }
 
-    fn parse_number_literal(&mut self) -> Option<ExprType> {
-        return match self.current.clone() {
+    fn parse_number_literal(&mut self) -> Option<ExprNode> {
+        let start = self.span;
+        match self.current.clone() {
             Token::Number(n) => {
                 let num = n.parse::<f64>().unwrap();
                 self.next();
-                Some(ExprType::Literal(Literal::Number(num)))
+                Some(self.node(ExprType::Literal(Literal::Number(num)), start))
+            }
+            // too big for an int, it stays a float
+            Token::Integer(n) => {
+                let literal = match n.parse::<i64>() {
+                    Ok(i) => Literal::Int(i),
+                    Err(_) => Literal::Number(n.parse::<f64>().unwrap()),
+                };
+                self.next();
+                Some(self.node(ExprType::Literal(literal), start))
             }
             _ => {
                 panic!("Unexpected");
             }
-        };
+        }
     }
 
-    fn parse_infix_expr(&mut self, left: ExprType) -> Option<ExprType> {
+    fn parse_infix_expr(&mut self, left: ExprNode) -> Option<ExprNode> {
+        let start = left.span;
         let op = self.current.clone();
         let mut precedence = self.current_token_precedence();
         self.next();
@@ -829,88 +899,107 @@ impl<'a> Parser<'a> {
         // println!("parse_infix_expr: {:?} {:?} {:?} {:?}", left, op, self.current, precedence);
 
         if let Some(right) = self.parse_expr(precedence) {
-            return Some(ExprType::InfixExpr(Box::new(left), op, Box::new(right)));
+            if let (Token::Equal, ExprType::Get { object, name }) = (&op, &left.node) {
+                return Some(self.node(
+                    ExprType::Set {
+                        object: object.clone(),
+                        name: name.clone(),
+                        value: Box::new(right),
+                    },
+                    start,
+                ));
+            }
+            return Some(self.node(
+                ExprType::InfixExpr(Box::new(left), op, Box::new(right)),
+                start,
+            ));
         }
-        println!("Unexpected token: {:?}", self.current);
-        self.lex
-            .log_error(self.current.clone(), "Expect expression");
-        return None;
+        self.error("Expect expression");
+        None
     }
 
-    fn parse_assign(&mut self) -> Option<Stmt> {
+    fn parse_assign(&mut self) -> Option<StmtNode> {
+        let start = self.span;
         let left = self.parse_ident();
         self.next();
+        let left = self.node(ExprType::Ident(left?), start);
         let op = self.current.clone();
         if op != Token::Equal {
-            self.lex
-                .log_error(self.current.clone(), "Expect '=' after identifier");
+            self.error("Expect '=' after identifier");
             return None;
         }
         self.next();
         let right = self.parse_expr(Precedence::Lowest);
         if let Some(right) = right {
             if self.current != Token::Semicolon {
-                self.lex
-                    .log_error(self.current.clone(), "Expect ';' after assign");
+                self.error("Expect ';' after assign");
                 return None;
             }
             self.next();
-            return Some(Stmt::Assign(ExprType::Ident(left.unwrap()), right));
+            return Some(self.node(Stmt::Assign(left, right), start));
         }
-        println!("Unexpected token: {:?}", self.current);
-        self.lex
-            .log_error(self.current.clone(), "Expect expression");
-        return None;
+        self.error("Expect expression");
+        None
     }
 
-    fn parse_assert_expr(&mut self) -> Option<Stmt> {
+    fn parse_assert_expr(&mut self) -> Option<StmtNode> {
+        let start = self.span;
         self.next();
         let expr = self.parse_expr(Precedence::Lowest);
         if self.current == Token::Comma {
             self.next();
             if let Token::String(s) = self.current.clone() {
+                let message = Spanned::new(ExprType::Literal(Literal::String(s)), self.span);
                 self.next();
 
                 if self.current != Token::Semicolon {
-                    self.lex
-                        .log_error(self.current.clone(), "Expect ';' after assert message");
+                    self.error("Expect ';' after assert message");
                     return None;
                 }
                 self.next();
-                return Some(Stmt::Assert {
-                    condition: Box::new(expr.unwrap()),
-                    message: Box::new(ExprType::Literal(Literal::String(s))),
-                });
+                return Some(self.node(
+                    Stmt::Assert {
+                        condition: Box::new(expr?),
+                        message: Box::new(message),
+                    },
+                    start,
+                ));
             }
         } else if self.current != Token::Semicolon {
-            self.lex
-                .log_error(self.current.clone(), "Expect ';' after assert");
+            self.error("Expect ';' after assert");
             return None;
         }
 
+        let message = Spanned::new(ExprType::Literal(Literal::String("".to_string())), self.span);
         self.next();
-        Some(Stmt::Assert {
-            condition: Box::new(expr.unwrap()),
-            message: Box::new(ExprType::Literal(Literal::String("".to_string()))),
-        })
+        Some(self.node(
+            Stmt::Assert {
+                condition: Box::new(expr?),
+                message: Box::new(message),
+            },
+            start,
+        ))
     }
 
     fn next(&mut self) -> Token {
-        let next = self.lex.next();
-        self.prev = self.current.clone();
-        self.current = self.next.clone();
-        self.next = next;
-        let tok = self.current.clone();
-        match tok {
-            Token::Comment(_) => self.next(),
-            _ => tok,
-        }
+        let next = Self::lex_token(&mut self.lex);
+        self.prev = std::mem::replace(&mut self.current, self.next.clone());
+        self.prev_span = self.span;
+        self.span = self.next_span;
+        self.next = next.node;
+        self.next_span = next.span;
+        self.current.clone()
     }
 
     pub fn has_errors(&self) -> bool {
         self.lex.has_errors()
     }
 
+    // diagnostics returns every lexer and parser error found so far.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.lex.errors
+    }
+
     // fn peek(&self) -> Token {
     //     self.next.clone()
     // }
@@ -919,8 +1008,8 @@ impl<'a> Parser<'a> {
 #[cfg(test)]
 mod test {
 
-    use crate::ast::{ExprType, Ident, Literal, Stmt};
-    use crate::lexer::Lexing;
+    use crate::ast::{ExprNode, ExprType, Ident, Literal, Stmt};
+use crate::lexer::Lexing;
     use crate::parser::Parser;
     use crate::token::Token;
 
@@ -935,7 +1024,7 @@ mod test {
             program,
             vec![Stmt::Var(
                 Ident(String::from("a")),
-                ExprType::Literal(Literal::Number(10.0))
+                ExprType::Literal(Literal::Int(10)).into()
             ),]
         );
     }
@@ -953,8 +1042,8 @@ mod test {
                 Ident(String::from("a")),
                 ExprType::PrefixExpr(
                     Token::Minus,
-                    Box::new(ExprType::Literal(Literal::Number(10.0)))
-                )
+                    Box::new(ExprType::Literal(Literal::Int(10)).into())
+                ).into()
             ),]
         );
     }
@@ -970,14 +1059,14 @@ mod test {
         assert_eq!(
             program,
             vec![Stmt::Expr(ExprType::InfixExpr(
-                Box::new(ExprType::Literal(Literal::Number(1.0))),
+                Box::new(ExprType::Literal(Literal::Int(1)).into()),
                 Token::Plus,
                 Box::new(ExprType::InfixExpr(
-                    Box::new(ExprType::Literal(Literal::Number(2.0))),
+                    Box::new(ExprType::Literal(Literal::Int(2)).into()),
                     Token::Star,
-                    Box::new(ExprType::Literal(Literal::Number(3.0)))
-                ))
-            ))]
+                    Box::new(ExprType::Literal(Literal::Int(3)).into())
+                ).into())
+            ).into())]
         )
     }
 
@@ -1002,13 +1091,13 @@ mod test {
             program,
             vec![Stmt::Expr(ExprType::InfixExpr(
                 Box::new(ExprType::InfixExpr(
-                    Box::new(ExprType::Literal(Literal::Number(16.0))),
+                    Box::new(ExprType::Literal(Literal::Int(16)).into()),
                     Token::Star,
-                    Box::new(ExprType::Literal(Literal::Number(38.0))),
-                )),
+                    Box::new(ExprType::Literal(Literal::Int(38)).into()),
+                ).into()),
                 Token::Slash,
-                Box::new(ExprType::Literal(Literal::Number(58.0)))
-            ))]
+                Box::new(ExprType::Literal(Literal::Int(58)).into())
+            ).into())]
         );
     }
 
@@ -1023,12 +1112,12 @@ mod test {
         //     program,
         //     vec![Stmt::Expr(ExprType::InfixExpr(
         //         Box::new(ExprType::InfixExpr(
-        //             Box::new(ExprType::Literal(Literal::Number(11.0))),
+        //             Box::new(ExprType::Literal(Literal::Int(11))),
         //             Token::Star,
-        //             Box::new(ExprType::Literal(Literal::Number(38.0))),
+        //             Box::new(ExprType::Literal(Literal::Int(38))),
         //         )),
         //         Token::Slash,
-        //         Box::new(ExprType::Literal(Literal::Number(58.0)))
+        //         Box::new(ExprType::Literal(Literal::Int(58)))
         //     ))]
         // );
     }
@@ -1045,22 +1134,22 @@ mod test {
             vec![
                 Stmt::Var(
                     Ident(String::from("a")),
-                    ExprType::Literal(Literal::Number(10.0))
+                    ExprType::Literal(Literal::Int(10)).into()
                 ),
                 Stmt::Var(
                     Ident(String::from("b")),
-                    ExprType::Literal(Literal::Number(20.0))
+                    ExprType::Literal(Literal::Int(20)).into()
                 ),
                 Stmt::Var(
                     Ident(String::from("c")),
                     ExprType::InfixExpr(
-                        Box::new(ExprType::Ident(Ident(String::from("a")))),
+                        Box::new(ExprType::Ident(Ident(String::from("a"))).into()),
                         Token::Minus,
                         Box::new(ExprType::PrefixExpr(
                             Token::Minus,
-                            Box::new(ExprType::Ident(Ident(String::from("b"))))
-                        ))
-                    )
+                            Box::new(ExprType::Ident(Ident(String::from("b"))).into())
+                        ).into())
+                    ).into()
                 )
             ]
         );
@@ -1069,7 +1158,7 @@ mod test {
     #[test]
     fn test_bang_true() {
         let input = "!true";
-        let lex: Lexing<'_> = Lexing::new(&input);
+        let lex: Lexing<'_> = Lexing::new(input);
         let mut parse = Parser::new(lex);
         let program = parse.parse();
         assert_eq!(program.len(), 1);
@@ -1077,45 +1166,45 @@ mod test {
             program,
             vec![Stmt::Expr(ExprType::PrefixExpr(
                 Token::Bang,
-                Box::new(ExprType::Literal(Literal::Bool(true)))
-            ))]
+                Box::new(ExprType::Literal(Literal::Bool(true)).into())
+            ).into())]
         );
     }
 
     #[test]
     fn test_grouped_string() {
         let input = "(\"foo\")";
-        let lex: Lexing<'_> = Lexing::new(&input);
+        let lex: Lexing<'_> = Lexing::new(input);
         let mut parse = Parser::new(lex);
         let program = parse.parse();
         assert_eq!(program.len(), 1);
         assert_eq!(
             program,
             vec![Stmt::Expr(ExprType::GroupingExpr(Box::new(
-                ExprType::Literal(Literal::String(String::from("foo")))
-            )))]
+                ExprType::Literal(Literal::String(String::from("foo"))).into()
+            )).into())]
         );
     }
 
     #[test]
     fn test_grouped_nil() {
         let input = "(nil)";
-        let lex: Lexing<'_> = Lexing::new(&input);
+        let lex: Lexing<'_> = Lexing::new(input);
         let mut parse = Parser::new(lex);
         let program = parse.parse();
         assert_eq!(program.len(), 1);
         assert_eq!(
             program,
             vec![Stmt::Expr(ExprType::GroupingExpr(Box::new(
-                ExprType::Literal(Literal::Nil)
-            )))]
+                ExprType::Literal(Literal::Nil).into()
+            )).into())]
         );
     }
 
     #[test]
     fn test_arithmetic_operators_3() {
         let input = "52 + 80 - 94";
-        let lex: Lexing<'_> = Lexing::new(&input);
+        let lex: Lexing<'_> = Lexing::new(input);
         let mut parse = Parser::new(lex);
         let program = parse.parse();
         assert_eq!(program.len(), 1);
@@ -1123,20 +1212,20 @@ mod test {
             program,
             vec![Stmt::Expr(ExprType::InfixExpr(
                 Box::new(ExprType::InfixExpr(
-                    Box::new(ExprType::Literal(Literal::Number(52.0))),
+                    Box::new(ExprType::Literal(Literal::Int(52)).into()),
                     Token::Plus,
-                    Box::new(ExprType::Literal(Literal::Number(80.0))),
-                )),
+                    Box::new(ExprType::Literal(Literal::Int(80)).into()),
+                ).into()),
                 Token::Minus,
-                Box::new(ExprType::Literal(Literal::Number(94.0)))
-            ))]
+                Box::new(ExprType::Literal(Literal::Int(94)).into())
+            ).into())]
         );
     }
 
     #[test]
     fn test_arithmetic_issue_2() {
         let input = "(-43 + 95) * (68 * 80) / (55 + 75)";
-        let lex: Lexing<'_> = Lexing::new(&input);
+        let lex: Lexing<'_> = Lexing::new(input);
         let mut parse = Parser::new(lex);
         let program = parse.parse();
         assert_eq!(program.len(), 1);
@@ -1144,39 +1233,35 @@ mod test {
             program,
             vec![Stmt::Expr(ExprType::InfixExpr(
                 Box::new(ExprType::InfixExpr(
-                    Box::new(ExprType::InfixExpr(
+                    Box::new(ExprType::GroupingExpr(Box::new(ExprType::InfixExpr(
                         Box::new(ExprType::PrefixExpr(
                             Token::Minus,
-                            Box::new(ExprType::Literal(Literal::Number(43.0)))
-                        )),
+                            Box::new(ExprType::Literal(Literal::Int(43)).into())
+                        ).into()),
                         Token::Plus,
-                        Box::new(ExprType::Literal(Literal::Number(95.0))),
-                    )),
+                        Box::new(ExprType::Literal(Literal::Int(95)).into()),
+                    ).into())).into()),
                     Token::Star,
-                    Box::new(ExprType::InfixExpr(
-                        Box::new(ExprType::Literal(Literal::Number(68.0))),
+                    Box::new(ExprType::GroupingExpr(Box::new(ExprType::InfixExpr(
+                        Box::new(ExprType::Literal(Literal::Int(68)).into()),
                         Token::Star,
-                        Box::new(ExprType::Literal(Literal::Number(80.0))),
-                    )),
-                )),
+                        Box::new(ExprType::Literal(Literal::Int(80)).into()),
+                    ).into())).into()),
+                ).into()),
                 Token::Slash,
-                Box::new(ExprType::InfixExpr(
-                    Box::new(ExprType::InfixExpr(
-                        Box::new(ExprType::Literal(Literal::Number(55.0))),
-                        Token::Plus,
-                        Box::new(ExprType::Literal(Literal::Number(75.0))),
-                    )),
+                Box::new(ExprType::GroupingExpr(Box::new(ExprType::InfixExpr(
+                    Box::new(ExprType::Literal(Literal::Int(55)).into()),
                     Token::Plus,
-                    Box::new(ExprType::Literal(Literal::Number(75.0))),
-                )),
-            ))]
+                    Box::new(ExprType::Literal(Literal::Int(75)).into()),
+                ).into())).into()),
+            ).into())]
         );
     }
 
     #[test]
     fn test_comparison_operator() {
         let input = "83 < 99 < 115";
-        let lex: Lexing<'_> = Lexing::new(&input);
+        let lex: Lexing<'_> = Lexing::new(input);
         let mut parse = Parser::new(lex);
         let program = parse.parse();
         assert_eq!(program.len(), 1);
@@ -1184,20 +1269,34 @@ mod test {
             program,
             vec![Stmt::Expr(ExprType::InfixExpr(
                 Box::new(ExprType::InfixExpr(
-                    Box::new(ExprType::Literal(Literal::Number(83.0))),
+                    Box::new(ExprType::Literal(Literal::Int(83)).into()),
                     Token::Less,
-                    Box::new(ExprType::Literal(Literal::Number(99.0))),
-                )),
+                    Box::new(ExprType::Literal(Literal::Int(99)).into()),
+                ).into()),
                 Token::Less,
-                Box::new(ExprType::Literal(Literal::Number(115.0)))
-            ))]
+                Box::new(ExprType::Literal(Literal::Int(115)).into())
+            ).into())]
         );
     }
 
+    #[test]
+    fn test_bitwise_operators() {
+        let input = "print 3 | 4 & 5;\n1 & 2;";
+        let lex: Lexing<'_> = Lexing::new(input);
+        let mut parse = Parser::new(lex);
+        let program = parse.parse();
+        assert!(!parse.has_errors());
+        let printed = program
+            .iter()
+            .map(|stmt| stmt.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(printed, vec!["(print (| 3.0 (& 4.0 5.0)))", "(& 1.0 2.0)"]);
+    }
+
     #[test]
     fn test_output_error() {
         let input = "(foo";
-        let lex: Lexing<'_> = Lexing::new(&input);
+        let lex: Lexing<'_> = Lexing::new(input);
         let mut parse = Parser::new(lex);
         let program = parse.parse();
         for error in parse.lex.errors {
@@ -1205,4 +1304,164 @@ mod test {
         }
         assert_eq!(program.len(), 0);
     }
+
+    #[test]
+    fn test_node_spans() {
+        let input = "var a = 1 +\n  foo(2);";
+        let lex: Lexing<'_> = Lexing::new(input);
+        let mut parse = Parser::new(lex);
+        let program = parse.parse();
+        assert_eq!(program.len(), 1);
+        assert_eq!((program[0].span.line, program[0].span.column), (1, 1));
+        assert_eq!(program[0].span.len, input.len());
+        if let Stmt::Var(_, expr) = &program[0].node {
+            assert_eq!((expr.span.line, expr.span.column), (1, 9));
+            assert_eq!(&input[expr.span.offset..expr.span.end()], "1 +\n  foo(2)");
+            if let ExprType::InfixExpr(_, _, right) = &expr.node {
+                assert_eq!((right.span.line, right.span.column), (2, 3));
+                return;
+            }
+        }
+        panic!("unexpected program: {:?}", program);
+    }
+
+    #[test]
+    fn test_break_continue() {
+        let input = "outer: while (true) { continue; break outer; }".to_string();
+        let lex: Lexing<'_> = Lexing::new(&input);
+        let mut parse = Parser::new(lex);
+        let program = parse.parse();
+        assert!(!parse.has_errors());
+        assert_eq!(
+            program,
+            vec![Stmt::Labeled(
+                Ident(String::from("outer")),
+                Box::new(
+                    Stmt::While(
+                        ExprType::Literal(Literal::Bool(true)).into(),
+                        vec![
+                            Stmt::Continue(None).into(),
+                            Stmt::Break(Some(Ident(String::from("outer")))).into(),
+                        ]
+                    )
+                    .into()
+                )
+            )]
+        );
+    }
+
+    #[test]
+    fn test_superclass() {
+        let input = "class B < A { fun f() { super.f(); } }".to_string();
+        let lex: Lexing<'_> = Lexing::new(&input);
+        let mut parse = Parser::new(lex);
+        let program = parse.parse();
+        assert!(!parse.has_errors());
+        assert_eq!(
+            program,
+            vec![Stmt::ClassStmt {
+                name: Ident(String::from("B")),
+                superclass: Some(Ident(String::from("A"))),
+                properties: vec![Stmt::Function(
+                    Ident(String::from("f")),
+                    vec![],
+                    vec![Stmt::Expr(
+                        ExprType::Call {
+                            callee: Box::new(ExprType::Super(Ident(String::from("f"))).into()),
+                            args: vec![],
+                        }
+                        .into()
+                    )
+                    .into()]
+                )
+                .into()],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_property_chain() {
+        let input = "a.b.c = f().d;".to_string();
+        let lex: Lexing<'_> = Lexing::new(&input);
+        let mut parse = Parser::new(lex);
+        let program = parse.parse();
+        assert!(!parse.has_errors());
+        let ident = |name: &str| -> ExprNode { ExprType::Ident(Ident(String::from(name))).into() };
+        assert_eq!(
+            program,
+            vec![Stmt::Expr(
+                ExprType::Set {
+                    object: Box::new(
+                        ExprType::Get {
+                            object: Box::new(ident("a")),
+                            name: Ident(String::from("b")),
+                        }
+                        .into()
+                    ),
+                    name: Ident(String::from("c")),
+                    value: Box::new(
+                        ExprType::Get {
+                            object: Box::new(
+                                ExprType::Call {
+                                    callee: Box::new(ident("f")),
+                                    args: vec![],
+                                }
+                                .into()
+                            ),
+                            name: Ident(String::from("d")),
+                        }
+                        .into()
+                    ),
+                }
+                .into()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_error_recovery() {
+        let input = r#"
+var a = ;
+var b = 2;
+}
+fun f() {
+    var c = (1;
+    return c;
+}
+while (true { print 1; }
+"#;
+        let lex: Lexing<'_> = Lexing::new(input);
+        let mut parse = Parser::new(lex);
+        let program = parse.parse();
+        assert!(parse.has_errors());
+        let lines: Vec<usize> = parse.diagnostics().iter().map(|d| d.span().line).collect();
+        assert_eq!(lines, vec![2, 4, 6, 9, 9]);
+        assert_eq!(
+            program,
+            vec![
+                Stmt::Var(
+                    Ident(String::from("b")),
+                    ExprType::Literal(Literal::Int(2)).into()
+                ),
+                Stmt::Function(
+                    Ident(String::from("f")),
+                    vec![],
+                    vec![Stmt::Return(ExprType::Ident(Ident(String::from("c"))).into()).into()]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unclosed_blocks() {
+        let input = "fun f() {\n  if (true) {\n    while (true) {\n      { print 1;\n";
+        let mut parse = Parser::new(Lexing::new(input));
+        parse.parse();
+        let messages: Vec<&str> = parse
+            .diagnostics()
+            .iter()
+            .map(|d| d.message.as_str())
+            .collect();
+        assert_eq!(messages, vec!["Expect '}' after block."]);
+    }
 }
@@ -0,0 +1,250 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use crate::ast::Program;
+use crate::evaluator::Evaluator;
+use crate::imports::Imports;
+use crate::lexer::Lexing;
+use crate::objects::Object;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::span::SourceMap;
+use crate::token::Token;
+
+const HELP: &str = "\
+:ast <code>       print the syntax tree of code
+:tokens <code>    print the tokens of code
+:env              print the global variables
+:load <file.lox>  run a file in this session
+:history          print the inputs of this session
+:help             print this message
+:quit             leave the repl";
+
+// Repl reads Lox from stdin a statement at a time. All input runs in one
+// Evaluator, so variables and functions persist between lines.
+pub struct Repl {
+    evaluator: Evaluator,
+    sources: SourceMap,
+    history: Vec<String>,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Repl {
+            evaluator: Evaluator::new(vec![], false),
+            sources: SourceMap::new(),
+            history: vec![],
+        }
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        let stdin = io::stdin();
+        let mut stdout = io::stdout();
+        let mut input = String::new();
+        loop {
+            write!(stdout, "{}", if input.is_empty() { "> " } else { "... " })?;
+            stdout.flush()?;
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line)? == 0 {
+                writeln!(stdout)?;
+                return Ok(());
+            }
+            input.push_str(&line);
+            if !is_complete(&input) {
+                continue;
+            }
+            let source = std::mem::take(&mut input);
+            let source = source.trim();
+            if source == ":quit" {
+                return Ok(());
+            }
+            self.handle(source, &mut stdout)?;
+        }
+    }
+
+    // handle runs one complete input, either a meta-command or Lox code.
+    // Results and errors are written to out; print statements go to stdout.
+    pub fn handle(&mut self, input: &str, out: &mut impl Write) -> io::Result<()> {
+        if input.is_empty() {
+            return Ok(());
+        }
+        self.history.push(input.to_string());
+        if !input.starts_with(':') {
+            return self.eval(input, out);
+        }
+        let (command, arg) = match input.split_once(char::is_whitespace) {
+            Some((command, arg)) => (command, arg.trim()),
+            None => (input, ""),
+        };
+        match command {
+            ":ast" => {
+                if let Some(program) = self.parse(arg, out)? {
+                    for stmt in &program {
+                        writeln!(out, "{}", stmt)?;
+                    }
+                }
+            }
+            ":tokens" => {
+                let mut lex = Lexing::new(arg);
+                loop {
+                    let token = lex.next_kind();
+                    writeln!(out, "{}", token)?;
+                    if token == Token::Eof {
+                        break;
+                    }
+                }
+            }
+            ":env" => {
+                for (name, value) in self.evaluator.globals() {
+                    writeln!(out, "{} = {}", name, describe(&value))?;
+                }
+            }
+            ":load" => self.load(arg, out)?,
+            ":history" => {
+                for (i, input) in self.history.iter().enumerate() {
+                    writeln!(out, "{:>4}  {}", i + 1, input)?;
+                }
+            }
+            ":help" => writeln!(out, "{}", HELP)?,
+            _ => writeln!(out, "Unknown command {}, try :help", command)?,
+        }
+        Ok(())
+    }
+
+    fn eval(&mut self, source: &str, out: &mut impl Write) -> io::Result<()> {
+        let program = match self.parse(source, out)? {
+            Some(program) => program,
+            None => return Ok(()),
+        };
+        self.execute(&program, out)
+    }
+
+    fn load(&mut self, filename: &str, out: &mut impl Write) -> io::Result<()> {
+        let source = match fs::read_to_string(filename) {
+            Ok(source) => source,
+            Err(err) => return writeln!(out, "Failed to read file {}: {}", filename, err),
+        };
+        let file = self.sources.add(filename, &source);
+        let mut parse = Parser::new(Lexing::with_file(&source, file));
+        let program = parse.parse();
+        if parse.has_errors() {
+            for diagnostic in parse.diagnostics() {
+                write!(out, "{}", diagnostic.render(&self.sources))?;
+            }
+            return Ok(());
+        }
+        let current_dir = Path::new(filename).parent().unwrap_or(Path::new("."));
+        let mut import = Imports::new(program, current_dir.to_path_buf());
+        match import.load(&mut self.sources) {
+            Ok(program) => self.execute(&program, out),
+            Err(errors) => {
+                for diagnostic in errors {
+                    write!(out, "{}", diagnostic.render(&self.sources))?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // parse parses source as the next repl input, reporting syntax errors to out.
+    fn parse(&mut self, source: &str, out: &mut impl Write) -> io::Result<Option<Program>> {
+        let name = format!("<repl:{}>", self.history.len());
+        let file = self.sources.add(&name, source);
+        let mut parse = Parser::new(Lexing::with_file(source, file));
+        let program = parse.parse();
+        if parse.has_errors() {
+            for diagnostic in parse.diagnostics() {
+                write!(out, "{}", diagnostic.render(&self.sources))?;
+            }
+            return Ok(None);
+        }
+        Ok(Some(program))
+    }
+
+    fn execute(&mut self, program: &Program, out: &mut impl Write) -> io::Result<()> {
+        let mut resolver = Resolver::new();
+        resolver.define_globals(self.evaluator.globals().into_iter().map(|(name, _)| name));
+        let locals = resolver.resolve(program);
+        if resolver.has_errors() {
+            for diagnostic in resolver.diagnostics() {
+                write!(out, "{}", diagnostic.render(&self.sources))?;
+            }
+            return Ok(());
+        }
+        self.evaluator.define_locals(locals);
+        // flush print statements of the program before the result is echoed
+        io::stdout().flush()?;
+        match self.evaluator.run(program) {
+            Ok(Some(Object::Nil)) | Ok(None) => Ok(()),
+            Ok(Some(value)) => writeln!(out, "{}", value),
+            Err(err) => write!(out, "{}", err.diagnostic().render(&self.sources)),
+        }
+    }
+}
+
+// is_complete reports whether input can be run: every brace, paren and
+// bracket it opens is closed. Until then the repl keeps reading lines.
+pub fn is_complete(input: &str) -> bool {
+    let mut lex = Lexing::new(input);
+    let mut depth = 0;
+    loop {
+        match lex.next_kind() {
+            Token::LeftBrace | Token::LeftParen | Token::LeftBracket => depth += 1,
+            Token::RightBrace | Token::RightParen | Token::RightBracket => depth -= 1,
+            Token::Eof => return depth <= 0,
+            _ => {}
+        }
+    }
+}
+
+// describe shortens functions and classes to their kind for :env.
+fn describe(value: &Object) -> String {
+    match value {
+        Object::Function(params, ..) => format!("<fn({})>", params.len()),
+        Object::Class(name, ..) => format!("<class {}>", name),
+        Object::ClassInstance { name, .. } => format!("<instance of {}>", name),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handle(repl: &mut Repl, input: &str) -> String {
+        let mut out = vec![];
+        repl.handle(input, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_is_complete() {
+        assert!(is_complete("var a = 1;"));
+        assert!(!is_complete("fun f() {\n"));
+        assert!(!is_complete("foo(1,\n"));
+        assert!(is_complete("fun f() {\n  return [1, 2];\n}\n"));
+        assert!(is_complete("print \"{\";"));
+    }
+
+    #[test]
+    fn test_persistent_env() {
+        let mut repl = Repl::new();
+        assert_eq!(handle(&mut repl, "var a = 1;"), "");
+        assert_eq!(handle(&mut repl, "fun add(x) {\n  return x + a;\n}"), "");
+        assert_eq!(handle(&mut repl, "add(2)"), "3\n");
+        assert_eq!(handle(&mut repl, "a + 1;"), "2\n");
+        assert_eq!(handle(&mut repl, ":env"), "a = 1\nadd = <fn(1)>\n");
+        assert!(handle(&mut repl, "-\"a\"")
+            .starts_with("error[E0300]: Operand must be a number.\n --> <repl:6>:1:1\n"));
+        assert_eq!(handle(&mut repl, "a"), "1\n");
+        assert_eq!(handle(&mut repl, ":ast 1 + 2;"), "(+ 1.0 2.0)\n");
+        assert!(handle(&mut repl, ":history").starts_with("   1  var a = 1;\n"));
+    }
+}
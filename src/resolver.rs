@@ -0,0 +1,549 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    ast::{ExprNode, ExprType, Ident, Literal, Stmt, StmtNode},
+    builtins,
+    diagnostic::{
+        Diagnostic, INVALID_JUMP, INVALID_RETURN, INVALID_SUPER, INVALID_THIS, REDECLARED_VARIABLE,
+        UNDEFINED_VARIABLE, USE_BEFORE_DEFINE,
+    },
+    span::Span,
+};
+
+// Locals maps the span of every identifier that names a local variable to its
+// depth: how many scopes out from the use the variable was declared. Names
+// that are not in the map are globals.
+pub type Locals = HashMap<Span, usize>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionKind {
+    None,
+    Function,
+    Method,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ClassKind {
+    None,
+    Class,
+    Subclass,
+}
+
+// Resolver walks a program before it runs and works out which declaration
+// each identifier refers to. It opens a scope wherever the evaluator creates
+// an Env, so a depth is exactly the number of outer links to follow.
+//
+// On the way it reports the mistakes that can be found without running the
+// program: undefined names, names used before their definition, a variable
+// declared twice in one scope, and return, this, super, break or continue
+// where they make no sense.
+pub struct Resolver {
+    scopes: Vec<HashMap<String, bool>>,
+    locals: Locals,
+    // globals defined so far, and every global the program defines
+    globals: HashSet<String>,
+    declared: HashSet<String>,
+    function: FunctionKind,
+    class: ClassKind,
+    // loops around the statement being resolved, and the labels among them
+    loops: usize,
+    labels: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver {
+            scopes: vec![],
+            locals: HashMap::new(),
+            globals: builtins::new_builtins().into_keys().collect(),
+            declared: HashSet::new(),
+            function: FunctionKind::None,
+            class: ClassKind::None,
+            loops: 0,
+            labels: vec![],
+            diagnostics: vec![],
+        }
+    }
+
+    // define_globals adds globals that exist before the program runs, such as
+    // the ones defined by earlier repl inputs.
+    pub fn define_globals(&mut self, names: impl IntoIterator<Item = String>) {
+        self.globals.extend(names);
+    }
+
+    pub fn resolve(&mut self, program: &[StmtNode]) -> Locals {
+        for stmt in program {
+            match &stmt.node {
+                Stmt::Var(ident, _) | Stmt::Function(ident, _, _) => {
+                    self.declared.insert(ident.0.clone());
+                }
+                Stmt::ClassStmt { name, .. } => {
+                    self.declared.insert(name.0.clone());
+                }
+                _ => {}
+            }
+        }
+        self.resolve_stmts(program);
+        std::mem::take(&mut self.locals)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        !self.diagnostics.is_empty()
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    fn error(&mut self, code: &'static str, span: Span, message: &str) {
+        self.diagnostics
+            .push(Diagnostic::error(code, message, span));
+    }
+
+    fn resolve_stmts(&mut self, stmts: &[StmtNode]) {
+        for stmt in stmts {
+            self.resolve_stmt(stmt);
+        }
+    }
+
+    fn resolve_block(&mut self, stmts: &[StmtNode]) {
+        self.begin_scope();
+        self.resolve_stmts(stmts);
+        self.end_scope();
+    }
+
+    fn resolve_stmt(&mut self, stmt: &StmtNode) {
+        match &stmt.node {
+            Stmt::Var(ident, expr) => {
+                self.declare(ident, stmt.span);
+                self.resolve_expr(expr);
+                self.define(ident);
+            }
+            Stmt::Expr(expr) => self.resolve_expr(expr),
+            Stmt::Return(expr) => {
+                if self.function == FunctionKind::None {
+                    self.error(
+                        INVALID_RETURN,
+                        stmt.span,
+                        "Can't return from top-level code.",
+                    );
+                }
+                self.resolve_expr(expr);
+            }
+            Stmt::Block(stmts) => self.resolve_block(stmts),
+            Stmt::Function(ident, params, body) => {
+                // defined before the body, so the function can call itself
+                self.declare(ident, stmt.span);
+                self.define(ident);
+                self.resolve_function(params, body, FunctionKind::Function, stmt.span);
+            }
+            Stmt::Switch(expr, cases) => {
+                self.resolve_expr(expr);
+                self.resolve_stmts(cases);
+            }
+            Stmt::Case(expr, block) => {
+                self.resolve_expr(expr);
+                self.resolve_block(block);
+            }
+            Stmt::Default(block) => self.resolve_block(block),
+            Stmt::While(condition, block) => {
+                self.resolve_expr(condition);
+                self.resolve_loop_body(block);
+            }
+            // the init of a for loop is in a scope around the whole loop
+            Stmt::For {
+                init,
+                conditions,
+                step,
+                block,
+            } => {
+                self.begin_scope();
+                self.resolve_stmt(init);
+                self.resolve_expr(conditions);
+                self.resolve_stmt(step);
+                self.resolve_loop_body(block);
+                self.end_scope();
+            }
+            // every key gets a scope of its own, and the body one inside that
+            Stmt::ForIn { var, iter, block } => {
+                self.resolve_expr(iter);
+                self.begin_scope();
+                if let Stmt::Var(ident, _) = &var.node {
+                    self.define(ident);
+                }
+                self.resolve_loop_body(block);
+                self.end_scope();
+            }
+            Stmt::Labeled(label, stmt) => {
+                self.labels.push(label.0.clone());
+                self.resolve_stmt(stmt);
+                self.labels.pop();
+            }
+            Stmt::Assign(left, right) => {
+                self.resolve_expr(right);
+                self.resolve_expr(left);
+            }
+            Stmt::ClassStmt {
+                name,
+                superclass,
+                properties,
+            } => {
+                self.declare(name, stmt.span);
+                let enclosing = self.class;
+                self.class = ClassKind::Class;
+                if let Some(superclass) = superclass {
+                    if superclass.0 == name.0 {
+                        self.error(
+                            INVALID_SUPER,
+                            stmt.span,
+                            "A class can't inherit from itself.",
+                        );
+                    }
+                    self.resolve_name(stmt.span, superclass);
+                    self.class = ClassKind::Subclass;
+                }
+                self.define(name);
+                for property in properties {
+                    match &property.node {
+                        // field initializers run in the scope of the class when
+                        // an instance is made, like a method does
+                        Stmt::Var(_, expr) => {
+                            let enclosing = self.function;
+                            self.function = FunctionKind::Method;
+                            self.resolve_expr(expr);
+                            self.function = enclosing;
+                        }
+                        Stmt::Function(_, params, body) => {
+                            self.resolve_function(params, body, FunctionKind::Method, property.span)
+                        }
+                        _ => {}
+                    }
+                }
+                self.class = enclosing;
+            }
+            Stmt::ClassInit(_, args) => self.resolve_exprs(args),
+            Stmt::Assert { condition, .. } => self.resolve_expr(condition),
+            Stmt::Break(label) => self.resolve_jump("break", label, stmt.span),
+            Stmt::Continue(label) => self.resolve_jump("continue", label, stmt.span),
+            Stmt::Blank | Stmt::Import(_) => {}
+        }
+    }
+
+    fn resolve_loop_body(&mut self, block: &[StmtNode]) {
+        self.loops += 1;
+        self.resolve_block(block);
+        self.loops -= 1;
+    }
+
+    // resolve_jump checks that a break or continue has a loop to leave: any
+    // loop, or one with its label.
+    fn resolve_jump(&mut self, keyword: &str, label: &Option<Ident>, span: Span) {
+        let message = match label {
+            Some(label) if !self.labels.contains(&label.0) => format!(
+                "Can't {} to '{}': no enclosing loop has that label.",
+                keyword, label
+            ),
+            None if self.loops == 0 => format!("Can't use '{}' outside of a loop.", keyword),
+            _ => return,
+        };
+        self.error(INVALID_JUMP, span, &message);
+    }
+
+    // resolve_function resolves a function body in a scope holding its params,
+    // which is also where the body's own declarations go.
+    fn resolve_function(
+        &mut self,
+        params: &[Ident],
+        body: &[StmtNode],
+        kind: FunctionKind,
+        span: Span,
+    ) {
+        let enclosing = self.function;
+        self.function = kind;
+        // break and continue can't reach loops outside the function
+        let loops = std::mem::take(&mut self.loops);
+        let labels = std::mem::take(&mut self.labels);
+        self.begin_scope();
+        for param in params {
+            self.declare(param, span);
+            self.define(param);
+        }
+        self.resolve_stmts(body);
+        self.end_scope();
+        self.function = enclosing;
+        self.loops = loops;
+        self.labels = labels;
+    }
+
+    fn resolve_exprs(&mut self, exprs: &[ExprNode]) {
+        for expr in exprs {
+            self.resolve_expr(expr);
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &ExprNode) {
+        match &expr.node {
+            ExprType::Ident(ident) => self.resolve_name(expr.span, ident),
+            ExprType::This => {
+                if self.class == ClassKind::None {
+                    self.error(
+                        INVALID_THIS,
+                        expr.span,
+                        "Can't use 'this' outside of a class.",
+                    );
+                }
+            }
+            ExprType::Super(_) => match self.class {
+                ClassKind::None => self.error(
+                    INVALID_SUPER,
+                    expr.span,
+                    "Can't use 'super' outside of a class.",
+                ),
+                ClassKind::Class => self.error(
+                    INVALID_SUPER,
+                    expr.span,
+                    "Can't use 'super' in a class with no superclass.",
+                ),
+                ClassKind::Subclass => {}
+            },
+            ExprType::Literal(Literal::Array(elements)) => self.resolve_exprs(elements),
+            ExprType::Literal(Literal::Hash(pairs)) => {
+                for (key, value) in pairs {
+                    self.resolve_expr(key);
+                    self.resolve_expr(value);
+                }
+            }
+            ExprType::Literal(_) => {}
+            ExprType::GroupingExpr(expr)
+            | ExprType::UnaryExpr(_, expr)
+            | ExprType::PrefixExpr(_, expr) => self.resolve_expr(expr),
+            ExprType::InfixExpr(left, _, right) | ExprType::IndexExpr(left, right) => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            ExprType::PrintExpr(exprs) => self.resolve_exprs(exprs),
+            ExprType::If {
+                condition,
+                elseif,
+                then_branch,
+                else_branch,
+            } => {
+                self.resolve_expr(condition);
+                self.resolve_block(then_branch);
+                for (condition, block) in elseif {
+                    self.resolve_expr(condition);
+                    self.resolve_block(block);
+                }
+                self.resolve_block(else_branch);
+            }
+            ExprType::Function { params, body } => {
+                self.resolve_function(params, body, FunctionKind::Function, expr.span)
+            }
+            ExprType::Call { callee, args } => {
+                self.resolve_expr(callee);
+                self.resolve_exprs(args);
+            }
+            ExprType::ClassInit { name, args } => {
+                self.resolve_name(expr.span, name);
+                self.resolve_exprs(args);
+            }
+            ExprType::Get { object, .. } => self.resolve_expr(object),
+            ExprType::Set { object, value, .. } => {
+                self.resolve_expr(value);
+                self.resolve_expr(object);
+            }
+        }
+    }
+
+    // resolve_name records the depth of the innermost scope declaring name.
+    // A name no scope declares has to be a global.
+    fn resolve_name(&mut self, span: Span, name: &Ident) {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(defined) = scope.get(&name.0) {
+                if !defined {
+                    self.error(
+                        USE_BEFORE_DEFINE,
+                        span,
+                        "Can't read local variable in its own initializer.",
+                    );
+                }
+                self.locals.insert(span, depth);
+                return;
+            }
+        }
+        if self.globals.contains(&name.0) {
+            return;
+        }
+        if !self.declared.contains(&name.0) {
+            self.error(
+                UNDEFINED_VARIABLE,
+                span,
+                &format!("Undefined variable '{}'.", name.0),
+            );
+        } else if self.function == FunctionKind::None {
+            // a function body may use a global defined after it, as long as
+            // it is called after that; top level code runs straight away
+            self.error(
+                USE_BEFORE_DEFINE,
+                span,
+                &format!("Can't use '{}' before its definition.", name.0),
+            );
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Ident, span: Span) {
+        let scope = match self.scopes.last_mut() {
+            Some(scope) => scope,
+            None => {
+                self.globals.insert(name.0.clone());
+                return;
+            }
+        };
+        if scope.insert(name.0.clone(), false).is_some() {
+            self.error(
+                REDECLARED_VARIABLE,
+                span,
+                &format!("Already a variable named '{}' in this scope.", name.0),
+            );
+        }
+    }
+
+    fn define(&mut self, name: &Ident) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.0.clone(), true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexing;
+    use crate::parser::Parser;
+
+    // depths returns the depth of each resolved name, in source order.
+    fn depths(input: &str) -> Vec<(String, usize)> {
+        let mut parse = Parser::new(Lexing::new(input));
+        let program = parse.parse();
+        assert!(!parse.has_errors());
+        let mut locals: Vec<(Span, usize)> =
+            Resolver::new().resolve(&program).into_iter().collect();
+        locals.sort_by_key(|(span, _)| span.offset);
+        locals
+            .into_iter()
+            .map(|(span, depth)| (input[span.offset..span.end()].to_string(), depth))
+            .collect()
+    }
+
+    #[test]
+    fn test_depth() {
+        let input = "var g = 1;\nfun f(a) {\n  var b = a;\n  {\n    print a + b + g;\n  }\n}";
+        assert_eq!(
+            depths(input),
+            vec![
+                ("a".to_string(), 0),
+                ("a".to_string(), 1),
+                ("b".to_string(), 1),
+            ]
+        );
+    }
+
+    // errors returns the message and line of every error found in input.
+    fn errors(input: &str) -> Vec<(String, usize)> {
+        let mut parse = Parser::new(Lexing::new(input));
+        let program = parse.parse();
+        assert!(!parse.has_errors());
+        let mut resolver = Resolver::new();
+        resolver.resolve(&program);
+        resolver
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| (diagnostic.message.clone(), diagnostic.span().line))
+            .collect()
+    }
+
+    #[test]
+    fn test_errors() {
+        let input = r#"
+print a;
+var a = 1;
+print b;
+fun f() {
+  var c = 1;
+  var c = 2;
+  var d = d;
+  return g();
+}
+fun g() {
+  return this;
+}
+return 1;
+class A {
+  fun m() {
+    return super.m();
+  }
+}
+print len(a);
+"#;
+        assert_eq!(
+            errors(input),
+            vec![
+                ("Can't use 'a' before its definition.".to_string(), 2),
+                ("Undefined variable 'b'.".to_string(), 4),
+                ("Already a variable named 'c' in this scope.".to_string(), 7),
+                (
+                    "Can't read local variable in its own initializer.".to_string(),
+                    8
+                ),
+                ("Can't use 'this' outside of a class.".to_string(), 12),
+                ("Can't return from top-level code.".to_string(), 14),
+                (
+                    "Can't use 'super' in a class with no superclass.".to_string(),
+                    17
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_jump_errors() {
+        let input = r#"
+break;
+outer: while (true) {
+  for (var i = 0; i < 2; i += 1) {
+    continue outer;
+  }
+  fun f() {
+    break;
+  }
+  break inner;
+}
+"#;
+        assert_eq!(
+            errors(input),
+            vec![
+                ("Can't use 'break' outside of a loop.".to_string(), 2),
+                ("Can't use 'break' outside of a loop.".to_string(), 8),
+                (
+                    "Can't break to 'inner': no enclosing loop has that label.".to_string(),
+                    10
+                ),
+            ]
+        );
+    }
+}
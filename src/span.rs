@@ -0,0 +1,123 @@
+use std::fmt::Display;
+
+// FileId identifies the source file a span belongs to. The entry file is
+// always FileId(0); imported files get the following ids.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FileId(pub usize);
+
+// Span is a location in a source file: byte offset and length, plus the
+// 1-based line and column of its first character.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub file: FileId,
+    pub offset: usize,
+    pub len: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(file: FileId, offset: usize, len: usize, line: usize, column: usize) -> Self {
+        Span {
+            file,
+            offset,
+            len,
+            line,
+            column,
+        }
+    }
+
+    // to returns a span covering everything from the start of self to the end of end.
+    pub fn to(&self, end: Span) -> Span {
+        let stop = end.offset + end.len;
+        Span {
+            len: stop.saturating_sub(self.offset).max(self.len),
+            ..*self
+        }
+    }
+
+    pub fn end(&self) -> usize {
+        self.offset + self.len
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+// Spanned attaches a source span to a token or an AST node.
+//
+// Spans do not take part in equality, so two trees that only differ in where
+// they were parsed from compare equal.
+#[derive(Debug, Clone)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Spanned { node, span }
+    }
+}
+
+impl<T> From<T> for Spanned<T> {
+    fn from(node: T) -> Self {
+        Spanned::new(node, Span::default())
+    }
+}
+
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.node == other.node
+    }
+}
+
+impl<T: PartialEq> PartialEq<T> for Spanned<T> {
+    fn eq(&self, other: &T) -> bool {
+        self.node == *other
+    }
+}
+
+impl<T: Display> Display for Spanned<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.node)
+    }
+}
+
+pub struct SourceFile {
+    pub name: String,
+    pub source: String,
+}
+
+// SourceMap owns the text of every file that has been lexed, indexed by FileId.
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap { files: vec![] }
+    }
+
+    pub fn add(&mut self, name: &str, source: &str) -> FileId {
+        self.files.push(SourceFile {
+            name: name.to_string(),
+            source: source.to_string(),
+        });
+        FileId(self.files.len() - 1)
+    }
+
+    pub fn get(&self, file: FileId) -> Option<&SourceFile> {
+        self.files.get(file.0)
+    }
+
+    // line returns the text of the 1-based line in file, without its newline.
+    pub fn line(&self, file: FileId, line: usize) -> Option<&str> {
+        let file = self.get(file)?;
+        file.source.lines().nth(line.checked_sub(1)?)
+    }
+}
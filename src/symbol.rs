@@ -41,21 +41,28 @@ impl Symbol {
 }
 
 // SymbolTable is a struct that holds a HashMap of symbols and a reference to an outer SymbolTable.
-// It also has a num_definitions field that keeps track of the number of symbols defined in the table.
+// names holds the name each slot was defined with, by index. It keeps the
+// names of block scoped variables after their block takes them out of store.
 #[derive(Clone, Debug)]
 pub struct SymbolTable {
     pub outer: Option<Box<SymbolTable>>,
     pub store: HashMap<String, Symbol>,
-    pub num_definitions: usize,
+    pub names: Vec<String>,
     pub free_symbols: Vec<Symbol>,
 }
 
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SymbolTable {
     pub fn new() -> Self {
         SymbolTable {
             outer: None,
             store: HashMap::new(),
-            num_definitions: 0,
+            names: vec![],
             free_symbols: vec![],
         }
     }
@@ -64,7 +71,7 @@ impl SymbolTable {
         SymbolTable {
             outer: Some(Box::new(outer)),
             store: HashMap::new(),
-            num_definitions: 0,
+            names: vec![],
             free_symbols: vec![],
         }
     }
@@ -75,9 +82,9 @@ impl SymbolTable {
             scope = Scope::Global;
         }
 
-        let symbol = Symbol::new(name.clone(), scope, self.num_definitions);
+        let symbol = Symbol::new(name.clone(), scope, self.names.len());
+        self.names.push(name.clone());
         self.store.insert(name, symbol.clone());
-        self.num_definitions += 1;
         symbol
     }
 
@@ -104,6 +111,14 @@ impl SymbolTable {
         symbol
     }
 
+    // global looks name up in the outermost table, the one of the globals.
+    pub fn global(&self, name: &str) -> Option<Symbol> {
+        match &self.outer {
+            Some(outer) => outer.global(name),
+            None => self.store.get(name).cloned(),
+        }
+    }
+
     pub fn resolve(&mut self, name: &str) -> Option<Symbol> {
         match self.store.get(name) {
             Some(symbol) => Some(symbol.clone()),
@@ -119,14 +134,7 @@ impl SymbolTable {
                                 Some(free)
                             }
                         }
-                        None => {
-                            let free = self.define_free(Symbol::new(
-                                name.to_string(),
-                                Scope::Free,
-                                self.free_symbols.len(),
-                            ));
-                            Some(free)
-                        }
+                        None => None,
                     }
                 }
                 None => None,
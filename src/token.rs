@@ -30,6 +30,7 @@ pub enum Token {
     Equal,
     String(String),
     Number(String),
+    Integer(String), // a number without a fraction
     Semicolon, // ;
     Eof,       // null
 
@@ -77,6 +78,9 @@ pub enum Token {
     Comment(String), // //
 
     Assert, // assert
+
+    Break,    // break
+    Continue, // continue
 }
 
 impl Display for Token {
@@ -140,6 +144,7 @@ impl Display for Token {
             Token::Mod => write!(f, "MOD % null"),
             Token::PlusPlus => write!(f, "PLUS_PLUS ++ null"),
             Token::MinusMinus => write!(f, "MINUS_MINUS -- null"),
+            Token::Integer(n) => write!(f, "NUMBER {} {}.0", n, n),
             Token::Number(n) => {
                 let num = n.parse::<f64>().unwrap();
                 let inum = (num as i64) as f64;
@@ -150,6 +155,8 @@ impl Display for Token {
                 }
             },
             Token::Assert => write!(f, "ASSERT assert null"),
+            Token::Break => write!(f, "BREAK break null"),
+            Token::Continue => write!(f, "CONTINUE continue null"),
         }
     }
 }
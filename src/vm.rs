@@ -1,29 +1,42 @@
-use std::{process::exit, vec};
+use std::{cell::RefCell, cmp::Ordering, collections::HashMap, process::exit, rc::Rc, vec};
 
-use crate::{builtins::Builtins, objects::Object, opcode::Opcode};
+use crate::{
+    builtins::Builtins,
+    callstack::CallStack,
+    compiler::{FunctionInfo, FIELDS},
+    error::{RuntimeError, RuntimeErrorKind, TraceFrame},
+    evaluator::MAX_CALL_DEPTH,
+    lines::LineTable,
+    objects::{arithmetic, compare, is_equal, is_truthy, position, Arithmetic, HashObject, Object},
+    opcode::Opcode,
+    output,
+    span::{FileId, Span},
+};
 
 pub struct VM<'a> {
     constants: Vec<Object>,
     stack: Vec<Object>,
     globals: Vec<Object>,
-    closures: Vec<(usize, usize)>,
     builtins: Builtins,
     sp: usize, // stack pointer
     main_start: usize,
     instructions: Vec<&'a Opcode>,
-    registers: Vec<(usize, usize, usize)>, // ip, free_start, free_len
-    free_start: usize,
+    frames: CallStack,
     stack_top: usize,
+    // where each instruction came from and the names of the functions, for
+    // runtime errors
+    lines: LineTable,
+    functions: HashMap<usize, String>,
 }
 
 const NIL: Object = Object::Nil;
 // const TRUE: Object = Object::Boolean(true);
 // const FALSE: Object = Object::Boolean(false);
 
-const GLOBALS_SIZE: usize = 65536;
+pub const GLOBALS_SIZE: usize = 65536;
 
 impl<'a> VM<'a> {
-    pub fn new(ins: (usize, Vec<&'a Opcode>)) -> VM {
+    pub fn new(ins: (usize, Vec<&'a Opcode>)) -> VM<'a> {
         VM {
             constants: Vec::new(),
             stack: vec![NIL; 1024],
@@ -32,27 +45,37 @@ impl<'a> VM<'a> {
             globals: vec![NIL; GLOBALS_SIZE],
             builtins: Builtins::new(),
             sp: 0,
-            registers: Vec::with_capacity(1024),
-            free_start: 0,
-            closures: vec![(0, 0); GLOBALS_SIZE],
+            frames: CallStack::new(),
             stack_top: 1024,
+            lines: LineTable::new(),
+            functions: HashMap::new(),
         }
     }
 
-    pub fn run(&mut self) -> Object {
-        let mut ip = self.main_start;
+    pub fn run(&mut self) -> Result<Object, RuntimeError> {
         let l = self.instructions.len();
-        println!("ip, {:?}, l: {:?}", ip, l);
+        // the script runs in the bottom frame, as a function without locals
+        let script = Object::CompiledFunction {
+            start: self.main_start,
+            len: l - self.main_start,
+            num_locals: 0,
+            num_parameters: 0,
+        };
+        self.frames.push_frame(script, self.main_start, 0);
+        // the ip of the running frame is kept here while it runs, and saved
+        // into the frame when a call leaves it
+        let mut ip = self.main_start;
         while ip < l {
             let instruction: &Opcode = self.instructions[ip];
-            // println!("ip: {:?}, {:?}  {:?}, free_start: {:?}", ip, instruction, self.registers, self.free_start);
-            ip = self.execute(instruction, ip, ip >= self.main_start);
+            ip = self
+                .execute(instruction, ip)
+                .map_err(|err| self.locate(err, ip))?;
         }
 
-        if self.sp <= 0 {
-            return NIL;
+        if self.sp == 0 {
+            return Ok(NIL);
         }
-        self.pop().clone()
+        Ok(self.pop().clone())
     }
 
     fn push(&mut self, obj: Object) {
@@ -84,223 +107,686 @@ impl<'a> VM<'a> {
     }
 
     #[inline]
-    fn execute(&mut self, instruction: &Opcode, ip: usize, is_main: bool) -> usize {
+    fn execute(&mut self, instruction: &Opcode, ip: usize) -> Result<usize, RuntimeError> {
         match instruction {
-            Opcode::Add
-            | Opcode::Divide
+            Opcode::Add => {
+                let right = self.pop().clone();
+                let left = self.pop().clone();
+                let result = match (left, right) {
+                    (l, r) if l.is_number() && r.is_number() => {
+                        arithmetic(Arithmetic::Add, &l, &r).unwrap()
+                    }
+                    (Object::String(l), Object::String(r)) => Object::String(l + &r),
+                    _ => {
+                        return Err(self.type_error("Operands must be two numbers or two strings."))
+                    }
+                };
+                self.push(result);
+                Ok(ip + 1)
+            }
+            Opcode::Divide
             | Opcode::Minus
             | Opcode::Multiply
             | Opcode::Mod
-            | Opcode::LessThan
-            | Opcode::EqualEqual
-            | Opcode::GreaterThan => {
-                let right = &self.stack[self.sp - 1];
-                self.sp -= 1;
-                let left = &self.stack[self.sp - 1];
-                self.stack[self.sp-1] = match (left, right) {
-                    (Object::Number(l), Object::Number(r)) => match instruction {
-                        Opcode::Add => Object::Number(*l + *r),
-                        Opcode::Divide => Object::Number(*l / *r),
-                        Opcode::Minus => Object::Number(*l - *r),
-                        Opcode::Multiply => Object::Number(*l * *r),
-                        Opcode::Mod => Object::Number(*l % *r),
-                        Opcode::GreaterThan => Object::Boolean(*l > *r),
-                        Opcode::LessThan => Object::Boolean(*l < *r),
-                        Opcode::EqualEqual => Object::Boolean(*l == *r),
-                        _ => Object::Nil,
-                    },
-                    _ => Object::Nil,
+            | Opcode::BitAnd
+            | Opcode::BitOr => {
+                let op = match instruction {
+                    Opcode::Divide => Arithmetic::Divide,
+                    Opcode::Minus => Arithmetic::Subtract,
+                    Opcode::Multiply => Arithmetic::Multiply,
+                    Opcode::Mod => Arithmetic::Mod,
+                    Opcode::BitAnd => Arithmetic::BitAnd,
+                    _ => Arithmetic::BitOr,
                 };
-                // self.push(result.clone());
-                ip + 1
+                let result =
+                    match arithmetic(op, &self.stack[self.sp - 2], &self.stack[self.sp - 1]) {
+                        Some(result) => result,
+                        None if matches!(op, Arithmetic::BitAnd | Arithmetic::BitOr) => {
+                            return Err(self.type_error("Operands must be integers."))
+                        }
+                        None => return Err(self.type_error("Operands must be numbers.")),
+                    };
+                self.sp -= 1;
+                self.stack[self.sp - 1] = result;
+                Ok(ip + 1)
             }
-            Opcode::Assert(pos) => {
-                let obj = self.pop();
-                if *obj == Object::Boolean(false) {
-                    panic!("assert failed");
-                } else {
-                    if !is_main {
-                        *pos
-                    } else {
-                        self.main_start + *pos
+            Opcode::LessThan
+            | Opcode::LessThanEqual
+            | Opcode::GreaterThan
+            | Opcode::GreaterThanEqual => {
+                let ordering = match compare(&self.stack[self.sp - 2], &self.stack[self.sp - 1]) {
+                    Some(ordering) => ordering,
+                    None => return Err(self.type_error("Operands must be numbers.")),
+                };
+                self.sp -= 1;
+                self.stack[self.sp - 1] = Object::Boolean(match instruction {
+                    Opcode::LessThan => ordering == Some(Ordering::Less),
+                    Opcode::LessThanEqual => {
+                        matches!(ordering, Some(Ordering::Less | Ordering::Equal))
                     }
+                    Opcode::GreaterThan => ordering == Some(Ordering::Greater),
+                    _ => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                });
+                Ok(ip + 1)
+            }
+            Opcode::EqualEqual | Opcode::NotEqual => {
+                let equal = is_equal(&self.stack[self.sp - 2], &self.stack[self.sp - 1]);
+                self.sp -= 1;
+                self.stack[self.sp - 1] = Object::Boolean(match instruction {
+                    Opcode::EqualEqual => equal,
+                    _ => !equal,
+                });
+                Ok(ip + 1)
+            }
+            Opcode::Bang => {
+                self.stack[self.sp - 1] = Object::Boolean(!is_truthy(self.last()));
+                Ok(ip + 1)
+            }
+            Opcode::Assert(index) => {
+                if *self.pop() == Object::Boolean(false) {
+                    let message = match &self.constants[*index] {
+                        Object::String(message) => message.clone(),
+                        _ => "Assertion failed.".to_string(),
+                    };
+                    return Err(self.error(RuntimeErrorKind::AssertionFailed, &message));
                 }
+                Ok(ip + 1)
             }
             Opcode::Exit(code) => {
                 exit(*code as i32);
             }
             Opcode::JumpIfFalse(pos) => {
-                let condition = self.pop();
-                if *condition == Object::Boolean(false) {
-                    if !is_main {
-                        *pos
-                    } else {
-                        self.main_start + *pos
-                    }
+                if is_truthy(self.pop()) {
+                    Ok(ip + 1)
                 } else {
-                    ip + 1
+                    Ok(*pos)
                 }
             }
-            Opcode::Jump(pos) => {
-                if !is_main {
-                    *pos
+            // and/or keep the deciding operand on the stack as their value
+            Opcode::JumpNotTruthy(pos) => {
+                if is_truthy(self.last()) {
+                    Ok(ip + 1)
                 } else {
-                    self.main_start + *pos
+                    Ok(*pos)
                 }
             }
+            Opcode::Jump(pos) => Ok(*pos),
             Opcode::LoadConstant(index) => {
                 self.push(self.constants[*index].clone());
-                ip + 1
+                Ok(ip + 1)
             }
             Opcode::Pop => {
                 self.pop();
-                ip + 1
+                Ok(ip + 1)
             }
             Opcode::Abs => {
                 let obj = self.last();
-                self.stack[self.sp - 1] = Object::Number(match obj {
-                    Object::Number(n) => n.abs(),
-                    _ => 0.0,
-                });
+                self.stack[self.sp - 1] = match obj {
+                    Object::Number(n) => Object::Number(n.abs()),
+                    Object::Int(i) => match i.checked_abs() {
+                        Some(i) => Object::Int(i),
+                        None => Object::Number((*i as f64).abs()),
+                    },
+                    _ => Object::Number(0.0),
+                };
                 // self.push(Object::Number(match obj {
                 //     Object::Number(n) => n.abs(),
                 //     _ => 0.0,
                 // }));
-                ip + 1
+                Ok(ip + 1)
             }
             Opcode::Nagetive => {
-                let obj = self.last();
-                self.stack[self.sp - 1] = Object::Number(match obj {
-                    Object::Number(n) => -n,
-                    _ => 0.0,
-                });
-                // self.push();
-                ip + 1
+                self.stack[self.sp - 1] = match self.last().negate() {
+                    Some(object) => object,
+                    None => return Err(self.type_error("Operand must be a number.")),
+                };
+                Ok(ip + 1)
             }
             Opcode::Print(n) => {
-                for _ in 0..*n {
-                    let obj = self.pop();
-                    print!("{} ", obj);
+                // the values were pushed left to right, print them that way
+                for obj in &self.stack[self.sp - n..self.sp] {
+                    output::print(format_args!("{}", obj));
                 }
-                ip + 1
-            }
-            Opcode::DefineGlobal(s) => {
-                let obj = self.pop();
-                println!("{} = {:?}", s, obj);
-                ip + 1
+                self.sp -= n;
+                Ok(ip + 1)
             }
             Opcode::GetGlobal(index) => {
                 self.push(self.globals[*index].clone());
-                ip + 1
+                Ok(ip + 1)
             }
             Opcode::SetGlobal(index) => {
-                let obj = self.pop();
-                match obj {
-                    Object::CompiledFunction {
-                        start,
-                        len: _,
-                        num_locals: _,
-                        num_parameters,
-                    } => {
-                        self.closures[*index] = (*start, *num_parameters);
+                let obj = self.pop().clone();
+                self.globals[*index] = obj;
+                Ok(ip + 1)
+            }
+            Opcode::GetBuiltin(index) => {
+                match self.builtins.get_by_index(*index) {
+                    Some(obj) => self.push(obj),
+                    None => {
+                        return Err(self.error(
+                            RuntimeErrorKind::UndefinedVariable,
+                            &format!("Undefined builtin {}.", index),
+                        ))
                     }
-                    _ => {
-                        self.globals[*index] = obj.clone();
+                }
+                Ok(ip + 1)
+            }
+            Opcode::Call(n) => self.call_value(*n, ip),
+            Opcode::Class(index) => {
+                let class = Object::CompiledClass {
+                    name: self.constants[*index].to_string(),
+                    superclass: None,
+                    methods: Rc::new(RefCell::new(HashMap::new())),
+                };
+                self.push(class);
+                Ok(ip + 1)
+            }
+            Opcode::Inherit => {
+                let superclass = self.pop().clone();
+                if !matches!(superclass, Object::CompiledClass { .. }) {
+                    return Err(self.type_error("Superclass must be a class."));
+                }
+                if let Object::CompiledClass {
+                    superclass: slot, ..
+                } = &mut self.stack[self.sp - 1]
+                {
+                    *slot = Some(Rc::new(superclass));
+                }
+                Ok(ip + 1)
+            }
+            Opcode::Method(index) => {
+                let method = self.pop().clone();
+                let name = self.constants[*index].to_string();
+                if let Object::CompiledClass { methods, .. } = self.last() {
+                    methods.borrow_mut().insert(name, method);
+                }
+                Ok(ip + 1)
+            }
+            Opcode::GetProperty(index) => {
+                let object = self.pop().clone();
+                let name = self.constants[*index].to_string();
+                let value = match &object {
+                    Object::ClassInstance { fields, class, .. } => {
+                        let field = fields.borrow().get(&name).cloned();
+                        match field {
+                            Some(value) => value,
+                            None => match find_method(class, &name) {
+                                Some((method, owner)) => Object::BoundMethod {
+                                    name,
+                                    receiver: Box::new(object.clone()),
+                                    class: owner,
+                                    method: Box::new(method),
+                                },
+                                None => NIL,
+                            },
+                        }
+                    }
+                    _ => return Err(self.type_error("Only instances have properties.")),
+                };
+                self.push(value);
+                Ok(ip + 1)
+            }
+            Opcode::SetProperty(index) => {
+                let value = self.pop().clone();
+                let name = self.constants[*index].to_string();
+                match self.pop() {
+                    Object::ClassInstance { fields, .. } => {
+                        fields.borrow_mut().insert(name, value);
                     }
+                    _ => return Err(self.type_error("Only instances have fields.")),
                 }
-                ip + 1
+                Ok(ip + 1)
             }
-            Opcode::GetBuiltin(index) => {
-                let obj = self.builtins.get_by_index(*index);
-                if obj.is_none() {
-                    unimplemented!("builtin not found: {:?}", index);
+            Opcode::Invoke(index, n) => {
+                let name = self.constants[*index].to_string();
+                let receiver = self.stack[self.sp - 1 - n].clone();
+                let (fields, class) = match &receiver {
+                    Object::ClassInstance { fields, class, .. } => (fields, class),
+                    _ => return Err(self.type_error("Only instances have methods.")),
+                };
+                // a field holding a function is called like any other value
+                let field = fields.borrow().get(&name).cloned();
+                if let Some(value) = field {
+                    self.stack[self.sp - 1 - n] = value;
+                    return self.call_value(*n, ip);
+                }
+                match find_method(class, &name) {
+                    Some((method, _)) => self.call_function(method, *n, ip),
+                    None => Err(self.undefined_property(&name)),
+                }
+            }
+            Opcode::GetSuper(index) => {
+                let superclass = Rc::new(self.pop().clone());
+                let receiver = self.pop().clone();
+                let name = self.constants[*index].to_string();
+                match find_method(&superclass, &name) {
+                    Some((method, owner)) => self.push(Object::BoundMethod {
+                        name,
+                        receiver: Box::new(receiver),
+                        class: owner,
+                        method: Box::new(method),
+                    }),
+                    None => return Err(self.undefined_property(&name)),
                 }
-                self.push(obj.unwrap().clone());
-                ip + 1
-            }
-            Opcode::Call(n) => {
-                let func = self.pop().clone();
-                // println!("----------> call: {:?}", func);
-                match func {
-                    Object::Builtin(_, _, f) => {
-                        let args = self.stack[self.sp-n..self.sp].to_vec();
-                        self.sp -= n;
-                        let _ = f(args);
-                        ip + 1
-                        //self.push(result);
+                Ok(ip + 1)
+            }
+            Opcode::Array(n) => {
+                let elements = self.stack[self.sp - n..self.sp].to_vec();
+                self.sp -= n;
+                self.push(Object::Array(Rc::new(RefCell::new(elements))));
+                Ok(ip + 1)
+            }
+            Opcode::Hash(n) => {
+                // n key and value pairs, the first pair lowest on the stack
+                let mut hash = HashObject::new();
+                for pair in self.stack[self.sp - 2 * n..self.sp].chunks(2) {
+                    hash.insert(self.hash_key(&pair[0])?, pair[1].clone());
+                }
+                self.sp -= 2 * n;
+                self.push(Object::Hash(Rc::new(RefCell::new(hash))));
+                Ok(ip + 1)
+            }
+            Opcode::Index => {
+                let index = self.pop().clone();
+                let left = self.pop().clone();
+                let value = self.index(&left, &index)?;
+                self.push(value);
+                Ok(ip + 1)
+            }
+            Opcode::SetIndex => {
+                let value = self.pop().clone();
+                let index = self.pop().clone();
+                match self.pop().clone() {
+                    Object::Hash(hash) => {
+                        let index = self.hash_key(&index)?;
+                        hash.borrow_mut().insert(index, value);
+                    }
+                    Object::Array(elements) => {
+                        let i = match position(&index) {
+                            Some(i) => i,
+                            None => return Err(self.invalid_index("Array index must be a number.")),
+                        };
+                        let mut elements = elements.borrow_mut();
+                        let len = elements.len();
+                        match elements.get_mut(i) {
+                            Some(element) => *element = value,
+                            None => {
+                                return Err(self.invalid_index(&format!(
+                                    "Index {} out of bounds for array of length {}.",
+                                    index, len
+                                )))
+                            }
+                        }
+                    }
+                    _ => {
+                        return Err(
+                            self.type_error("Only arrays and hashes support index assignment.")
+                        )
                     }
-                    _ => unimplemented!("unimplemented function: {:?}", func),
                 }
+                Ok(ip + 1)
+            }
+            Opcode::True => {
+                self.push(Object::Boolean(true));
+                Ok(ip + 1)
             }
-            Opcode::Closure(index, free_count) => {
-                let (start, _) = self.closures[*index];
-                self.registers.push((ip, self.free_start, *free_count));
-                self.free_start = self.sp - free_count;
-                //println!("free_start: {:?}", self.free_start);
-                return start;
+            Opcode::False => {
+                self.push(Object::Boolean(false));
+                Ok(ip + 1)
             }
-            Opcode::GetFree(index) => {
-                let obj = self.stack[self.free_start + *index].clone();
+            Opcode::Iterator => {
+                // a hash iterates its keys, an array its elements
+                let items = match self.pop() {
+                    Object::Hash(hash) => hash.borrow().keys().cloned().collect(),
+                    Object::Array(items) => items.borrow().clone(),
+                    _ => return Err(self.type_error("Can only iterate over arrays and hashes.")),
+                };
+                self.push(Object::Iterator(Rc::new(items), 0));
+                Ok(ip + 1)
+            }
+            Opcode::IterNext(pos) => {
+                // leaves the advanced iterator and the next item, or jumps to
+                // pos when the items run out
+                let (items, index) = match self.pop() {
+                    Object::Iterator(items, index) => (Rc::clone(items), *index),
+                    _ => return Err(self.type_error("Can only iterate over arrays and hashes.")),
+                };
+                if index >= items.len() {
+                    return Ok(*pos);
+                }
+                self.push(Object::Iterator(Rc::clone(&items), index + 1));
+                self.push(items[index].clone());
+                Ok(ip + 1)
+            }
+            Opcode::CurrentClosure => {
+                let obj = self.frames.current_frame().unwrap().closure().clone();
                 self.push(obj);
-                ip + 1
+                Ok(ip + 1)
             }
             Opcode::ReturnValue => {
-                //let result = self.pop();
-                // self.pop_frame();
-                let last = self.last();
-                self.stack[self.free_start] = last.clone();
-                //self.stack.drain(self.free_start..self.sp - 1);
-                //println!("-------------------> return: {:?}", self.stack.len());
-                self.sp = self.free_start + 1;
-                //self.push(result);
-                // self.frees.pop();
-                //println!("return ip: {:?}", ip)    ;
-                let (ip, arg_start, _) = self.registers.pop().unwrap();
-                //println!("return: ip: {:?}, stack: {:?}", ip + 1, self.stack);
-                self.free_start = arg_start;
-                ip + 1
-            }
-            Opcode::Return => {
-                //let result = self.pop();
-                // self.pop_frame();
-                // let last = self.last();
-                // self.stack[self.free_start] = last.clone();
-                //self.stack.drain(self.free_start..self.sp - 1);
-                //println!("-------------------> return: {:?}", self.stack.len());
-                self.sp = self.free_start;
-                //self.push(result);
-                // self.frees.pop();
-                //println!("return ip: {:?}", ip)    ;
-                let (ip, arg_start, _) = self.registers.pop().unwrap();
-                //println!("return: ip: {:?}, stack: {:?}", ip + 1, self.stack);
-                self.free_start = arg_start;
-                ip + 1
-            }
-            Opcode::SetLocal(_index) => {
-                // let obj = self.pop();
-                // println!("set local: {:?}", index);
-                ip + 1
-            }
-            Opcode::GetLocal(_index) => {
-                ip + 1
-                //println!("get local: {:?}", index);
-                // let frame = self.current_frame();
-                // let obj = frame.get_local(index);
-                // self.push(obj);
-            }
-            _ => unimplemented!("unimplemented opcode: {:?}", instruction),
+                let result = self.pop().clone();
+                self.return_from_frame(result)
+            }
+            Opcode::Return => self.return_from_frame(NIL),
+            Opcode::SetLocal(index) => {
+                let obj = self.pop().clone();
+                let base_pointer = self.base_pointer();
+                self.stack[base_pointer + *index] = obj;
+                Ok(ip + 1)
+            }
+            Opcode::GetLocal(index) => {
+                let obj = self.stack[self.base_pointer() + *index].clone();
+                self.push(obj);
+                Ok(ip + 1)
+            }
+            _ => Err(self.error(
+                RuntimeErrorKind::Unsupported,
+                &format!("Unsupported instruction {:?}.", instruction),
+            )),
+        }
+    }
+
+    // call_value calls the value below the n arguments on top of the stack
+    // and returns the ip to continue at.
+    fn call_value(&mut self, n: usize, ip: usize) -> Result<usize, RuntimeError> {
+        let func = self.stack[self.sp - 1 - n].clone();
+        match func {
+            Object::Builtin(_, _, f) => {
+                let args = self.stack[self.sp - n..self.sp].to_vec();
+                self.sp -= n + 1;
+                self.push(f(args));
+                Ok(ip + 1)
+            }
+            Object::CompiledFunction { .. } => self.call_function(func, n, ip),
+            Object::BoundMethod {
+                receiver, method, ..
+            } => {
+                self.stack[self.sp - 1 - n] = *receiver;
+                self.call_function(*method, n, ip)
+            }
+            Object::CompiledClass { .. } => self.instantiate(func, n, ip),
+            _ => Err(self.not_callable()),
+        }
+    }
+
+    // call_function pushes a frame for func. Local 0 of the frame is the
+    // callee slot, the n arguments on top of the stack are the next locals.
+    // Like the evaluator, it stops MAX_CALL_DEPTH calls below the script.
+    fn call_function(&mut self, func: Object, n: usize, ip: usize) -> Result<usize, RuntimeError> {
+        let (start, num_locals, num_parameters) = match func {
+            Object::CompiledFunction {
+                start,
+                num_locals,
+                num_parameters,
+                ..
+            } => (start, num_locals, num_parameters),
+            _ => return Err(self.not_callable()),
+        };
+        if n != num_parameters {
+            return Err(self.arity_mismatch(num_parameters, n));
+        }
+        if self.frames.depth() > MAX_CALL_DEPTH {
+            return Err(self.error(RuntimeErrorKind::StackOverflow, "Stack overflow."));
+        }
+        if let Some(frame) = self.frames.current_frame_mut() {
+            frame.set_ip(ip + 1);
+        }
+        self.frames.push_frame(func, start, self.sp - n - 1);
+        for _ in n + 1..num_locals {
+            self.push(NIL);
+        }
+        Ok(start)
+    }
+
+    // instantiate creates an instance of class, sets its fields, superclass
+    // fields first, and then calls init with the n arguments on the stack.
+    fn instantiate(&mut self, class: Object, n: usize, ip: usize) -> Result<usize, RuntimeError> {
+        let name = match &class {
+            Object::CompiledClass { name, .. } => name.clone(),
+            _ => unreachable!(),
+        };
+        let class = Rc::new(class);
+        let instance = Object::ClassInstance {
+            name,
+            class: Rc::clone(&class),
+            fields: Rc::new(RefCell::new(HashMap::new())),
+            properties: Rc::new(RefCell::new(HashMap::new())),
+        };
+
+        let mut chain = vec![];
+        let mut next = Some(Rc::clone(&class));
+        while let Some(class) = next {
+            next = match class.as_ref() {
+                Object::CompiledClass { superclass, .. } => superclass.clone(),
+                _ => None,
+            };
+            chain.push(class);
+        }
+        for class in chain.iter().rev() {
+            let fields = match class.as_ref() {
+                Object::CompiledClass { methods, .. } => methods.borrow().get(FIELDS).cloned(),
+                _ => None,
+            };
+            if let Some(fields) = fields {
+                let depth = self.frames.depth();
+                self.push(instance.clone());
+                let start = self.call_function(fields, 0, ip)?;
+                self.run_frame(start, depth)?;
+                self.pop();
+            }
+        }
+
+        self.stack[self.sp - 1 - n] = instance;
+        match find_method(&class, "init") {
+            // init may be called with fewer arguments than it has params, the rest are nil
+            Some((init, _)) => {
+                let n = match init {
+                    Object::CompiledFunction { num_parameters, .. } if n < num_parameters => {
+                        for _ in n..num_parameters {
+                            self.push(NIL);
+                        }
+                        num_parameters
+                    }
+                    _ => n,
+                };
+                self.call_function(init, n, ip)
+            }
+            None => {
+                if n != 0 {
+                    return Err(self.arity_mismatch(0, n));
+                }
+                Ok(ip + 1)
+            }
+        }
+    }
+
+    // run_frame runs the frame just pushed, starting at ip, until it returns
+    // to the frame at depth.
+    fn run_frame(&mut self, mut ip: usize, depth: usize) -> Result<(), RuntimeError> {
+        while self.frames.depth() > depth {
+            let instruction: &Opcode = self.instructions[ip];
+            ip = self
+                .execute(instruction, ip)
+                .map_err(|err| self.locate(err, ip))?;
+        }
+        Ok(())
+    }
+
+    // return_from_frame drops the frame of the running function together with
+    // its callee slot, leaves result in their place and resumes the caller.
+    fn return_from_frame(&mut self, result: Object) -> Result<usize, RuntimeError> {
+        let base_pointer = self.frames.pop_frame().unwrap();
+        self.sp = base_pointer;
+        self.push(result);
+        Ok(self.frames.current_frame().unwrap().ip())
+    }
+
+    // index reads left[index], the way the evaluator does.
+    fn index(&self, left: &Object, index: &Object) -> Result<Object, RuntimeError> {
+        match left {
+            Object::Array(elements) => {
+                let i = match position(index) {
+                    Some(i) => i,
+                    None => return Err(self.invalid_index("Array index must be a number.")),
+                };
+                let elements = elements.borrow();
+                match elements.get(i) {
+                    Some(object) => Ok(object.clone()),
+                    None => Err(self.invalid_index(&format!(
+                        "Index {} out of bounds for array of length {}.",
+                        index,
+                        elements.len()
+                    ))),
+                }
+            }
+            Object::Hash(hash) => {
+                let index = self.hash_key(index)?;
+                Ok(hash.borrow().get(&index).cloned().unwrap_or(NIL))
+            }
+            Object::String(s) => match (position(index), index) {
+                // past the end of a string is nil, scanners rely on it
+                (Some(i), _) if i < usize::MAX => Ok(s
+                    .chars()
+                    .nth(i)
+                    .map(|c| Object::String(c.to_string()))
+                    .unwrap_or(NIL)),
+                (Some(_), _) => Err(self.invalid_index(&format!(
+                    "Index {} out of bounds for string of length {}.",
+                    index,
+                    s.chars().count()
+                ))),
+                (None, Object::String(_)) => {
+                    Err(self.invalid_index("String index must be a number."))
+                }
+                (None, _) => Ok(NIL),
+            },
+            _ => Err(self.type_error("Only arrays, hashes and strings can be indexed.")),
+        }
+    }
+
+    fn hash_key(&self, key: &Object) -> Result<Object, RuntimeError> {
+        key.clone()
+            .key()
+            .map_err(|message| self.type_error(&message))
+    }
+
+    fn invalid_index(&self, message: &str) -> RuntimeError {
+        self.error(RuntimeErrorKind::InvalidIndex, message)
+    }
+
+    // error creates an error without a location, the run loop locates it.
+    fn error(&self, kind: RuntimeErrorKind, message: &str) -> RuntimeError {
+        RuntimeError::new(kind, message, Span::default())
+    }
+
+    // locate points err at the instruction at ip and adds the Lox calls that
+    // led there. An error from a nested run is already located.
+    fn locate(&self, err: RuntimeError, ip: usize) -> RuntimeError {
+        if err.span != Span::default() {
+            return err;
+        }
+        let span = self.span_at(ip);
+        let frames = self.frames.frames();
+        // the bottom frame is the script, a caller resumes after its call
+        let trace = frames
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(i, frame)| TraceFrame {
+                function: self.function_name(frame.closure()),
+                call: self.span_at(frames[i - 1].ip() - 1),
+            })
+            .collect();
+        RuntimeError { span, ..err }.with_trace(trace)
+    }
+
+    fn span_at(&self, ip: usize) -> Span {
+        match self.lines.position(ip) {
+            Some((line, column)) => Span::new(FileId(0), 0, 0, line, column),
+            None => Span::default(),
         }
     }
 
+    fn function_name(&self, function: &Object) -> String {
+        match function {
+            Object::CompiledFunction { start, .. } => self.functions.get(start).cloned(),
+            _ => None,
+        }
+        .unwrap_or_else(|| "fn".to_string())
+    }
+
+    fn type_error(&self, message: &str) -> RuntimeError {
+        self.error(RuntimeErrorKind::TypeError, message)
+    }
+
+    fn not_callable(&self) -> RuntimeError {
+        self.error(
+            RuntimeErrorKind::NotCallable,
+            "Can only call functions and classes.",
+        )
+    }
+
+    fn arity_mismatch(&self, expected: usize, got: usize) -> RuntimeError {
+        self.error(
+            RuntimeErrorKind::ArityMismatch,
+            &format!("Expected {} arguments but got {}.", expected, got),
+        )
+    }
+
+    fn undefined_property(&self, name: &str) -> RuntimeError {
+        self.error(
+            RuntimeErrorKind::UndefinedProperty,
+            &format!("Undefined property '{}'.", name),
+        )
+    }
+
+    fn base_pointer(&self) -> usize {
+        self.frames.current_frame().unwrap().base_pointer()
+    }
+
     pub fn define_constants(&mut self, constants: Vec<Object>) {
         self.constants = constants;
     }
+
+    // define_lines and define_functions let runtime errors say where they
+    // happened.
+    pub fn define_lines(&mut self, lines: LineTable) {
+        self.lines = lines;
+    }
+
+    pub fn define_functions(&mut self, functions: &[FunctionInfo]) {
+        for function in functions {
+            self.functions.insert(function.start, function.name.clone());
+        }
+    }
+}
+
+// find_method looks name up in class and then its superclasses. It returns
+// the method and the class that defines it.
+fn find_method(class: &Rc<Object>, name: &str) -> Option<(Object, Rc<Object>)> {
+    let mut next = Some(Rc::clone(class));
+    while let Some(class) = next {
+        match class.as_ref() {
+            Object::CompiledClass {
+                methods,
+                superclass,
+                ..
+            } => {
+                if let Some(method) = methods.borrow().get(name) {
+                    return Some((method.clone(), Rc::clone(&class)));
+                }
+                next = superclass.clone();
+            }
+            _ => return None,
+        }
+    }
+    None
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{compiler::Compiler, lexer::Lexing, parser::Parser};
+    use crate::{compiler::Compiler, lexer::Lexing, parser::Parser, resolver::Resolver};
 
     #[test]
     fn test_vm() {
@@ -332,14 +818,213 @@ mod tests {
         assert_eq!(result, Object::Nil);
     }
 
+    #[test]
+    fn test_function_locals() {
+        let result = test_vm_code("fun f(a) { var b = a * 2; b = b + 1; return b; } f(3);");
+        assert_eq!(result, Object::Number(7.0));
+        let result = test_vm_code(
+            "fun g(x) { var y = x + 1; return y; } fun f(a) { var b = g(a); return a + b; } f(1);",
+        );
+        assert_eq!(result, Object::Number(3.0));
+        let result = test_vm_code("fun f() { var a = 1; } f();");
+        assert_eq!(result, Object::Nil);
+    }
+
+    #[test]
+    fn test_recursion() {
+        let result = test_vm_code(
+            "fun fib(n) { if (n < 2) { return n; } return fib(n - 1) + fib(n - 2); } fib(10);",
+        );
+        assert_eq!(result, Object::Number(55.0));
+        let result = test_vm_code(
+            "fun outer() { fun count(n) { if (n == 0) { return 0; } return 1 + count(n - 1); } return count(5); } outer();",
+        );
+        assert_eq!(result, Object::Number(5.0));
+    }
+
+    #[test]
+    fn test_class() {
+        let result = test_vm_code(
+            "class A { var x = 1; fun init(y) { this.y = y; } fun sum() { return this.x + this.y; } }
+            class B < A { var z = 10; fun sum() { return super.sum() + this.z; } }
+            var b = new B(2); var sum = b.sum; b.x = 5; sum();",
+        );
+        assert_eq!(result, Object::Number(17.0));
+    }
+
+    #[test]
+    fn test_loops() {
+        let result = test_vm_code(
+            "fun f(n) {
+                var total = 0;
+                var i = 0;
+                while (i < n) { i += 1; if (i == 2) { continue; } total += i; }
+                outer: for (var a = 0; a < 3; a += 1) {
+                    for (var b = 0; b < 3; b += 1) {
+                        if (b == 2) { continue outer; }
+                        if (a == 2) { break outer; }
+                        total = total + 100;
+                    }
+                }
+                return total;
+            }
+            f(5);",
+        );
+        assert_eq!(result, Object::Number(413.0));
+    }
+
+    #[test]
+    fn test_index() {
+        let result = test_vm_code("var a = [1, 2, 3]; a[1] + a[2];");
+        assert_eq!(result, Object::Number(5.0));
+        let result = test_vm_code("var h = {'a': 1}; h['b'] = 2; h['a'] + h['b'];");
+        assert_eq!(result, Object::Number(3.0));
+        let result = test_vm_code("'abc'[1];");
+        assert_eq!(result, Object::String("b".to_string()));
+    }
+
+    #[test]
+    fn test_shared_arrays() {
+        let result = test_vm_code("var a = [1, 2]; var b = a; b[0] = 3; a[0];");
+        assert_eq!(result, Object::Int(3));
+        let result = test_vm_code("var a = [1]; push(a, 2, 3); a[2];");
+        assert_eq!(result, Object::Int(3));
+        let result = test_vm_code("var a = [1, 2]; pop(a) + len(a);");
+        assert_eq!(result, Object::Int(3));
+        let result = test_vm_code("var a = [[1], [2]]; a[1][0] = 5; a[1][0];");
+        assert_eq!(result, Object::Int(5));
+    }
+
+    #[test]
+    fn test_index_error() {
+        let err = run_vm_code("var a = [1]; a[3];").unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::InvalidIndex);
+        assert_eq!(err.message, "Index 3 out of bounds for array of length 1.");
+        let err = run_vm_code("var a = [1]; a['x'];").unwrap_err();
+        assert_eq!(err.message, "Array index must be a number.");
+        let err = run_vm_code("var a = [1]; a[1] = 2;").unwrap_err();
+        assert_eq!(err.message, "Index 1 out of bounds for array of length 1.");
+        let err = run_vm_code("var a = [1, 2]; a[-1];").unwrap_err();
+        assert_eq!(err.message, "Index -1 out of bounds for array of length 2.");
+        let err = run_vm_code("var a = [1, 2]; a[0.5];").unwrap_err();
+        assert_eq!(err.message, "Index 0.5 out of bounds for array of length 2.");
+        let err = run_vm_code("var s = \"abc\"; s[-1];").unwrap_err();
+        assert_eq!(err.message, "Index -1 out of bounds for string of length 3.");
+        let err = run_vm_code("var s = \"abc\"; s[1.5];").unwrap_err();
+        assert_eq!(err.message, "Index 1.5 out of bounds for string of length 3.");
+        assert_eq!(test_vm_code("var s = \"abc\"; s[3];"), NIL);
+        let err = run_vm_code("var a = 1; a[0] = 2;").unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::TypeError);
+        let err = run_vm_code("var a = 1; a[0];").unwrap_err();
+        assert_eq!(
+            err.message,
+            "Only arrays, hashes and strings can be indexed."
+        );
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(
+            test_vm_code("\"ab\" + \"c\";"),
+            Object::String("abc".to_string())
+        );
+        assert_eq!(test_vm_code("1 <= 1;"), Object::Boolean(true));
+        assert_eq!(test_vm_code("2 >= 3;"), Object::Boolean(false));
+        assert_eq!(test_vm_code("1 != 2;"), Object::Boolean(true));
+        assert_eq!(test_vm_code("\"a\" == \"a\";"), Object::Boolean(true));
+        assert_eq!(test_vm_code("nil == false;"), Object::Boolean(false));
+        assert_eq!(test_vm_code("!nil;"), Object::Boolean(true));
+        assert_eq!(test_vm_code("!0;"), Object::Boolean(false));
+        assert_eq!(test_vm_code("-(1 + 2);"), Object::Number(-3.0));
+        assert_eq!(
+            test_vm_code("nil or \"x\";"),
+            Object::String("x".to_string())
+        );
+        assert_eq!(test_vm_code("1 and 2;"), Object::Number(2.0));
+        assert_eq!(test_vm_code("false and 2;"), Object::Boolean(false));
+        // the right operand is skipped once the left one decides
+        assert_eq!(test_vm_code("true or -\"x\";"), Object::Boolean(true));
+    }
+
+    #[test]
+    fn test_integers() {
+        assert!(matches!(test_vm_code("1 + 2;"), Object::Int(3)));
+        assert!(matches!(test_vm_code("7 % -3;"), Object::Int(1)));
+        assert!(matches!(test_vm_code("6 & 3;"), Object::Int(2)));
+        assert!(matches!(test_vm_code("6 | 3;"), Object::Int(7)));
+        assert!(matches!(test_vm_code("-(1 + 2);"), Object::Int(-3)));
+        assert!(matches!(test_vm_code("1 + 0.5;"), Object::Number(n) if n == 1.5));
+        assert!(matches!(test_vm_code("6 / 3;"), Object::Number(n) if n == 2.0));
+        assert!(matches!(
+            test_vm_code("9223372036854775807 * 2;"),
+            Object::Number(_)
+        ));
+        assert_eq!(test_vm_code("2 == 2.0;"), Object::Boolean(true));
+        assert_eq!(test_vm_code("1 < 1.5;"), Object::Boolean(true));
+        assert_eq!(
+            test_vm_code("var h = {1: \"one\"}; h[1.0];"),
+            Object::String("one".to_string())
+        );
+        let err = run_vm_code("1 | 1.5;").unwrap_err();
+        assert_eq!(err.message, "Operands must be integers.");
+    }
+
+    #[test]
+    fn test_operator_errors() {
+        let err = run_vm_code("1 + \"a\";").unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::TypeError);
+        assert_eq!(err.message, "Operands must be two numbers or two strings.");
+        let err = run_vm_code("1 < nil;").unwrap_err();
+        assert_eq!(err.message, "Operands must be numbers.");
+        let err = run_vm_code("-\"a\";").unwrap_err();
+        assert_eq!(err.message, "Operand must be a number.");
+    }
+
+    #[test]
+    fn test_error_location() {
+        let err = run_vm_code("var a = 1;\n\nprint a + nil;").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Operands must be two numbers or two strings.\n[line 3]"
+        );
+
+        let err = run_vm_code(
+            "fun inner(x) {\n  return -x;\n}\nfun outer() {\n  return inner(\"a\");\n}\nouter();",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Operand must be a number.\n[line 2] in inner()\n[line 5] in outer()\n[line 7] in script"
+        );
+
+        let err = run_vm_code("assert 1 > 2, 'oops';").unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::AssertionFailed);
+        assert_eq!(err.message, "Assertion failed: (> 1.0 2.0) oops");
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let err = run_vm_code("fun f(n) { return f(n + 1); } f(0);").unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::StackOverflow);
+        assert_eq!(err.message, "Stack overflow.");
+        assert_eq!(err.trace.len(), MAX_CALL_DEPTH);
+    }
+
     fn test_vm_code(code: &str) -> Object {
+        run_vm_code(code).unwrap()
+    }
+
+    fn run_vm_code(code: &str) -> Result<Object, RuntimeError> {
         let lexer = Lexing::new(code);
         let mut parser = Parser::new(lexer);
         let program = parser.parse();
-        let mut compiler = Compiler::new(program);
+        let locals = Resolver::new().resolve(&program);
+        let mut compiler = Compiler::new(program, locals);
         compiler.compile();
         let (l, codes) = compiler.get_instructions();
-        let mut vm = VM::new((l, codes.iter().map(|x| x).collect()));
+        let mut vm = VM::new((l, codes.iter().collect()));
+        vm.define_lines(compiler.get_lines());
+        vm.define_functions(&compiler.functions);
         vm.define_constants(compiler.constants);
         vm.run()
     }
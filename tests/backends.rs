@@ -0,0 +1,192 @@
+// backends runs every test script through both the tree-walking evaluator
+// (`run`) and the compiler and vm (`compile`), and fails when they disagree on
+// stdout, exit code or the value of the last expression, or when either one
+// crashes.
+
+mod common;
+
+use std::env;
+use std::fs;
+use std::panic;
+
+use codecrafters_interpreter::compiler::Compiler;
+use codecrafters_interpreter::evaluator::Evaluator;
+use codecrafters_interpreter::lexer::Lexing;
+use codecrafters_interpreter::objects::Object;
+use codecrafters_interpreter::parser::Parser;
+use codecrafters_interpreter::resolver::Resolver;
+use codecrafters_interpreter::vm::VM;
+
+use common::{interpret, interpret_args, lox_files, name, Expectations};
+
+// Scripts the backends are known to disagree on, and why. A script that
+// starts to agree fails the test until it is taken off the list. A crash is
+// never a known difference.
+const KNOWN_DIFFERENCES: &[(&str, &str)] = &[
+    ("add.lox", "the compiler does not support import"),
+    ("import.lox", "the compiler does not support import"),
+    (
+        "closure.lox",
+        "the compiler reports captured locals and function expressions",
+    ),
+    ("test2.lox", "the compiler reports function expressions"),
+];
+
+#[test]
+fn test_backends_agree() {
+    let mut failures = vec![];
+    for path in lox_files() {
+        let name = name(&path);
+        let run = interpret("run", &path);
+        let compile = interpret("compile", &path);
+        let crashes: Vec<String> = [("run", &run), ("compile", &compile)]
+            .iter()
+            .filter(|(_, output)| output.crashed())
+            .map(|(command, output)| {
+                let message = output.stderr.lines().find(|line| !line.is_empty());
+                format!("{} crashed: {}", command, message.unwrap_or(""))
+            })
+            .collect();
+        if !crashes.is_empty() {
+            failures.push(format!("{}: {}", name, crashes.join("\n")));
+            continue;
+        }
+        let mut diffs = vec![];
+        if run.stdout != compile.stdout {
+            diffs.push(format!(
+                "stdout differs\n  run:     {:?}\n  compile: {:?}",
+                run.stdout, compile.stdout
+            ));
+        }
+        if run.code != compile.code {
+            diffs.push(format!(
+                "exit code differs: run {:?}, compile {:?}\n  compile stderr: {}",
+                run.code,
+                compile.code,
+                compile.stderr.lines().next().unwrap_or("")
+            ));
+        }
+        if diffs.is_empty() && run.code == Some(0) {
+            let source = fs::read_to_string(&path).unwrap();
+            let (evaluated, compiled) = (evaluate(&source), execute(&source));
+            if evaluated != compiled {
+                diffs.push(format!(
+                    "final value differs: run {}, compile {}",
+                    evaluated, compiled
+                ));
+            }
+        }
+
+        let known = KNOWN_DIFFERENCES.iter().find(|(known, _)| *known == name);
+        match (known, diffs.is_empty()) {
+            (None, false) => failures.push(format!("{}: {}", name, diffs.join("\n"))),
+            (Some(_), true) => failures.push(format!(
+                "{}: the backends agree now, remove it from KNOWN_DIFFERENCES",
+                name
+            )),
+            _ => {}
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+}
+
+#[test]
+fn test_expect_annotations() {
+    let mut failures = vec![];
+    for path in lox_files() {
+        let source = fs::read_to_string(&path).unwrap();
+        let expected = Expectations::parse(&source).output;
+        if expected.is_empty() {
+            continue;
+        }
+        for command in ["run", "compile"] {
+            let output = interpret(command, &path);
+            let actual: Vec<&str> = output.stdout.lines().collect();
+            if actual != expected {
+                failures.push(format!(
+                    "{} ({}):\n  expected: {:?}\n  actual:   {:?}",
+                    name(&path),
+                    command,
+                    expected,
+                    actual
+                ));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+}
+
+// test_exec runs every script the compiler can handle from a .loxc file and
+// expects the same as compiling and running it directly. Known differences
+// are left out.
+#[test]
+fn test_exec() {
+    let mut failures = vec![];
+    for path in lox_files() {
+        let name = name(&path);
+        if KNOWN_DIFFERENCES.iter().any(|(known, _)| *known == name) {
+            continue;
+        }
+        let out = env::temp_dir().join(format!("backends-{}c", name.replace('/', "-")));
+        let compiled = interpret_args(&[
+            "compile".as_ref(),
+            "-o".as_ref(),
+            out.as_os_str(),
+            path.as_os_str(),
+        ]);
+        if compiled.crashed() {
+            failures.push(format!("{}: compile -o crashed", name));
+            continue;
+        }
+        if compiled.code != Some(0) {
+            continue;
+        }
+        let exec = interpret("exec", &out);
+        let compile = interpret("compile", &path);
+        fs::remove_file(&out).unwrap();
+        if exec.crashed() || (&exec.stdout, exec.code) != (&compile.stdout, compile.code) {
+            failures.push(format!(
+                "{}:
+  exec:    {:?} {:?}
+  compile: {:?} {:?}",
+                name, exec.code, exec.stdout, compile.code, compile.stdout
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+}
+
+// evaluate returns the value of the last expression of source under the
+// evaluator, printed so it compares with the vm's.
+fn evaluate(source: &str) -> String {
+    let program = Parser::new(Lexing::new(source)).parse();
+    let mut evaluator = Evaluator::new(vec![], false);
+    evaluator.define_locals(Resolver::new().resolve(&program));
+    match evaluator.run(&program) {
+        Ok(value) => value.unwrap_or(Object::Nil).to_string(),
+        Err(err) => err.to_string(),
+    }
+}
+
+// execute is evaluate for the compiler and vm.
+fn execute(source: &str) -> String {
+    let program = Parser::new(Lexing::new(source)).parse();
+    let locals = Resolver::new().resolve(&program);
+    let result = panic::catch_unwind(|| {
+        let mut compiler = Compiler::new(program, locals);
+        compiler.compile();
+        if let Some(diagnostic) = compiler.diagnostics().first() {
+            return diagnostic.message.clone();
+        }
+        let (main_start, instructions) = compiler.get_instructions();
+        let mut vm = VM::new((main_start, instructions.iter().collect()));
+        vm.define_lines(compiler.get_lines());
+        vm.define_functions(&compiler.functions);
+        vm.define_constants(compiler.constants);
+        match vm.run() {
+            Ok(value) => value.to_string(),
+            Err(err) => err.to_string(),
+        }
+    });
+    result.unwrap_or_else(|_| "<compiler panicked>".to_string())
+}
@@ -0,0 +1,44 @@
+// commands checks how the interpreter binary reports errors: the codecrafters
+// commands print the lines the course tester reads, the others quote the
+// source.
+
+mod common;
+
+use std::env;
+use std::fs;
+
+use common::{interpret, Output};
+
+// interpret_source runs command on source written to a file of its own.
+fn interpret_source(command: &str, name: &str, source: &str) -> Output {
+    let path = env::temp_dir().join(format!("commands-{}.lox", name));
+    fs::write(&path, source).unwrap();
+    let output = interpret(command, &path);
+    fs::remove_file(&path).unwrap();
+    output
+}
+
+#[test]
+fn test_codecrafters_errors() {
+    let output = interpret_source("tokenize", "tokenize", "var a = @;\n");
+    assert_eq!(output.code, Some(65));
+    assert_eq!(output.stderr, "[line 1] Error: Unexpected character: @\n");
+
+    let output = interpret_source("parse", "parse", "print 1 +;\n");
+    assert_eq!(output.code, Some(65));
+    assert_eq!(output.stderr, "[line 1] Error: Expect expression.\n");
+
+    let output = interpret_source("evaluate", "evaluate", "print 1;\nprint -\"a\";\n");
+    assert_eq!(output.code, Some(70));
+    assert_eq!(output.stderr, "Operand must be a number.\n[line 2]\n");
+}
+
+#[test]
+fn test_rendered_errors() {
+    let output = interpret_source("run", "run", "print 1;\nprint -\"a\";\n");
+    assert_eq!(output.code, Some(70));
+    assert!(output
+        .stderr
+        .starts_with("error[E0300]: Operand must be a number.\n"));
+    assert!(output.stderr.contains("2 | print -\"a\";\n"));
+}
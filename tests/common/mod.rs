@@ -0,0 +1,106 @@
+// Helpers shared by the integration tests. Each test crate uses only some
+// of them.
+#![allow(dead_code)]
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Output is what one run of the interpreter binary left behind.
+pub struct Output {
+    pub stdout: String,
+    pub stderr: String,
+    pub code: Option<i32>,
+}
+
+impl Output {
+    // crashed reports a panic, or a signal such as a stack overflow, instead
+    // of an exit through an error the interpreter reports.
+    pub fn crashed(&self) -> bool {
+        matches!(self.code, Some(101) | None)
+    }
+}
+
+// Benchmarks take too long in a debug build to run with the tests.
+const BENCHMARKS: &[&str] = &["opcode/fabonacci.lox"];
+
+// lox_files lists the .lox scripts under tests/ and tests/opcode/, sorted by
+// name, leaving out the benchmarks.
+pub fn lox_files() -> Vec<PathBuf> {
+    let mut files = vec![];
+    for dir in [tests_dir(), tests_dir().join("opcode")] {
+        files.extend(
+            fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+                .filter(|path| !BENCHMARKS.contains(&name(path).as_str())),
+        );
+    }
+    files.sort();
+    files
+}
+
+fn tests_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+// name is the path of a script under tests/, used to report and look up
+// results.
+pub fn name(path: &Path) -> String {
+    let path = path.strip_prefix(tests_dir()).unwrap_or(path);
+    path.to_string_lossy().to_string()
+}
+
+// interpret runs the interpreter binary with command on path.
+pub fn interpret(command: &str, path: &Path) -> Output {
+    interpret_args(&[command.as_ref(), path.as_os_str()])
+}
+
+pub fn interpret_args(args: &[&OsStr]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_codecrafters-interpreter"))
+        .args(args)
+        .output()
+        .unwrap();
+    Output {
+        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        code: output.status.code(),
+    }
+}
+
+// Expectations are the annotations of a script:
+//
+//   print 1 + 2; // expect: 3
+//   1 + nil;     // expect runtime error: Operands must be two numbers or two strings.
+//   print a;     // expect error: Undefined variable 'a'.
+//
+// Each `expect:` is one line of stdout. `expect error:` is a diagnostic
+// reported before the script runs.
+#[derive(Debug, Default)]
+pub struct Expectations {
+    pub output: Vec<String>,
+    pub runtime_error: Option<String>,
+    pub error: Option<String>,
+}
+
+impl Expectations {
+    pub fn parse(source: &str) -> Self {
+        let mut expectations = Expectations::default();
+        for line in source.lines() {
+            if let Some((_, expect)) = line.split_once("// expect: ") {
+                expectations.output.push(expect.to_string());
+            } else if let Some((_, error)) = line.split_once("// expect runtime error: ") {
+                expectations.runtime_error = Some(error.trim().to_string());
+            } else if let Some((_, error)) = line.split_once("// expect error: ") {
+                expectations.error = Some(error.trim().to_string());
+            }
+        }
+        expectations
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.output.is_empty() && self.runtime_error.is_none() && self.error.is_none()
+    }
+}
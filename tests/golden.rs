@@ -0,0 +1,135 @@
+// golden runs every tests/*.lox script through the evaluator the way `run`
+// does, and checks what it prints and the error it stops with against the
+// `// expect` annotations of the script. Scripts without annotations only
+// have to run without errors.
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::thread;
+
+use codecrafters_interpreter::diagnostic::Diagnostic;
+use codecrafters_interpreter::evaluator::{Evaluator, STACK_SIZE};
+use codecrafters_interpreter::imports::Imports;
+use codecrafters_interpreter::lexer::Lexing;
+use codecrafters_interpreter::output;
+use codecrafters_interpreter::parser::Parser;
+use codecrafters_interpreter::resolver::Resolver;
+use codecrafters_interpreter::span::SourceMap;
+
+use common::{lox_files, name, Expectations};
+
+// Outcome is what running a script produced.
+struct Outcome {
+    output: String,
+    runtime_error: Option<String>,
+    error: Option<String>,
+}
+
+#[test]
+fn test_golden_files() {
+    let mut failures = vec![];
+    for path in lox_files() {
+        let source = fs::read_to_string(&path).unwrap();
+        let expected = Expectations::parse(&source);
+        // the evaluator needs the stack the interpreter binary gives it
+        let outcome = thread::scope(|scope| {
+            thread::Builder::new()
+                .stack_size(STACK_SIZE)
+                .spawn_scoped(scope, || interpret(&path, &source))
+                .unwrap()
+                .join()
+                .unwrap()
+        });
+        let mut diffs = vec![];
+
+        let actual: Vec<&str> = outcome.output.lines().collect();
+        if !expected.is_empty() && actual != expected.output {
+            diffs.push(format!("stdout:\n{}", diff(&expected.output, &actual)));
+        }
+        if outcome.runtime_error != expected.runtime_error {
+            diffs.push(format!(
+                "runtime error:\n- {}\n+ {}",
+                expected.runtime_error.as_deref().unwrap_or("<none>"),
+                outcome.runtime_error.as_deref().unwrap_or("<none>")
+            ));
+        }
+        if outcome.error != expected.error {
+            diffs.push(format!(
+                "error:\n- {}\n+ {}",
+                expected.error.as_deref().unwrap_or("<none>"),
+                outcome.error.as_deref().unwrap_or("<none>")
+            ));
+        }
+        if !diffs.is_empty() {
+            failures.push(format!("{}\n{}", name(&path), diffs.join("\n")));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+}
+
+// interpret parses, imports, resolves and evaluates source, stopping at the
+// first stage that reports an error.
+fn interpret(path: &Path, source: &str) -> Outcome {
+    let mut outcome = Outcome {
+        output: String::new(),
+        runtime_error: None,
+        error: None,
+    };
+    let first_error =
+        |diagnostics: &[Diagnostic]| diagnostics.first().map(|diagnostic| diagnostic.message.clone());
+
+    let mut sources = SourceMap::new();
+    let file = sources.add(&path.to_string_lossy(), source);
+    let mut parser = Parser::new(Lexing::with_file(source, file));
+    let program = parser.parse();
+    if parser.has_errors() {
+        outcome.error = first_error(parser.diagnostics());
+        return outcome;
+    }
+    let mut imports = Imports::new(program, path.parent().unwrap().to_path_buf());
+    let program = match imports.load(&mut sources) {
+        Ok(program) => program,
+        Err(errors) => {
+            outcome.error = first_error(&errors);
+            return outcome;
+        }
+    };
+    let mut resolver = Resolver::new();
+    let locals = resolver.resolve(&program);
+    if resolver.has_errors() {
+        outcome.error = first_error(resolver.diagnostics());
+        return outcome;
+    }
+
+    let mut evaluator = Evaluator::new(program, false);
+    evaluator.define_locals(locals);
+    let (output, result) = output::capture(|| evaluator.evaluate());
+    outcome.output = output;
+    // annotations can't end in spaces, so the message is compared without them
+    outcome.runtime_error = result.err().map(|err| err.message.trim_end().to_string());
+    outcome
+}
+
+// diff lists expected and actual side by side, line by line: matching lines
+// are indented, expected ones start with - and actual ones with +.
+fn diff(expected: &[String], actual: &[&str]) -> String {
+    let mut lines = vec![];
+    for i in 0..expected.len().max(actual.len()) {
+        match (expected.get(i), actual.get(i)) {
+            (Some(expected), Some(actual)) if expected == actual => {
+                lines.push(format!("  {}", expected))
+            }
+            (expected, actual) => {
+                if let Some(expected) = expected {
+                    lines.push(format!("- {}", expected));
+                }
+                if let Some(actual) = actual {
+                    lines.push(format!("+ {}", actual));
+                }
+            }
+        }
+    }
+    lines.join("\n")
+}